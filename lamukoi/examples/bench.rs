@@ -0,0 +1,100 @@
+//! Runs the same prelude programs on each evaluator and reports the time taken.
+//! With `--profile`, also prints the steps the tree and graph reducers take for each program under
+//! each strategy, giving up after ten million steps or a hundred thousand pending work stack
+//! entries.
+//!
+//! ```sh
+//! cargo run --example bench --release [-- --profile]
+//! ```
+
+use lamukoi::error::*;
+use lamukoi::interpreter::gmachine::GMachine;
+use lamukoi::interpreter::graph_reducer::Graph;
+use lamukoi::interpreter::grin::Grin;
+use lamukoi::interpreter::profile::Profile;
+use lamukoi::interpreter::stg::Stg;
+use lamukoi::interpreter::tim::Tim;
+use lamukoi::interpreter::tree_reducer::TreeReducer;
+use lamukoi::interpreter::{Backend, Budget, Outcome, Strategy};
+use lamukoi::library::{prelude, prelude_primops};
+use lamukoi::parser::parse_module;
+use lamukoi::structures::*;
+use std::time::Instant;
+
+const PROGRAMS: &[(&str, &str)] = &[
+    (
+        "fib",
+        "fib n = if (LT n 2) n (ADD (fib (SUB n 1)) (fib (SUB n 2))); main = fib 20;",
+    ),
+    ("sum", "main = sum (map (λx. MUL x x) (range 1 300));"),
+    (
+        "primes",
+        "sieve (Cons p xs) = Cons p (sieve (filter (λx. not (EQ (MOD x p) 0)) xs));
+        sieve Nil = Nil;
+        main = sum (sieve (range 2 60));",
+    ),
+];
+
+fn compile(src: &str) -> Result<ScProgram> {
+    let src = format!("import Prelude; {}", src);
+    Ok(parse_module(&src)?
+        .link(vec![prelude()])?
+        .into_anon()?
+        .lambda_lift()
+        .lambda_elim()?
+        .compress())
+}
+
+// run `main` on backend `B`, and report the time taken from loading the program to its WHNF
+fn bench<'a, B: Backend<'a>>(name: &str, sc: &ScProgram) -> Result<B> {
+    let start = Instant::now();
+    let mut backend = B::load(sc.clone(), prelude_primops)?;
+    let value = backend.run_to_whnf("main")?;
+    let result = backend.whnf_to_string(&value);
+    println!("  {:<14} {:>10.2?}  {}", name, start.elapsed(), result);
+    Ok(backend)
+}
+
+// the steps `main` takes on `backend` under each strategy
+fn profile_strategies<'a, B: Backend<'a>>(
+    backend: &mut B,
+    set_strategy: fn(&mut B, Strategy),
+) -> Result<()> {
+    for strategy in [Strategy::ByName, Strategy::ByNeed, Strategy::ByValue] {
+        set_strategy(backend, strategy);
+        let mut value = backend.entry("main")?;
+        let mut profile = Profile::new();
+        let mut budget = Budget::fuel(10_000_000).with_memory_limit(100_000);
+        match backend.reduce_to_whnf_observed(&mut value, &mut budget, &mut profile) {
+            Ok(Outcome::Done) => {
+                println!("  {}", strategy);
+                print!("{}", profile.report(backend.program()));
+            }
+            Ok(outcome) => println!("  {}: {:?}", strategy, outcome),
+            Err(error) => println!("  {}: {}", strategy, error),
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let profile = std::env::args().any(|arg| arg == "--profile");
+    for (name, src) in PROGRAMS {
+        println!("{}", name);
+        let sc = compile(src)?;
+
+        let mut reducer = bench::<TreeReducer>("tree reducer", &sc)?;
+        if profile {
+            profile_strategies(&mut reducer, TreeReducer::set_strategy)?;
+        }
+        let mut graph = bench::<Graph>("graph reducer", &sc)?;
+        if profile {
+            profile_strategies(&mut graph, Graph::set_strategy)?;
+        }
+        bench::<GMachine>("G-machine", &sc)?;
+        bench::<Tim>("TIM", &sc)?;
+        bench::<Stg>("STG", &sc)?;
+        bench::<Grin>("GRIN", &sc)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+//! Runs a Binary Lambda Calculus program on stdin.
+//!
+//! ```sh
+//! cargo run --example blc -- program.blc < input      # BLC8: bytes in, bytes out
+//! cargo run --example blc -- -b program.txt < input   # BLC: `0`/`1` characters in and out
+//! ```
+//!
+//! As with other BLC8 interpreters, bytes after the end of the program in
+//! `program.blc` are read before stdin.
+
+use lamukoi::blc::{self, Mode};
+use lamukoi::error::*;
+use std::io::{BufWriter, Read, Write};
+
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (mode, path) = match &args[..] {
+        [flag, path] if flag == "-b" => (Mode::Blc, path),
+        [path] => (Mode::Blc8, path),
+        _ => {
+            eprintln!("usage: blc [-b] <program>");
+            std::process::exit(2);
+        }
+    };
+    let source = std::fs::read(path).map_err(|error| Error::Io {
+        path: path.into(),
+        error,
+    })?;
+    let mut output = BufWriter::new(std::io::stdout());
+    match mode {
+        Mode::Blc => {
+            let term = blc::parse_bits(&String::from_utf8_lossy(&source))?;
+            blc::run(term, mode, std::io::stdin(), &mut output)?;
+        }
+        Mode::Blc8 => {
+            let (term, len) = blc::parse_bytes(&source)?;
+            let input = (&source[len..]).chain(std::io::stdin());
+            blc::run(term, mode, input, &mut output)?;
+        }
+    }
+    output.flush().unwrap();
+    Ok(())
+}
@@ -5,54 +5,58 @@
 //! ```sh
 //! cargo run --example prelude_v0 --release | pv > /dev/null
 //! ```
+//!
+//! Pass `tim` to run it on the Three Instruction Machine instead of the tree reducer.
 
 #![recursion_limit = "256"]
 use lamukoi::structures::*;
 use lamukoi::error::*;
 use lamukoi::*;
-use lamukoi::interpreter::tree_reducer::Node;
+use lamukoi::interpreter::tim::Tim;
+use lamukoi::interpreter::tree_reducer::TreeReducer;
+use lamukoi::interpreter::Backend;
 use std::collections::VecDeque;
 use std::io::{Read, Write};
 
 mod prelude_v0;
 use prelude_v0::*;
 
-fn test_echo<I: Read + 'static, O: Write + 'static>(input: &mut I, output: &mut O) -> Result<()> {
+fn test_echo<I: Read + 'static, O: Write + 'static>(input: &mut I, output: &mut O, tim: bool) -> Result<()> {
     let mut prog = program![
         echo = cShow READ;
     ];
-    prog.defs.extend(prelude().defs);
+    prog.extend(prelude());
 
     let processed = prog
         .into_anon()?
         .lambda_lift()
         .lambda_elim()?
         .compress();
-    let table = processed.def_indexes();
-    let echo = table["echo"];
-    let mut primops = prelude_defs(
-        input,
-        output,
-        &table
-    );
-    let mut processed = processed.attach_prim(&mut primops)?;
-    let mut node = Node::from_sc(echo);
-    processed.reduce_to_nf(&mut node)?;
+    if tim {
+        let mut machine = Tim::load(processed, |table| prelude_defs(input, output, table))?;
+        machine.run_to_nf("echo")?;
+        return Ok(());
+    }
+    let mut reducer = TreeReducer::load(processed, |table| prelude_defs(input, output, table))?;
+    // `echo` reads a fresh byte each time, so it must not keep its first value
+    reducer.exclude_effectful_cafs(&["READ"])?;
+    reducer.run_to_nf("echo")?;
     Ok(())
 }
 
 fn main() -> Result<()> {
+    let tim = std::env::args().any(|arg| arg == "tim");
     // test finite string input "hello"
     let mut input = VecDeque::from(b"hello".to_vec());
     let mut output: Vec<u8> = vec![];
-    test_echo(&mut input, &mut output)?;
+    test_echo(&mut input, &mut output, tim)?;
     let mut stdout = std::io::stdout();
-    stdout.write(&output).unwrap();
+    stdout.write_all(&output).unwrap();
     println!();
 
     // test infinite string input of repeated zeros
     let mut input = std::io::repeat(b'0');
     let mut output = std::io::stdout();
-    test_echo(&mut input, &mut output)?;
+    test_echo(&mut input, &mut output, tim)?;
     Ok(())
 }
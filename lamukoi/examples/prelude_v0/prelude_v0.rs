@@ -1,18 +1,16 @@
 use lamukoi::structures::*;
 use lamukoi::*;
 use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::io::{BufReader, Read, Write};
 
 pub(crate) fn prelude() -> Program {
     program![
         #EQ x y;
         #ADD x y;
         #SUB x y;
-        True t f = t;
-        False t f = f;
+        data Bool = True | False;
         // Scott list
-        SNil nil cons = nil;
-        SCons x xs nil cons = cons x xs;
+        data SList = SNil | SCons x xs;
         // Church list (right fold)
         cNil = |c n| n;
         cCons = |h t c n| c h (t c n);
@@ -27,14 +25,17 @@ pub(crate) fn prelude() -> Program {
         cList2sList clist = clist SCons SNil;
         // Scott list of bits is CList2SList READ
         #SHOW x; // puts current bit to the stream, returning sShow
-        sShow stream = stream id (|item xs| SHOW (item 1 0) xs);
+        sShow stream = case stream of {
+            SNil -> id;
+            SCons item xs -> (SHOW (case item of { True -> 1; False -> 0 }) xs);
+        };
         cShow stream = sShow (cList2sList stream);
         id x = x;
     ]
 }
 
 struct InputDevice<I: Read> {
-    bytes: std::io::Bytes<I>,
+    bytes: std::io::Bytes<BufReader<I>>,
     cur_byte: u8,
     cur_bit: u8,
 }
@@ -42,7 +43,7 @@ struct InputDevice<I: Read> {
 impl<I: Read> InputDevice<I> {
     fn new(device: I) -> Self {
         Self {
-            bytes: device.bytes(),
+            bytes: BufReader::new(device).bytes(),
             cur_byte: 0,
             cur_bit: 0,
         }
@@ -83,7 +84,7 @@ impl<O: Write> OutputDevice<O> {
         self.cur_bit += 1;
         if self.cur_bit == 8 {
             self.cur_bit = 0;
-            self.device.write(&[self.cur_byte]).unwrap();
+            self.device.write_all(&[self.cur_byte]).unwrap();
             self.cur_byte = 0;
         }
     }
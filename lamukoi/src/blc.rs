@@ -0,0 +1,323 @@
+// Binary Lambda Calculus frontend and runner
+// a BLC term is a bitstring: `00 M` is λ. M, `01 M N` is M N, and `1^(i+1) 0` is the variable
+// bound by the i-th enclosing lambda (counting from 0), which maps directly to AnonExpr::DeBruijn
+// the program is applied to its input and must return its output, both as lists in the standard
+// BLC encoding: `cons h t = λz. z h t`, `nil = λx y. y`, bit 0 = `λx y. x`, bit 1 = `λx y. y`
+// in BLC mode the list items are bits, read and written as the characters `0` and `1` (other
+// input characters are skipped); in BLC8 mode they are bytes, each a list of 8 bits, most
+// significant first
+// input is read lazily and cached by position, so reading a list cell twice gives the same item
+
+use crate::error::*;
+use crate::interpreter::tree_reducer::TreeReducer;
+use crate::interpreter::Backend;
+use crate::structures::*;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{BufReader, Bytes, Read, Write};
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Blc,
+    Blc8,
+}
+
+struct BitParser<I: Iterator<Item = bool>> {
+    bits: I,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = bool>> BitParser<I> {
+    fn bit(&mut self) -> Result<bool> {
+        let bit = self.bits.next().ok_or_else(|| Error::Syntax {
+            message: "unexpected end of BLC program".to_string(),
+            span: Span::new(self.pos, self.pos),
+        })?;
+        self.pos += 1;
+        Ok(bit)
+    }
+
+    fn term(&mut self, depth: usize) -> Result<AnonExpr> {
+        let start = self.pos;
+        if self.bit()? {
+            let mut index = 0;
+            while self.bit()? {
+                index += 1;
+            }
+            if index >= depth {
+                return Err(Error::Syntax {
+                    message: format!(
+                        "variable {} is not bound by any of {} lambdas",
+                        index, depth
+                    ),
+                    span: Span::new(start, self.pos),
+                });
+            }
+            return Ok(AnonExpr::DeBruijn(index));
+        }
+        if self.bit()? {
+            let e1 = self.term(depth)?;
+            let e2 = self.term(depth)?;
+            Ok(AnonExpr::App(Box::new(e1), Box::new(e2)))
+        } else {
+            Ok(AnonExpr::Lam(Box::new(self.term(depth + 1)?)))
+        }
+    }
+}
+
+// a program written as the characters `0` and `1`; whitespace is ignored
+// error spans count bits, not characters
+pub fn parse_bits(src: &str) -> Result<AnonExpr> {
+    let mut bits = vec![];
+    for (i, c) in src.char_indices() {
+        match c {
+            '0' => bits.push(false),
+            '1' => bits.push(true),
+            c if c.is_whitespace() => {}
+            c => {
+                return Err(Error::Syntax {
+                    message: format!("unexpected character `{}` in BLC program", c),
+                    span: Span::new(i, i + c.len_utf8()),
+                })
+            }
+        }
+    }
+    let len = bits.len();
+    let mut parser = BitParser {
+        bits: bits.into_iter(),
+        pos: 0,
+    };
+    let term = parser.term(0)?;
+    if parser.pos < len {
+        return Err(Error::Syntax {
+            message: "trailing bits after BLC program".to_string(),
+            span: Span::new(parser.pos, len),
+        });
+    }
+    Ok(term)
+}
+
+// a program packed into bytes, most significant bit first, as read by BLC8 interpreters
+// returns the term and the number of bytes it occupies; the rest of the last byte is padding
+pub fn parse_bytes(bytes: &[u8]) -> Result<(AnonExpr, usize)> {
+    let bits = bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 == 1));
+    let mut parser = BitParser { bits, pos: 0 };
+    let term = parser.term(0)?;
+    Ok((term, parser.pos.div_ceil(8)))
+}
+
+// the runtime around a BLC term; its entry point is `main`
+const DRIVER: &str = "
+    // true when the input has an item at `pos`
+    #MORE pos;
+    // the bit at bit position `pos`
+    #BIT pos;
+    #ADD x y;
+    #MUL x y;
+    // writes a bit or byte and returns the identity, which continues with the rest of the output
+    #PUT x;
+    id x = x;
+    true x y = x;
+    nil x y = y;
+    cons h t z = z h t;
+    bit b = b 0 1;
+";
+
+const DRIVER_BLC: &str = "
+    input pos = MORE pos (cons (BIT pos) (input (ADD pos 1))) nil;
+    output l = l (λh t d. PUT (bit h) (output t)) 0;
+";
+
+const DRIVER_BLC8: &str = "
+    input pos = MORE pos (cons (byte (MUL pos 8)) (input (ADD pos 1))) nil;
+    byte p = cons (BIT p) (cons (BIT (ADD p 1)) (cons (BIT (ADD p 2)) (cons (BIT (ADD p 3))
+        (cons (BIT (ADD p 4)) (cons (BIT (ADD p 5)) (cons (BIT (ADD p 6)) (cons (BIT (ADD p 7))
+        nil)))))));
+    output l = l (λb t d. PUT (value b 0) (output t)) 0;
+    value b acc = b (λh t d. value t (ADD (MUL acc 2) (bit h))) acc;
+";
+
+// `main = output (term (input 0))`
+pub fn program(term: AnonExpr, mode: Mode) -> Result<AnonProgram> {
+    let driver = match mode {
+        Mode::Blc => DRIVER_BLC,
+        Mode::Blc8 => DRIVER_BLC8,
+    };
+    let driver: Program = format!("{}{}", DRIVER, driver).parse()?;
+    let mut program = driver.into_anon()?;
+    let def_id = |name: &str| {
+        let id = program
+            .defs
+            .iter()
+            .position(|def| def.name == Name::Named(name.to_string()));
+        AnonExpr::DefId(id.unwrap())
+    };
+    let input = AnonExpr::App(Box::new(def_id("input")), Box::new(AnonExpr::Prim(0)));
+    let result = AnonExpr::App(Box::new(term), Box::new(input));
+    let body = AnonExpr::App(Box::new(def_id("output")), Box::new(result));
+    program.defs.push(AnonDef {
+        name: Name::Named("main".to_string()),
+        params: 0,
+        body: Some(body),
+        span: None,
+    });
+    Ok(program)
+}
+
+struct Input<R: Read> {
+    bytes: Bytes<BufReader<R>>,
+    mode: Mode,
+    // bits in BLC mode, bytes in BLC8 mode
+    items: Vec<u8>,
+}
+
+impl<R: Read> Input<R> {
+    fn get(&mut self, pos: usize) -> Option<u8> {
+        while self.items.len() <= pos {
+            let byte = self.bytes.next()?.ok()?;
+            match (self.mode, byte) {
+                (Mode::Blc, b'0' | b'1') => self.items.push(byte - b'0'),
+                (Mode::Blc, _) => {}
+                (Mode::Blc8, _) => self.items.push(byte),
+            }
+        }
+        Some(self.items[pos])
+    }
+
+    fn bit(&mut self, pos: usize) -> Option<bool> {
+        match self.mode {
+            Mode::Blc => self.get(pos).map(|bit| bit == 1),
+            Mode::Blc8 => self.get(pos / 8).map(|byte| byte >> (7 - pos % 8) & 1 == 1),
+        }
+    }
+}
+
+// primops for the defs of `program(_, mode)`; `table` is the `def_indexes` of the compiled program
+pub fn primops<'a>(
+    mode: Mode,
+    table: &HashMap<&str, usize>,
+    input: impl Read + 'a,
+    mut output: impl Write + 'a,
+) -> HashMap<&'static str, Primop<'a>> {
+    let input = Rc::new(RefCell::new(Input {
+        bytes: BufReader::new(input).bytes(),
+        mode,
+        items: vec![],
+    }));
+    let (t, f) = (table["true"], table["nil"]);
+    let bool = move |b: bool| Some(Atom::Sc(if b { t } else { f }));
+    let id = table["id"];
+    let mut primops: HashMap<&'static str, Primop<'a>> = HashMap::new();
+    let more = input.clone();
+    primops.insert(
+        "MORE",
+        Box::new(move |a: &[i64]| bool(more.borrow_mut().get(a[0] as usize).is_some())),
+    );
+    primops.insert(
+        "BIT",
+        Box::new(move |a: &[i64]| {
+            let bit = input.borrow_mut().bit(a[0] as usize)?;
+            bool(!bit)
+        }),
+    );
+    primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+    primops.insert("MUL", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] * a[1]))));
+    primops.insert(
+        "PUT",
+        Box::new(move |a: &[i64]| {
+            let written = match mode {
+                Mode::Blc => output.write_all(if a[0] == 0 { b"0" } else { b"1" }),
+                Mode::Blc8 => output.write_all(&[a[0] as u8]),
+            };
+            written.ok()?;
+            Some(Atom::Sc(id))
+        }),
+    );
+    primops
+}
+
+// run a BLC term on the tree reducer until its output list ends
+pub fn run(term: AnonExpr, mode: Mode, input: impl Read, output: impl Write) -> Result<()> {
+    run_on::<TreeReducer>(term, mode, input, output)
+}
+
+// run a BLC term on any backend
+pub fn run_on<'a, B: Backend<'a>>(
+    term: AnonExpr,
+    mode: Mode,
+    input: impl Read + 'a,
+    output: impl Write + 'a,
+) -> Result<()> {
+    let program = program(term, mode)?.lambda_lift().lambda_elim()?.compress();
+    let mut backend = B::load(program, |table| primops(mode, table, input, output))?;
+    backend.run_to_whnf("main")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::gmachine::GMachine;
+    use crate::interpreter::graph_reducer::Graph;
+    use crate::interpreter::grin::Grin;
+    use crate::interpreter::stg::Stg;
+    use crate::interpreter::tim::Tim;
+
+    // the outputs of the sample programs on backend `B`
+    fn run_samples<'a, B: Backend<'a>>(outputs: &'a mut [Vec<u8>; 3]) {
+        let [cat, tail, reverse] = outputs;
+        // a lone space is `0010 0000`, the identity followed by padding: a BLC8 `cat`
+        let program = b" Hello, world!";
+        let (term, len) = parse_bytes(program).unwrap();
+        run_on::<B>(term, Mode::Blc8, &program[len..], cat).unwrap();
+        // λl. l (λh t d. t) l drops the first bit
+        let term = parse_bits("0001011000000011010").unwrap();
+        run_on::<B>(term, Mode::Blc, &b"1 0 1\n"[..], tail).unwrap();
+        // λi. Y (λr a l. l (λh t d. r (λz. z h a) t) a) (λx y. y) i reverses a list, here of bytes
+        let term = parse_bits(
+            "0001010100 0100011100 1101000011 1001101000 0000010110 0000000101 1111110000 \
+             1011011110 1111110110 1100000101 0",
+        )
+        .unwrap();
+        run_on::<B>(term, Mode::Blc8, &b"Hello, world!"[..], reverse).unwrap();
+    }
+
+    #[test]
+    fn parses_bitstrings() {
+        let term = parse_bits("00 01 01 10 000000 110 10").unwrap();
+        let program = AnonProgram {
+            defs: vec![AnonDef {
+                name: Name::Named("tail".to_string()),
+                params: 0,
+                body: Some(term),
+                span: None,
+            }],
+        };
+        assert_eq!(program.to_string(), "tail = λv0. v0 (λv1. λv2. λv3. v2) v0");
+        assert!(matches!(parse_bits("0010 1"), Err(Error::Syntax { .. })));
+        assert!(matches!(parse_bits("00 110"), Err(Error::Syntax { .. })));
+    }
+
+    #[test]
+    fn runs_blc_programs() {
+        let mut outputs: [[Vec<u8>; 3]; 6] = Default::default();
+        let [tree, graph, gmachine, tim, stg, grin] = &mut outputs;
+        run_samples::<TreeReducer>(tree);
+        run_samples::<Graph>(graph);
+        run_samples::<GMachine>(gmachine);
+        run_samples::<Tim>(tim);
+        run_samples::<Stg>(stg);
+        run_samples::<Grin>(grin);
+        let expected = [
+            b"Hello, world!".to_vec(),
+            b"01".to_vec(),
+            b"!dlrow ,olleH".to_vec(),
+        ];
+        for outputs in outputs {
+            assert_eq!(outputs, expected);
+        }
+    }
+}
@@ -1 +1,4 @@
-
+pub mod gmachine;
+pub mod grin;
+pub mod stg;
+pub mod tim;
@@ -0,0 +1,197 @@
+// G-machine code generation (Peyton Jones and Lester, "Implementing functional languages", ch. 3)
+// each supercombinator becomes a sequence of G-code instructions that builds its instantiated
+// body on the heap, instead of walking the body tree at every reduction
+// on entry the arguments of a supercombinator are on the stack, the first one on top, and the
+// root of the redex right below them
+// compilation schemes:
+// SC[f x0 .. xn-1 = e] = R[e] n
+// R[e] d (the body, in a context that updates the redex) = E[e] if it is a primop call, C[e]
+//   otherwise, followed by Update d; Pop d; Unwind
+// E[e] (evaluate e to WHNF) = E[ek-1] .. E[e0]; Prim p; Eval for a saturated primop call
+//   `p e0 .. ek-1`, since primop arguments are strict, PushInt i for an integer, and
+//   C[e]; Eval otherwise
+// C[e] (build the graph of e) = Push / PushGlobal / PushInt for atoms, C[e2]; C[e1]; MkAp for
+//   applications
+// a primop itself compiles as a supercombinator that evaluates all of its arguments
+
+use crate::structures::*;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    Unwind,
+    PushGlobal(usize),
+    PushInt(i64),
+    // push the address `n` slots below the top of the stack
+    Push(usize),
+    MkAp,
+    // overwrite the node `n` slots below the popped top with an indirection to it
+    Update(usize),
+    Pop(usize),
+    Eval,
+    // call primop definition `p` on the evaluated arguments on top of the stack, the first on top
+    Prim(usize),
+}
+
+pub struct GGlobal {
+    pub name: Name,
+    pub arity: usize,
+    pub code: Vec<Instr>,
+}
+
+pub struct GCode {
+    pub globals: Vec<GGlobal>,
+}
+
+struct Compiler<'p, 'a> {
+    program: &'p ScPrimProgram<'a>,
+    code: Vec<Instr>,
+}
+
+impl Compiler<'_, '_> {
+    // the primop and the arguments of a saturated primop call
+    fn primop_call<'e>(&self, expr: &'e ScExpr) -> Option<(usize, Vec<&'e ScExpr>)> {
+        let mut args = vec![];
+        let mut head = expr;
+        while let ScExpr::App(e1, e2) = head {
+            args.push(&**e2);
+            head = e1;
+        }
+        let ScExpr::DefId(p) = *head else {
+            return None;
+        };
+        let def = &self.program.defs[p];
+        if !matches!(def.body, ScBody::Prim(_)) || def.params != args.len() {
+            return None;
+        }
+        args.reverse();
+        Some((p, args))
+    }
+
+    fn compile_r(&mut self, expr: &ScExpr, depth: usize) {
+        match self.primop_call(expr) {
+            Some(_) => self.compile_e(expr, 0),
+            None => self.compile_c(expr, 0),
+        }
+        self.code.push(Instr::Update(depth));
+        self.code.push(Instr::Pop(depth));
+        self.code.push(Instr::Unwind);
+    }
+
+    // `offset` is the number of addresses pushed above the arguments
+    fn compile_e(&mut self, expr: &ScExpr, offset: usize) {
+        if let ScExpr::Prim(i) = expr {
+            self.code.push(Instr::PushInt(*i));
+            return;
+        }
+        let Some((p, args)) = self.primop_call(expr) else {
+            self.compile_c(expr, offset);
+            self.code.push(Instr::Eval);
+            return;
+        };
+        for (i, arg) in args.iter().enumerate().rev() {
+            self.compile_e(arg, offset + args.len() - 1 - i);
+        }
+        self.code.push(Instr::Prim(p));
+        self.code.push(Instr::Eval);
+    }
+
+    fn compile_c(&mut self, expr: &ScExpr, offset: usize) {
+        match expr {
+            ScExpr::DefId(i) => self.code.push(Instr::PushGlobal(*i)),
+            ScExpr::ArgId(i) => self.code.push(Instr::Push(i + offset)),
+            ScExpr::Prim(i) => self.code.push(Instr::PushInt(*i)),
+            ScExpr::App(e1, e2) => {
+                self.compile_c(e2, offset);
+                self.compile_c(e1, offset + 1);
+                self.code.push(Instr::MkAp);
+            }
+        }
+    }
+}
+
+impl ScPrimProgram<'_> {
+    pub fn compile_gmachine(&self) -> GCode {
+        let mut globals = vec![];
+        for (p, def) in self.defs.iter().enumerate() {
+            let mut compiler = Compiler {
+                program: self,
+                code: vec![],
+            };
+            match &def.body {
+                ScBody::Body(body) => compiler.compile_r(body, def.params),
+                ScBody::Prim(_) => {
+                    let args = (0..def.params).map(ScExpr::ArgId);
+                    let call = args.fold(ScExpr::DefId(p), |f, arg| {
+                        ScExpr::App(Box::new(f), Box::new(arg))
+                    });
+                    compiler.compile_r(&call, def.params);
+                }
+            }
+            globals.push(GGlobal {
+                name: def.name.clone(),
+                arity: def.params,
+                code: compiler.code,
+            });
+        }
+        GCode { globals }
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instr::Unwind => write!(f, "Unwind"),
+            Instr::PushGlobal(i) => write!(f, "PushGlobal {}", i),
+            Instr::PushInt(i) => write!(f, "PushInt {}", i),
+            Instr::Push(n) => write!(f, "Push {}", n),
+            Instr::MkAp => write!(f, "MkAp"),
+            Instr::Update(n) => write!(f, "Update {}", n),
+            Instr::Pop(n) => write!(f, "Pop {}", n),
+            Instr::Eval => write!(f, "Eval"),
+            Instr::Prim(p) => write!(f, "Prim {}", p),
+        }
+    }
+}
+
+impl Display for GCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, global) in self.globals.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let code = global.code.iter().map(Instr::to_string);
+            let code = code.collect::<Vec<_>>().join("; ");
+            write!(f, "{} {}: {}", global.name, global.arity, code)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_sc_program;
+    use std::collections::HashMap;
+
+    #[test]
+    fn primop_arguments_are_evaluated_strictly() {
+        let sc = parse_sc_program(
+            "
+            ADD x0 x1 = <builtin>
+            K x0 x1 = x0
+            f x0 x1 = ADD (ADD x1 i1) x0
+            g x0 = K (ADD x0 i1) x0
+            ",
+        )
+        .unwrap();
+        let mut primops = HashMap::new();
+        primops.insert("ADD", Box::new(|_: &[i64]| None) as _);
+        let program = sc.attach_prim(&mut primops).unwrap();
+        let expected = "\
+ADD 2: Push 1; Eval; Push 1; Eval; Prim 0; Eval; Update 2; Pop 2; Unwind
+K 2: Push 0; Update 2; Pop 2; Unwind
+f 2: Push 0; Eval; PushInt 1; Push 3; Eval; Prim 0; Eval; Prim 0; Eval; Update 2; Pop 2; Unwind
+g 1: Push 0; PushInt 1; Push 2; PushGlobal 0; MkAp; MkAp; PushGlobal 1; MkAp; MkAp; Update 1; Pop 1; Unwind";
+        assert_eq!(program.compile_gmachine().to_string(), expected);
+    }
+}
@@ -0,0 +1,931 @@
+// lowering to a GRIN-style first-order IR (Boquist, "Code optimisation techniques for lazy
+// functional languages")
+// the heap holds nodes, a tag with fields; a suspended call `F f` with the arguments of a
+// saturated call of `f`, a partial application `P k f` missing `k` arguments, `Fap` for the
+// application of an unknown function to one argument, and the values `CInt`, `CIoRes`, `CWorld`
+// every supercombinator becomes a function from pointers to its arguments to the node of its
+// value, and laziness is explicit: arguments are `store`d, and `eval` and `apply` are ordinary
+// GRIN functions that `fetch` a node, `case` on its tag and `update` a forced thunk
+// compilation schemes:
+// V[e] (the node of the value of e) = `eval xk` for a parameter, `unit (CInt i)`, a call for a
+//   saturated spine, `unit (P k f ..)` for a partial one, `apply` for extra arguments, and a
+//   `prim` on the values of the arguments for a saturated primop call, since primops are strict
+// A[e] (a pointer to e) = the parameter itself, or a `store` of the node that suspends e
+// the heap-points-to analysis finds the nodes each store site may hold and each variable may
+// point to; it does not merge the calls of `eval` and `apply` in their parameters, but runs
+// them at each call site on what reaches it, so the sites stay apart
+// inline_eval_apply then replaces every call of `eval` and `apply` by a copy of its body that
+// keeps only the case alternatives whose tags can reach the call, so that each call site only
+// makes the few calls it actually can; the nested calls in the copies stay calls
+// the runtime may force any stored thunk in place when it reads a result back, which the
+// analysis accounts for; `CInt` and `CWorld` nodes allocated by the runtime for `IoRes i f` ->
+// `f i World` live at the reserved store sites `INT_SITE` and `WORLD_SITE`
+// the analysis assumes that primops return integers, IO results and constructors without
+// fields, like `Prelude.True`
+
+use crate::compiler::stg::ctor_info;
+use crate::structures::*;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+
+pub const INT_SITE: usize = 0;
+pub const WORLD_SITE: usize = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Tag {
+    // an integer, held as a basic value in the only field
+    Int,
+    IoRes,
+    World,
+    // a suspended saturated call of function `f`
+    F(usize),
+    // function `f` missing `k` arguments
+    P(usize, usize),
+    // a suspended application of the first field to the second
+    Ap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Var(usize),
+    Lit(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SExpr {
+    Unit(Tag, Vec<Operand>),
+    Return(usize),
+    // allocate a node; the first number identifies the store site for the analysis
+    Store(usize, Tag, Vec<Operand>),
+    Fetch(usize),
+    // overwrite the node at the first variable with the second
+    Update(usize, usize),
+    Call(usize, Vec<usize>),
+    // a primop on the nodes of its evaluated arguments
+    Prim(usize, Vec<usize>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GExpr {
+    Simple(SExpr),
+    // `v <- e1; e2`
+    Bind(usize, Box<GExpr>, Box<GExpr>),
+    // the default binds the node to a variable
+    Case(usize, Vec<Alt>, Option<(usize, Box<GExpr>)>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alt {
+    pub tag: Tag,
+    pub fields: Vec<usize>,
+    pub body: GExpr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrinFn {
+    pub name: String,
+    // the parameters are the first variables
+    pub params: usize,
+    pub vars: usize,
+    pub body: GExpr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrinProgram {
+    // the function of supercombinator `f` is `fns[f]`, followed by `eval` and `apply`
+    pub fns: Vec<GrinFn>,
+    pub eval: usize,
+    pub apply: usize,
+    pub stores: usize,
+    // the nodes a primop may return
+    pub prim_results: Vec<Tag>,
+}
+
+impl GrinProgram {
+    pub fn field_count(&self, tag: Tag) -> usize {
+        match tag {
+            Tag::Int | Tag::IoRes => 1,
+            Tag::World => 0,
+            Tag::F(f) => self.fns[f].params,
+            Tag::P(f, k) => self.fns[f].params - k,
+            Tag::Ap => 2,
+        }
+    }
+
+    pub fn is_whnf(tag: Tag) -> bool {
+        !matches!(tag, Tag::F(_) | Tag::Ap)
+    }
+}
+
+struct Lowering<'p, 'a> {
+    program: &'p ScPrimProgram<'a>,
+    eval: usize,
+    apply: usize,
+    stores: usize,
+    vars: usize,
+}
+
+type Binds = Vec<(usize, GExpr)>;
+
+fn simple(sexpr: SExpr) -> GExpr {
+    GExpr::Simple(sexpr)
+}
+
+fn with_binds(binds: Binds, last: GExpr) -> GExpr {
+    let binds = binds.into_iter().rev();
+    binds.fold(last, |e, (v, e1)| GExpr::Bind(v, Box::new(e1), Box::new(e)))
+}
+
+fn vars(vars: &[usize]) -> Vec<Operand> {
+    vars.iter().map(|&v| Operand::Var(v)).collect()
+}
+
+// the head of the spine and its arguments, outermost last
+fn spine(expr: &ScExpr) -> (&ScExpr, Vec<&ScExpr>) {
+    let mut args = vec![];
+    let mut head = expr;
+    while let ScExpr::App(e1, e2) = head {
+        args.push(&**e2);
+        head = e1;
+    }
+    args.reverse();
+    (head, args)
+}
+
+impl Lowering<'_, '_> {
+    fn var(&mut self) -> usize {
+        self.vars += 1;
+        self.vars - 1
+    }
+
+    fn bind(&mut self, binds: &mut Binds, expr: GExpr) -> usize {
+        let v = self.var();
+        binds.push((v, expr));
+        v
+    }
+
+    fn store(&mut self, binds: &mut Binds, tag: Tag, fields: Vec<Operand>) -> usize {
+        self.stores += 1;
+        let site = self.stores - 1;
+        self.bind(binds, simple(SExpr::Store(site, tag, fields)))
+    }
+
+    fn arity(&self, f: usize) -> usize {
+        self.program.defs[f].params
+    }
+
+    fn is_prim(&self, f: usize) -> bool {
+        matches!(self.program.defs[f].body, ScBody::Prim(_))
+    }
+
+    fn value(&mut self, expr: &ScExpr, binds: &mut Binds) -> GExpr {
+        let (head, args) = spine(expr);
+        if let ScExpr::DefId(p) = *head {
+            if self.is_prim(p) && args.len() == self.arity(p) {
+                let values = args.into_iter().map(|arg| {
+                    let value = self.value(arg, binds);
+                    self.bind(binds, value)
+                });
+                return simple(SExpr::Prim(p, values.collect()));
+            }
+        }
+        let mut ptrs: Vec<_> = args.into_iter().map(|arg| self.ptr(arg, binds)).collect();
+        let mut node = match *head {
+            ScExpr::ArgId(k) => simple(SExpr::Call(self.eval, vec![k])),
+            ScExpr::Prim(i) => simple(SExpr::Unit(Tag::Int, vec![Operand::Lit(i)])),
+            ScExpr::DefId(f) => {
+                let arity = self.arity(f);
+                if ptrs.len() < arity {
+                    let tag = Tag::P(f, arity - ptrs.len());
+                    return simple(SExpr::Unit(tag, vars(&ptrs)));
+                }
+                let rest = ptrs.split_off(arity);
+                let call = simple(SExpr::Call(f, ptrs));
+                ptrs = rest;
+                call
+            }
+            ScExpr::App(..) => unreachable!(),
+        };
+        for ptr in ptrs {
+            let f = self.bind(binds, node);
+            node = simple(SExpr::Call(self.apply, vec![f, ptr]));
+        }
+        node
+    }
+
+    fn ptr(&mut self, expr: &ScExpr, binds: &mut Binds) -> usize {
+        if let ScExpr::ArgId(k) = *expr {
+            return k;
+        }
+        let (tag, fields) = self.node(expr, binds);
+        self.store(binds, tag, fields)
+    }
+
+    // the node that suspends `expr`
+    fn node(&mut self, expr: &ScExpr, binds: &mut Binds) -> (Tag, Vec<Operand>) {
+        let (head, args) = spine(expr);
+        let mut ptrs: Vec<_> = args.into_iter().map(|arg| self.ptr(arg, binds)).collect();
+        let f = match *head {
+            ScExpr::Prim(i) if ptrs.is_empty() => return (Tag::Int, vec![Operand::Lit(i)]),
+            ScExpr::Prim(i) => self.store(binds, Tag::Int, vec![Operand::Lit(i)]),
+            ScExpr::ArgId(k) => k,
+            ScExpr::DefId(f) => {
+                let arity = self.arity(f);
+                if ptrs.len() < arity {
+                    return (Tag::P(f, arity - ptrs.len()), vars(&ptrs));
+                }
+                let rest = ptrs.split_off(arity);
+                if rest.is_empty() {
+                    return (Tag::F(f), vars(&ptrs));
+                }
+                let f = self.store(binds, Tag::F(f), vars(&ptrs));
+                ptrs = rest;
+                f
+            }
+            ScExpr::App(..) => unreachable!(),
+        };
+        let last = ptrs.pop().unwrap();
+        let f = ptrs.into_iter().fold(f, |f, ptr| {
+            self.store(binds, Tag::Ap, vec![Operand::Var(f), Operand::Var(ptr)])
+        });
+        (Tag::Ap, vec![Operand::Var(f), Operand::Var(last)])
+    }
+
+    fn function(&mut self, f: usize) -> GrinFn {
+        let def = &self.program.defs[f];
+        self.vars = def.params;
+        let mut binds = vec![];
+        let last = match &def.body {
+            ScBody::Body(body) => self.value(body, &mut binds),
+            ScBody::Prim(_) => {
+                let values = (0..def.params).map(|k| {
+                    let value = simple(SExpr::Call(self.eval, vec![k]));
+                    self.bind(&mut binds, value)
+                });
+                simple(SExpr::Prim(f, values.collect()))
+            }
+        };
+        GrinFn {
+            name: def.name.to_string(),
+            params: def.params,
+            vars: self.vars,
+            body: with_binds(binds, last),
+        }
+    }
+
+    // eval p = n <- fetch p; case n of
+    //   F f x.. -> r <- f x..; update p r; unit r
+    //   Fap g x -> h <- eval g; r <- apply h x; update p r; unit r
+    //   v -> unit v
+    fn eval_fn(&mut self) -> GrinFn {
+        self.vars = 1;
+        let n = self.var();
+        let mut alts = vec![];
+        let thunks = (0..self.program.defs.len()).map(Tag::F);
+        for tag in thunks.chain([Tag::Ap]) {
+            let mut binds = vec![];
+            let fields: Vec<_> = match tag {
+                Tag::F(f) => (0..self.arity(f)).map(|_| self.var()).collect(),
+                _ => vec![self.var(), self.var()],
+            };
+            let value = match tag {
+                Tag::F(f) => simple(SExpr::Call(f, fields.clone())),
+                _ => {
+                    let call = simple(SExpr::Call(self.eval, vec![fields[0]]));
+                    let h = self.bind(&mut binds, call);
+                    simple(SExpr::Call(self.apply, vec![h, fields[1]]))
+                }
+            };
+            let r = self.bind(&mut binds, value);
+            self.bind(&mut binds, simple(SExpr::Update(0, r)));
+            let body = with_binds(binds, simple(SExpr::Return(r)));
+            alts.push(Alt { tag, fields, body });
+        }
+        let v = self.var();
+        let default = Some((v, Box::new(simple(SExpr::Return(v)))));
+        let body = GExpr::Case(n, alts, default);
+        let fetch = simple(SExpr::Fetch(0));
+        GrinFn {
+            name: "eval".to_string(),
+            params: 1,
+            vars: self.vars,
+            body: GExpr::Bind(n, Box::new(fetch), Box::new(body)),
+        }
+    }
+
+    // apply n x = case n of
+    //   P 1 f a.. -> f a.. x
+    //   P k f a.. -> unit (P (k - 1) f a.. x)
+    //   CIoRes i -> h <- eval x; i' <- store (CInt i); w <- store CWorld;
+    //     h' <- apply h i'; apply h' w
+    fn apply_fn(&mut self) -> GrinFn {
+        self.vars = 2;
+        let mut alts = vec![];
+        for f in 0..self.program.defs.len() {
+            let arity = self.arity(f);
+            for k in 1..=arity {
+                let mut fields: Vec<_> = (0..arity - k).map(|_| self.var()).collect();
+                let alt_fields = fields.clone();
+                fields.push(1);
+                let body = match k {
+                    1 => simple(SExpr::Call(f, fields)),
+                    _ => simple(SExpr::Unit(Tag::P(f, k - 1), vars(&fields))),
+                };
+                let tag = Tag::P(f, k);
+                alts.push(Alt {
+                    tag,
+                    fields: alt_fields,
+                    body,
+                });
+            }
+        }
+        let i = self.var();
+        let mut binds = vec![];
+        let h = self.bind(&mut binds, simple(SExpr::Call(self.eval, vec![1])));
+        let int = self.store(&mut binds, Tag::Int, vec![Operand::Var(i)]);
+        let world = self.store(&mut binds, Tag::World, vec![]);
+        let h = self.bind(&mut binds, simple(SExpr::Call(self.apply, vec![h, int])));
+        let last = simple(SExpr::Call(self.apply, vec![h, world]));
+        alts.push(Alt {
+            tag: Tag::IoRes,
+            fields: vec![i],
+            body: with_binds(binds, last),
+        });
+        GrinFn {
+            name: "apply".to_string(),
+            params: 2,
+            vars: self.vars,
+            body: GExpr::Case(0, alts, None),
+        }
+    }
+}
+
+impl ScPrimProgram<'_> {
+    pub fn compile_grin(&self) -> GrinProgram {
+        let eval = self.defs.len();
+        let mut lowering = Lowering {
+            program: self,
+            eval,
+            apply: eval + 1,
+            stores: WORLD_SITE + 1,
+            vars: 0,
+        };
+        let mut fns: Vec<_> = (0..self.defs.len()).map(|f| lowering.function(f)).collect();
+        fns.push(lowering.eval_fn());
+        fns.push(lowering.apply_fn());
+        let mut prim_results = vec![Tag::Int, Tag::IoRes, Tag::World];
+        for (c, def) in self.defs.iter().enumerate() {
+            if let Some(ctor) = ctor_info(def).filter(|ctor| ctor.fields == 0) {
+                prim_results.push(Tag::P(c, ctor.alts));
+            }
+        }
+        GrinProgram {
+            fns,
+            eval,
+            apply: eval + 1,
+            stores: lowering.stores,
+            prim_results,
+        }
+    }
+}
+
+// heap-points-to analysis
+
+pub type Locs = BTreeSet<usize>;
+
+// the store sites a pointer may point to, and the nodes a node variable may hold with the
+// sites each field may point to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AbsValue {
+    pub locs: Locs,
+    pub nodes: BTreeMap<Tag, Vec<Locs>>,
+}
+
+impl AbsValue {
+    fn locs(locs: Locs) -> Self {
+        Self {
+            locs,
+            nodes: BTreeMap::new(),
+        }
+    }
+
+    fn node(tag: Tag, fields: Vec<Locs>) -> Self {
+        Self {
+            locs: Locs::new(),
+            nodes: BTreeMap::from([(tag, fields)]),
+        }
+    }
+
+    // true if anything was added
+    fn join(&mut self, other: &AbsValue) -> bool {
+        let mut changed = false;
+        for loc in &other.locs {
+            changed |= self.locs.insert(*loc);
+        }
+        for (tag, fields) in &other.nodes {
+            match self.nodes.get_mut(tag) {
+                Some(old) => {
+                    for (old, new) in old.iter_mut().zip(fields) {
+                        let len = old.len();
+                        old.extend(new);
+                        changed |= old.len() > len;
+                    }
+                }
+                None => {
+                    self.nodes.insert(*tag, fields.clone());
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PointsTo {
+    // the value of each variable of each function
+    pub vars: Vec<Vec<AbsValue>>,
+    // the nodes each store site may hold, including values it is updated with
+    pub heap: Vec<AbsValue>,
+    pub returns: Vec<AbsValue>,
+}
+
+struct Analysis<'p> {
+    program: &'p GrinProgram,
+    result: PointsTo,
+    changed: bool,
+    // the continuations of `IoRes` being applied, to cut cycles through imprecise sites
+    io_conts: Vec<Locs>,
+}
+
+impl Analysis<'_> {
+    fn join_var(&mut self, f: usize, v: usize, value: &AbsValue) {
+        self.changed |= self.result.vars[f][v].join(value);
+    }
+
+    fn join_heap(&mut self, loc: usize, value: &AbsValue) {
+        self.changed |= self.result.heap[loc].join(value);
+    }
+
+    fn operand(&self, f: usize, operand: &Operand) -> Locs {
+        match operand {
+            Operand::Var(v) => self.result.vars[f][*v].locs.clone(),
+            Operand::Lit(_) => Locs::new(),
+        }
+    }
+
+    fn node(&self, f: usize, tag: Tag, fields: &[Operand]) -> AbsValue {
+        let fields = match tag {
+            Tag::Int | Tag::IoRes => vec![Locs::new()],
+            _ => fields.iter().map(|field| self.operand(f, field)).collect(),
+        };
+        AbsValue::node(tag, fields)
+    }
+
+    fn call(&mut self, g: usize, args: Vec<AbsValue>) -> AbsValue {
+        for (v, arg) in args.iter().enumerate() {
+            self.join_var(g, v, arg);
+        }
+        self.result.returns[g].clone()
+    }
+
+    fn sexpr(&mut self, f: usize, sexpr: &SExpr) -> AbsValue {
+        match sexpr {
+            SExpr::Unit(tag, fields) => self.node(f, *tag, fields),
+            SExpr::Return(v) => self.result.vars[f][*v].clone(),
+            SExpr::Store(site, tag, fields) => {
+                let node = self.node(f, *tag, fields);
+                self.join_heap(*site, &node);
+                AbsValue::locs(Locs::from([*site]))
+            }
+            SExpr::Fetch(p) => {
+                let mut value = AbsValue::default();
+                for loc in &self.result.vars[f][*p].locs {
+                    value.join(&self.result.heap[*loc]);
+                }
+                value
+            }
+            SExpr::Update(p, n) => {
+                let node = AbsValue {
+                    locs: Locs::new(),
+                    nodes: self.result.vars[f][*n].nodes.clone(),
+                };
+                for loc in self.result.vars[f][*p].locs.clone() {
+                    self.join_heap(loc, &node);
+                }
+                AbsValue::default()
+            }
+            // the result of `eval` at each site is what the runtime would find once it forced
+            // the pointers in place, and `apply` is analysed per call the way the runtime applies
+            SExpr::Call(g, args) if *g == self.program.eval => {
+                self.whnf(&self.result.vars[f][args[0]].locs)
+            }
+            SExpr::Call(g, args) if *g == self.program.apply => {
+                let h = self.result.vars[f][args[0]].clone();
+                let x = self.result.vars[f][args[1]].locs.clone();
+                self.apply(&h, &x)
+            }
+            SExpr::Call(g, args) => {
+                let args = args.iter().map(|&v| self.result.vars[f][v].clone());
+                self.call(*g, args.collect())
+            }
+            SExpr::Prim(..) => {
+                let mut value = AbsValue::default();
+                for &tag in &self.program.prim_results {
+                    let fields = vec![Locs::new(); self.program.field_count(tag)];
+                    value.join(&AbsValue::node(tag, fields));
+                }
+                value
+            }
+        }
+    }
+
+    fn expr(&mut self, f: usize, expr: &GExpr) -> AbsValue {
+        match expr {
+            GExpr::Simple(sexpr) => self.sexpr(f, sexpr),
+            GExpr::Bind(v, e1, e2) => {
+                let value = self.expr(f, e1);
+                self.join_var(f, *v, &value);
+                self.expr(f, e2)
+            }
+            GExpr::Case(n, alts, default) => {
+                let mut rest = self.result.vars[f][*n].nodes.clone();
+                let mut value = AbsValue::default();
+                for alt in alts {
+                    let Some(fields) = rest.remove(&alt.tag) else {
+                        continue;
+                    };
+                    for (v, locs) in alt.fields.iter().zip(fields) {
+                        self.join_var(f, *v, &AbsValue::locs(locs));
+                    }
+                    value.join(&self.expr(f, &alt.body));
+                }
+                if let (Some((v, body)), false) = (default, rest.is_empty()) {
+                    let rest = AbsValue {
+                        locs: Locs::new(),
+                        nodes: rest,
+                    };
+                    self.join_var(f, *v, &rest);
+                    value.join(&self.expr(f, body));
+                }
+                value
+            }
+        }
+    }
+
+    // the values the pointers may have once forced
+    fn whnf(&self, locs: &Locs) -> AbsValue {
+        let mut value = AbsValue::default();
+        for loc in locs {
+            for (tag, fields) in &self.result.heap[*loc].nodes {
+                if GrinProgram::is_whnf(*tag) {
+                    value.join(&AbsValue::node(*tag, fields.clone()));
+                }
+            }
+        }
+        value
+    }
+
+    fn apply(&mut self, function: &AbsValue, arg: &Locs) -> AbsValue {
+        let mut value = AbsValue::default();
+        for (tag, fields) in &function.nodes {
+            let mut args = fields.clone();
+            args.push(arg.clone());
+            match *tag {
+                Tag::P(g, 1) => {
+                    let result = self.call(g, args.into_iter().map(AbsValue::locs).collect());
+                    value.join(&result);
+                }
+                Tag::P(g, k) => {
+                    value.join(&AbsValue::node(Tag::P(g, k - 1), args));
+                }
+                Tag::IoRes if !self.io_conts.contains(arg) => {
+                    self.io_conts.push(arg.clone());
+                    self.join_heap(INT_SITE, &AbsValue::node(Tag::Int, vec![Locs::new()]));
+                    self.join_heap(WORLD_SITE, &AbsValue::node(Tag::World, vec![]));
+                    let h = self.whnf(arg);
+                    let h = self.apply(&h, &Locs::from([INT_SITE]));
+                    value.join(&self.apply(&h, &Locs::from([WORLD_SITE])));
+                    self.io_conts.pop();
+                }
+                _ => {}
+            }
+        }
+        value
+    }
+
+    // the runtime may force the thunk at any store site in place
+    fn force(&mut self, loc: usize) {
+        for (tag, fields) in self.result.heap[loc].nodes.clone() {
+            let value = match tag {
+                Tag::F(g) => self.call(g, fields.into_iter().map(AbsValue::locs).collect()),
+                Tag::Ap => {
+                    let h = self.whnf(&fields[0]);
+                    self.apply(&h, &fields[1])
+                }
+                _ => continue,
+            };
+            self.join_heap(loc, &value);
+        }
+    }
+}
+
+impl GrinProgram {
+    pub fn points_to(&self) -> PointsTo {
+        let mut analysis = Analysis {
+            program: self,
+            result: PointsTo {
+                vars: self
+                    .fns
+                    .iter()
+                    .map(|f| vec![AbsValue::default(); f.vars])
+                    .collect(),
+                heap: vec![AbsValue::default(); self.stores],
+                returns: vec![AbsValue::default(); self.fns.len()],
+            },
+            changed: true,
+            io_conts: vec![],
+        };
+        while analysis.changed {
+            analysis.changed = false;
+            for (f, function) in self.fns.iter().enumerate() {
+                if f == self.eval || f == self.apply {
+                    continue;
+                }
+                let value = analysis.expr(f, &function.body);
+                analysis.changed |= analysis.result.returns[f].join(&value);
+            }
+            for loc in 0..self.stores {
+                analysis.force(loc);
+            }
+        }
+        analysis.result
+    }
+
+    pub fn inline_eval_apply(&self) -> GrinProgram {
+        let points_to = self.points_to();
+        let mut program = self.clone();
+        for f in 0..self.fns.len() {
+            if f == self.eval || f == self.apply {
+                continue;
+            }
+            let mut inliner = Inliner {
+                program: self,
+                vars: &points_to.vars[f],
+                heap: &points_to.heap,
+                next_var: self.fns[f].vars,
+                stores: program.stores,
+            };
+            program.fns[f].body = inliner.expr(&self.fns[f].body);
+            program.fns[f].vars = inliner.next_var;
+            program.stores = inliner.stores;
+        }
+        program
+    }
+}
+
+struct Inliner<'p> {
+    program: &'p GrinProgram,
+    vars: &'p [AbsValue],
+    heap: &'p [AbsValue],
+    next_var: usize,
+    stores: usize,
+}
+
+impl Inliner<'_> {
+    fn expr(&mut self, expr: &GExpr) -> GExpr {
+        let (eval, apply) = (self.program.eval, self.program.apply);
+        match expr {
+            GExpr::Simple(SExpr::Call(g, args)) if *g == eval || *g == apply => {
+                // the tags `eval` may fetch, or the tags of the node `apply` applies
+                let tags: BTreeSet<Tag> = if *g == eval {
+                    let locs = &self.vars[args[0]].locs;
+                    let nodes = locs.iter().flat_map(|loc| self.heap[*loc].nodes.keys());
+                    nodes.copied().collect()
+                } else {
+                    self.vars[args[0]].nodes.keys().copied().collect()
+                };
+                let callee = &self.program.fns[*g];
+                let base = self.next_var;
+                self.next_var += callee.vars - callee.params;
+                let rename = |v: usize| {
+                    if v < callee.params {
+                        args[v]
+                    } else {
+                        base + v - callee.params
+                    }
+                };
+                let mut body = callee.body.rename(&rename, &mut self.stores);
+                restrict(&mut body, &tags);
+                body
+            }
+            GExpr::Simple(_) => expr.clone(),
+            GExpr::Bind(v, e1, e2) => {
+                GExpr::Bind(*v, Box::new(self.expr(e1)), Box::new(self.expr(e2)))
+            }
+            GExpr::Case(n, alts, default) => {
+                let alts = alts.iter().map(|alt| Alt {
+                    tag: alt.tag,
+                    fields: alt.fields.clone(),
+                    body: self.expr(&alt.body),
+                });
+                let alts = alts.collect();
+                let default = default
+                    .as_ref()
+                    .map(|(v, body)| (*v, Box::new(self.expr(body))));
+                GExpr::Case(*n, alts, default)
+            }
+        }
+    }
+}
+
+// keep only the alternatives of the body of `eval` or `apply` that can match `tags`
+fn restrict(expr: &mut GExpr, tags: &BTreeSet<Tag>) {
+    match expr {
+        GExpr::Bind(_, _, body) => restrict(body, tags),
+        GExpr::Case(_, alts, default) => {
+            alts.retain(|alt| tags.contains(&alt.tag));
+            if tags
+                .iter()
+                .all(|tag| alts.iter().any(|alt| alt.tag == *tag))
+            {
+                *default = None;
+            }
+        }
+        GExpr::Simple(_) => {}
+    }
+}
+
+impl GExpr {
+    // a copy with the variables renamed and new store sites
+    fn rename(&self, rename: &impl Fn(usize) -> usize, stores: &mut usize) -> GExpr {
+        let operands = |fields: &[Operand]| {
+            let fields = fields.iter().map(|field| match field {
+                Operand::Var(v) => Operand::Var(rename(*v)),
+                Operand::Lit(i) => Operand::Lit(*i),
+            });
+            fields.collect::<Vec<_>>()
+        };
+        let vars = |vs: &[usize]| vs.iter().map(|&v| rename(v)).collect::<Vec<_>>();
+        match self {
+            GExpr::Simple(sexpr) => simple(match sexpr {
+                SExpr::Unit(tag, fields) => SExpr::Unit(*tag, operands(fields)),
+                SExpr::Return(v) => SExpr::Return(rename(*v)),
+                SExpr::Store(_, tag, fields) => {
+                    *stores += 1;
+                    SExpr::Store(*stores - 1, *tag, operands(fields))
+                }
+                SExpr::Fetch(p) => SExpr::Fetch(rename(*p)),
+                SExpr::Update(p, n) => SExpr::Update(rename(*p), rename(*n)),
+                SExpr::Call(g, args) => SExpr::Call(*g, vars(args)),
+                SExpr::Prim(p, args) => SExpr::Prim(*p, vars(args)),
+            }),
+            GExpr::Bind(v, e1, e2) => GExpr::Bind(
+                rename(*v),
+                Box::new(e1.rename(rename, stores)),
+                Box::new(e2.rename(rename, stores)),
+            ),
+            GExpr::Case(n, alts, default) => {
+                let alts = alts.iter().map(|alt| Alt {
+                    tag: alt.tag,
+                    fields: vars(&alt.fields),
+                    body: alt.body.rename(rename, stores),
+                });
+                let alts = alts.collect();
+                let default = default
+                    .as_ref()
+                    .map(|(v, body)| (rename(*v), Box::new(body.rename(rename, stores))));
+                GExpr::Case(rename(*n), alts, default)
+            }
+        }
+    }
+}
+
+impl GrinProgram {
+    pub fn tag_to_string(&self, tag: Tag) -> String {
+        match tag {
+            Tag::Int => "CInt".to_string(),
+            Tag::IoRes => "CIoRes".to_string(),
+            Tag::World => "CWorld".to_string(),
+            Tag::F(f) => format!("F{}", self.fns[f].name),
+            Tag::P(f, k) => format!("P{} {}", k, self.fns[f].name),
+            Tag::Ap => "Fap".to_string(),
+        }
+    }
+
+    fn node_to_string(&self, tag: Tag, fields: &[Operand]) -> String {
+        let mut s = format!("({}", self.tag_to_string(tag));
+        for field in fields {
+            match field {
+                Operand::Var(v) => s += &format!(" v{}", v),
+                Operand::Lit(i) => s += &format!(" {}", i),
+            }
+        }
+        s + ")"
+    }
+
+    fn sexpr_to_string(&self, sexpr: &SExpr) -> String {
+        let vars = |vs: &[usize]| vs.iter().map(|v| format!(" v{}", v)).collect::<String>();
+        match sexpr {
+            SExpr::Unit(tag, fields) => format!("unit {}", self.node_to_string(*tag, fields)),
+            SExpr::Return(v) => format!("unit v{}", v),
+            SExpr::Store(_, tag, fields) => {
+                format!("store {}", self.node_to_string(*tag, fields))
+            }
+            SExpr::Fetch(p) => format!("fetch v{}", p),
+            SExpr::Update(p, n) => format!("update v{} v{}", p, n),
+            SExpr::Call(g, args) => format!("{}{}", self.fns[*g].name, vars(args)),
+            SExpr::Prim(p, args) => format!("prim {}{}", self.fns[*p].name, vars(args)),
+        }
+    }
+
+    fn write_expr(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        expr: &GExpr,
+        indent: usize,
+    ) -> std::fmt::Result {
+        let pad = " ".repeat(indent);
+        match expr {
+            GExpr::Simple(sexpr) => write!(f, "{}{}", pad, self.sexpr_to_string(sexpr)),
+            GExpr::Bind(v, e1, e2) => {
+                if let GExpr::Simple(sexpr) = &**e1 {
+                    writeln!(f, "{}v{} <- {}", pad, v, self.sexpr_to_string(sexpr))?;
+                } else {
+                    writeln!(f, "{}v{} <-", pad, v)?;
+                    self.write_expr(f, e1, indent + 2)?;
+                    writeln!(f)?;
+                }
+                self.write_expr(f, e2, indent)
+            }
+            GExpr::Case(n, alts, default) => {
+                write!(f, "{}case v{} of", pad, n)?;
+                for alt in alts {
+                    let fields = vars(&alt.fields);
+                    writeln!(f)?;
+                    writeln!(f, "{}  {} ->", pad, self.node_to_string(alt.tag, &fields))?;
+                    self.write_expr(f, &alt.body, indent + 4)?;
+                }
+                if let Some((v, body)) = default {
+                    writeln!(f)?;
+                    writeln!(f, "{}  v{} ->", pad, v)?;
+                    self.write_expr(f, body, indent + 4)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for GrinProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, function) in self.fns.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+                writeln!(f)?;
+            }
+            let params = (0..function.params).map(|v| format!(" v{}", v));
+            writeln!(f, "{}{} =", function.name, params.collect::<String>())?;
+            self.write_expr(f, &function.body, 2)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_sc_program;
+    use std::collections::HashMap;
+
+    #[test]
+    fn inlines_only_the_reachable_alternatives() {
+        let sc = parse_sc_program(
+            "
+            ADD x0 x1 = <builtin>
+            f x0 = ADD x0 i1
+            main = f (f i2)
+            ",
+        )
+        .unwrap();
+        let mut primops = HashMap::new();
+        primops.insert("ADD", Box::new(|_: &[i64]| None) as _);
+        let program = sc.attach_prim(&mut primops).unwrap();
+        let grin = program.compile_grin().inline_eval_apply();
+        let f = grin.to_string();
+        let f = f.split("\n\n").nth(1).unwrap();
+        let expected = "\
+f v0 =
+  v1 <-
+    v3 <- fetch v0
+    case v3 of
+      (Ff v8) ->
+        v9 <- f v8
+        v10 <- update v0 v9
+        unit v9
+      v18 ->
+        unit v18
+  v2 <- unit (CInt 1)
+  prim ADD v1 v2";
+        assert_eq!(f, expected);
+    }
+}
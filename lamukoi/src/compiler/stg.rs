@@ -0,0 +1,429 @@
+// translation to an STG-like form (Peyton Jones, "Implementing lazy functional languages on stock
+// hardware: the Spineless Tagless G-machine")
+// every supercombinator becomes a global lambda form, and every argument that is not an atom
+// becomes a let-bound lambda form: a closure of the free variables it captures, which is a
+// thunk to be updated with its value (`\u`) or, with parameters, a function (`\n`)
+// variables are numbered per lambda form: the captured free variables first, then the
+// parameters, then each let or case binder in scope
+// primop arguments are strict, so each one is evaluated by a `case` before the call
+// a supercombinator of the form `C f0 .. fm-1 c0 .. cn-1 = ck f0 .. fm-1` is the Scott encoding
+// of a constructor with m fields and n alternatives; a call with exactly its m fields becomes a
+// constructor application, which is a value, and applying that value to n alternatives enters
+// alternative k with the fields
+
+use crate::structures::*;
+use std::fmt::Display;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StgArg {
+    Local(usize),
+    Global(usize),
+    Int(i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StgExpr {
+    // apply the function to the arguments, or just evaluate it if there are none
+    App(StgArg, Vec<StgArg>),
+    // a saturated constructor application
+    Con(usize, Vec<StgArg>),
+    // a saturated primop call on evaluated arguments
+    Prim(usize, Vec<StgArg>),
+    Lit(i64),
+    // allocate a closure for each lambda form, binding the next variables
+    Let(Vec<usize>, Rc<StgExpr>),
+    // evaluate the first expression, bind its value to the next variable and continue
+    Case(Rc<StgExpr>, Rc<StgExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LambdaForm {
+    // variables of the enclosing form captured by the closure
+    pub free: Vec<usize>,
+    pub updatable: bool,
+    pub params: usize,
+    pub body: Rc<StgExpr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CtorInfo {
+    pub fields: usize,
+    pub alts: usize,
+    pub index: usize,
+}
+
+pub struct StgProgram {
+    pub names: Vec<Name>,
+    // the lambda form of global `g` is `forms[g]`, followed by the let-bound forms
+    pub forms: Vec<LambdaForm>,
+    // the global whose body each form comes from
+    pub owners: Vec<usize>,
+    pub ctors: Vec<Option<CtorInfo>>,
+}
+
+// the variable of each supercombinator parameter and the number of variables in scope
+struct Env {
+    args: Vec<usize>,
+    depth: usize,
+}
+
+struct Translator<'p, 'a> {
+    program: &'p ScPrimProgram<'a>,
+    ctors: &'p [Option<CtorInfo>],
+    forms: Vec<LambdaForm>,
+}
+
+pub(crate) fn ctor_info(def: &ScPrimDef) -> Option<CtorInfo> {
+    let ScBody::Body(body) = &def.body else {
+        return None;
+    };
+    let (head, args) = spine(body);
+    let ScExpr::ArgId(selected) = *head else {
+        return None;
+    };
+    let fields = args.len();
+    if selected < fields || selected >= def.params {
+        return None;
+    }
+    let in_order = args.iter().enumerate();
+    if !in_order.into_iter().all(|(i, arg)| **arg == ScExpr::ArgId(i)) {
+        return None;
+    }
+    Some(CtorInfo {
+        fields,
+        alts: def.params - fields,
+        index: selected - fields,
+    })
+}
+
+// the head of the spine and its arguments, outermost last
+fn spine(expr: &ScExpr) -> (&ScExpr, Vec<&ScExpr>) {
+    let mut args = vec![];
+    let mut head = expr;
+    while let ScExpr::App(e1, e2) = head {
+        args.push(&**e2);
+        head = e1;
+    }
+    args.reverse();
+    (head, args)
+}
+
+fn arg_ids(expr: &ScExpr, out: &mut Vec<usize>) {
+    match expr {
+        ScExpr::ArgId(k) => {
+            if !out.contains(k) {
+                out.push(*k);
+            }
+        }
+        ScExpr::App(e1, e2) => {
+            arg_ids(e1, out);
+            arg_ids(e2, out);
+        }
+        ScExpr::DefId(_) | ScExpr::Prim(_) => {}
+    }
+}
+
+impl Translator<'_, '_> {
+    fn primop_arity(&self, expr: &ScExpr) -> Option<usize> {
+        match *expr {
+            ScExpr::DefId(p) => match self.program.defs[p].body {
+                ScBody::Prim(_) => Some(self.program.defs[p].params),
+                ScBody::Body(_) => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn expr(&mut self, expr: &ScExpr, env: &Env) -> StgExpr {
+        let (head, args) = spine(expr);
+        if let (ScExpr::Prim(i), true) = (head, args.is_empty()) {
+            return StgExpr::Lit(*i);
+        }
+        if let Some(params) = self.primop_arity(head) {
+            if args.len() >= params {
+                let ScExpr::DefId(p) = *head else {
+                    unreachable!()
+                };
+                let (call, extra) = args.split_at(params);
+                return self.strict(p, call, vec![], extra, env.depth, env);
+            }
+        }
+        if let ScExpr::DefId(c) = *head {
+            if let Some(ctor) = self.ctors[c] {
+                if ctor.fields > 0 && ctor.fields == args.len() {
+                    return self.with_atoms(&args, env, |atoms| StgExpr::Con(c, atoms));
+                }
+            }
+        }
+        let head = self.atom(head, env, &mut vec![]);
+        self.with_atoms(&args, env, |atoms| StgExpr::App(head, atoms))
+    }
+
+    // evaluate the remaining primop arguments one by one, then call the primop and apply the
+    // result to `extra`
+    fn strict(
+        &mut self,
+        p: usize,
+        call: &[&ScExpr],
+        mut atoms: Vec<StgArg>,
+        extra: &[&ScExpr],
+        depth: usize,
+        env: &Env,
+    ) -> StgExpr {
+        let Some((arg, rest)) = call.split_first() else {
+            let call = StgExpr::Prim(p, atoms);
+            if extra.is_empty() {
+                return call;
+            }
+            let env = Env {
+                args: env.args.clone(),
+                depth: depth + 1,
+            };
+            let head = StgArg::Local(depth);
+            let body = self.with_atoms(extra, &env, |atoms| StgExpr::App(head, atoms));
+            return StgExpr::Case(Rc::new(call), Rc::new(body));
+        };
+        if let ScExpr::Prim(i) = arg {
+            atoms.push(StgArg::Int(*i));
+            return self.strict(p, rest, atoms, extra, depth, env);
+        }
+        let scrutinee = self.expr(
+            arg,
+            &Env {
+                args: env.args.clone(),
+                depth,
+            },
+        );
+        atoms.push(StgArg::Local(depth));
+        let body = self.strict(p, rest, atoms, extra, depth + 1, env);
+        StgExpr::Case(Rc::new(scrutinee), Rc::new(body))
+    }
+
+    // `body` of the atoms for `args`, under a `let` for those that are not atoms
+    fn with_atoms(
+        &mut self,
+        args: &[&ScExpr],
+        env: &Env,
+        body: impl FnOnce(Vec<StgArg>) -> StgExpr,
+    ) -> StgExpr {
+        let mut forms = vec![];
+        let atoms = args
+            .iter()
+            .map(|arg| self.atom(arg, env, &mut forms))
+            .collect();
+        let body = body(atoms);
+        if forms.is_empty() {
+            body
+        } else {
+            StgExpr::Let(forms, Rc::new(body))
+        }
+    }
+
+    fn atom(&mut self, expr: &ScExpr, env: &Env, forms: &mut Vec<usize>) -> StgArg {
+        match expr {
+            ScExpr::ArgId(k) => StgArg::Local(env.args[*k]),
+            ScExpr::DefId(g) => StgArg::Global(*g),
+            ScExpr::Prim(i) => StgArg::Int(*i),
+            ScExpr::App(..) => {
+                let local = env.depth + forms.len();
+                forms.push(self.thunk(expr, env));
+                StgArg::Local(local)
+            }
+        }
+    }
+
+    fn thunk(&mut self, expr: &ScExpr, env: &Env) -> usize {
+        let mut free = vec![];
+        arg_ids(expr, &mut free);
+        free.sort_unstable();
+        let mut args = vec![usize::MAX; env.args.len()];
+        for (i, &k) in free.iter().enumerate() {
+            args[k] = i;
+        }
+        let inner = Env {
+            args,
+            depth: free.len(),
+        };
+        let body = self.expr(expr, &inner);
+        self.forms.push(LambdaForm {
+            free: free.iter().map(|&k| env.args[k]).collect(),
+            updatable: true,
+            params: 0,
+            body: Rc::new(body),
+        });
+        self.forms.len() - 1
+    }
+}
+
+impl ScPrimProgram<'_> {
+    pub fn compile_stg(&self) -> StgProgram {
+        let ctors: Vec<_> = self.defs.iter().map(ctor_info).collect();
+        let placeholder = LambdaForm {
+            free: vec![],
+            updatable: false,
+            params: 0,
+            body: Rc::new(StgExpr::Lit(0)),
+        };
+        let mut translator = Translator {
+            program: self,
+            ctors: &ctors,
+            forms: vec![placeholder; self.defs.len()],
+        };
+        let mut owners: Vec<_> = (0..self.defs.len()).collect();
+        for (g, def) in self.defs.iter().enumerate() {
+            let env = Env {
+                args: (0..def.params).collect(),
+                depth: def.params,
+            };
+            let body = match &def.body {
+                ScBody::Body(body) => translator.expr(body, &env),
+                ScBody::Prim(_) => {
+                    let args: Vec<_> = (0..def.params).map(ScExpr::ArgId).collect();
+                    let args: Vec<_> = args.iter().collect();
+                    translator.strict(g, &args, vec![], &[], def.params, &env)
+                }
+            };
+            translator.forms[g] = LambdaForm {
+                free: vec![],
+                updatable: def.params == 0,
+                params: def.params,
+                body: Rc::new(body),
+            };
+            owners.resize(translator.forms.len(), g);
+        }
+        let forms = translator.forms;
+        StgProgram {
+            names: self.defs.iter().map(|def| def.name.clone()).collect(),
+            forms,
+            owners,
+            ctors,
+        }
+    }
+}
+
+impl StgProgram {
+    fn fmt_arg(&self, f: &mut std::fmt::Formatter<'_>, arg: &StgArg) -> std::fmt::Result {
+        match arg {
+            StgArg::Local(i) => write!(f, "v{}", i),
+            StgArg::Global(g) => write!(f, "{}", self.names[*g]),
+            StgArg::Int(i) => write!(f, "{}", i),
+        }
+    }
+
+    fn fmt_args(&self, f: &mut std::fmt::Formatter<'_>, args: &[StgArg]) -> std::fmt::Result {
+        write!(f, "{{")?;
+        self.fmt_list(f, args)?;
+        write!(f, "}}")
+    }
+
+    fn fmt_list(&self, f: &mut std::fmt::Formatter<'_>, args: &[StgArg]) -> std::fmt::Result {
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            self.fmt_arg(f, arg)?;
+        }
+        Ok(())
+    }
+
+    // `depth` is the number of variables in scope
+    fn fmt_expr(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        expr: &StgExpr,
+        depth: usize,
+    ) -> std::fmt::Result {
+        match expr {
+            StgExpr::App(head, args) => {
+                self.fmt_arg(f, head)?;
+                if !args.is_empty() {
+                    write!(f, " ")?;
+                    self.fmt_args(f, args)?;
+                }
+                Ok(())
+            }
+            StgExpr::Con(c, args) => {
+                write!(f, "{} [", self.names[*c])?;
+                self.fmt_list(f, args)?;
+                write!(f, "]")
+            }
+            StgExpr::Prim(p, args) => {
+                write!(f, "{}# ", self.names[*p])?;
+                self.fmt_args(f, args)
+            }
+            StgExpr::Lit(i) => write!(f, "{}", i),
+            StgExpr::Let(forms, body) => {
+                write!(f, "let {{ ")?;
+                for (i, &form) in forms.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "v{} = ", depth + i)?;
+                    self.fmt_form(f, &self.forms[form])?;
+                }
+                write!(f, " }} in ")?;
+                self.fmt_expr(f, body, depth + forms.len())
+            }
+            StgExpr::Case(scrutinee, body) => {
+                write!(f, "case ")?;
+                self.fmt_expr(f, scrutinee, depth)?;
+                write!(f, " of v{} -> ", depth)?;
+                self.fmt_expr(f, body, depth + 1)
+            }
+        }
+    }
+
+    fn fmt_form(&self, f: &mut std::fmt::Formatter<'_>, form: &LambdaForm) -> std::fmt::Result {
+        let free: Vec<_> = form.free.iter().map(|&v| StgArg::Local(v)).collect();
+        self.fmt_args(f, &free)?;
+        write!(f, " \\{} ", if form.updatable { 'u' } else { 'n' })?;
+        let n = form.free.len();
+        let params: Vec<_> = (n..n + form.params).map(StgArg::Local).collect();
+        self.fmt_args(f, &params)?;
+        write!(f, " -> ")?;
+        self.fmt_expr(f, &form.body, n + form.params)
+    }
+}
+
+impl Display for StgProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (g, name) in self.names.iter().enumerate() {
+            if g > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{} = ", name)?;
+            self.fmt_form(f, &self.forms[g])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_sc_program;
+    use std::collections::HashMap;
+
+    #[test]
+    fn translates_closures_and_constructors() {
+        let sc = parse_sc_program(
+            "
+            ADD x0 x1 = <builtin>
+            Cons x0 x1 x2 x3 = x3 x0 x1
+            f x0 x1 = x0 (Cons (ADD x1 i1) (f x0 x1))
+            g x0 = Cons x0 (ADD x0 i2)
+            ",
+        )
+        .unwrap();
+        let mut primops = HashMap::new();
+        primops.insert("ADD", Box::new(|_: &[i64]| None) as _);
+        let program = sc.attach_prim(&mut primops).unwrap().compile_stg();
+        let expected = "\
+ADD = {} \\n {v0, v1} -> case v0 of v2 -> case v1 of v3 -> ADD# {v2, v3}
+Cons = {} \\n {v0, v1, v2, v3} -> v3 {v0, v1}
+f = {} \\n {v0, v1} -> let { v2 = {v0, v1} \\u {} -> let { v2 = {v1} \\u {} -> \
+case v0 of v1 -> ADD# {v1, 1}; v3 = {v0, v1} \\u {} -> f {v0, v1} } in Cons [v2, v3] } in v0 {v2}
+g = {} \\n {v0} -> let { v1 = {v0} \\u {} -> case v0 of v1 -> ADD# {v1, 2} } in Cons [v0, v1]";
+        assert_eq!(program.to_string(), expected);
+    }
+}
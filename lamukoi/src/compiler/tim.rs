@@ -0,0 +1,266 @@
+// Three Instruction Machine code generation (Peyton Jones and Lester, "Implementing functional
+// languages", ch. 4)
+// a closure is a code block and a frame, the array of closures for the parameters of one
+// supercombinator call; every supercombinator becomes `Take n` followed by the code of its body,
+// which pushes the arguments of the body's spine and enters its head
+// compilation schemes:
+// SC[f x0 .. xn-1 = e] = Take n; R[e]
+// R[e] (enter the value of e) = Push A[ek-1] .. Push A[e0]; Enter A[h] for a spine
+//   `h e0 .. ek-1`, and B[e]; Return for a saturated primop call
+// A[e] (the closure of e) = Arg k, Label f or Int i for atoms, and Code c for anything else,
+//   where c is a new updatable block with the code R[e] in the current frame
+// B[e] k (push the value of e on the value stack, then continue with k) = PushV i for an
+//   integer, B[e0] (B[e1] .. (Prim p; k)) for a saturated primop call `p e0 .. en-1`, since
+//   primop arguments are strict, and PushCont c; R[e] otherwise, where the block c = k continues
+//   in the current frame once e returns its value
+// a primop itself compiles as a supercombinator whose body is the saturated call
+
+use crate::structures::*;
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    // the closure in slot `k` of the current frame
+    Arg(usize),
+    // the supercombinator `f`
+    Label(usize),
+    // block `c` in the current frame
+    Code(usize),
+    Int(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    // move `n` arguments from the stack to a new frame
+    Take(usize),
+    Push(Mode),
+    // push a continuation: block `c` in the current frame
+    PushCont(usize),
+    Enter(Mode),
+    PushV(i64),
+    // call primop `p` on the values on top of the value stack, the last argument on top
+    Prim(usize),
+    // enter the value on top of the value stack
+    Return,
+}
+
+pub struct Block {
+    pub code: Vec<Instr>,
+    // a thunk whose value is written back to the frame slot it is entered from
+    pub updatable: bool,
+    // for a supercombinator body, the supercombinator
+    pub sc: Option<usize>,
+    // for a continuation, the primop waiting for the value
+    pub prim: Option<usize>,
+}
+
+pub struct TimCode {
+    pub blocks: Vec<Block>,
+    // the block of each supercombinator
+    pub entries: Vec<usize>,
+}
+
+struct Compiler<'p, 'a> {
+    program: &'p ScPrimProgram<'a>,
+    blocks: Vec<Block>,
+}
+
+impl Compiler<'_, '_> {
+    fn block(&mut self, code: Vec<Instr>, updatable: bool, prim: Option<usize>) -> usize {
+        self.blocks.push(Block {
+            code,
+            updatable,
+            sc: None,
+            prim,
+        });
+        self.blocks.len() - 1
+    }
+
+    // the primop and the arguments of a saturated primop call
+    fn primop_call<'e>(&self, expr: &'e ScExpr) -> Option<(usize, Vec<&'e ScExpr>)> {
+        let (head, args) = spine(expr);
+        let ScExpr::DefId(p) = *head else {
+            return None;
+        };
+        let def = &self.program.defs[p];
+        if !matches!(def.body, ScBody::Prim(_)) || def.params != args.len() {
+            return None;
+        }
+        Some((p, args))
+    }
+
+    fn compile_r(&mut self, expr: &ScExpr, code: &mut Vec<Instr>) {
+        if self.primop_call(expr).is_some() {
+            self.compile_b(expr, vec![Instr::Return], code);
+            return;
+        }
+        let (head, args) = spine(expr);
+        for arg in args.into_iter().rev() {
+            let mode = self.compile_a(arg);
+            code.push(Instr::Push(mode));
+        }
+        let mode = self.compile_a(head);
+        code.push(Instr::Enter(mode));
+    }
+
+    fn compile_a(&mut self, expr: &ScExpr) -> Mode {
+        match expr {
+            ScExpr::ArgId(k) => Mode::Arg(*k),
+            ScExpr::DefId(f) => Mode::Label(*f),
+            ScExpr::Prim(i) => Mode::Int(*i),
+            ScExpr::App(..) => {
+                let mut code = vec![];
+                self.compile_r(expr, &mut code);
+                Mode::Code(self.block(code, true, None))
+            }
+        }
+    }
+
+    fn compile_b(&mut self, expr: &ScExpr, cont: Vec<Instr>, code: &mut Vec<Instr>) {
+        self.compile_b_for(expr, cont, code, None)
+    }
+
+    // `prim` is the primop waiting for the value of `expr`, if any
+    fn compile_b_for(
+        &mut self,
+        expr: &ScExpr,
+        cont: Vec<Instr>,
+        code: &mut Vec<Instr>,
+        prim: Option<usize>,
+    ) {
+        if let ScExpr::Prim(i) = expr {
+            code.push(Instr::PushV(*i));
+            code.extend(cont);
+            return;
+        }
+        let Some((p, args)) = self.primop_call(expr) else {
+            let cont = self.block(cont, false, prim);
+            code.push(Instr::PushCont(cont));
+            self.compile_r(expr, code);
+            return;
+        };
+        let mut rest = vec![Instr::Prim(p)];
+        rest.extend(cont);
+        for arg in args.into_iter().rev() {
+            let mut arg_code = vec![];
+            self.compile_b_for(arg, rest, &mut arg_code, Some(p));
+            rest = arg_code;
+        }
+        code.extend(rest);
+    }
+}
+
+// the head of the spine and its arguments, outermost last
+fn spine(expr: &ScExpr) -> (&ScExpr, Vec<&ScExpr>) {
+    let mut args = vec![];
+    let mut head = expr;
+    while let ScExpr::App(e1, e2) = head {
+        args.push(&**e2);
+        head = e1;
+    }
+    args.reverse();
+    (head, args)
+}
+
+impl ScPrimProgram<'_> {
+    pub fn compile_tim(&self) -> TimCode {
+        let mut compiler = Compiler {
+            program: self,
+            blocks: vec![],
+        };
+        let mut entries = vec![];
+        for (f, def) in self.defs.iter().enumerate() {
+            let mut code = vec![Instr::Take(def.params)];
+            match &def.body {
+                ScBody::Body(body) => compiler.compile_r(body, &mut code),
+                ScBody::Prim(_) => {
+                    let args = (0..def.params).map(ScExpr::ArgId);
+                    let call = args.fold(ScExpr::DefId(f), |f, arg| {
+                        ScExpr::App(Box::new(f), Box::new(arg))
+                    });
+                    compiler.compile_r(&call, &mut code);
+                }
+            }
+            // a CAF is a thunk as well
+            let block = compiler.block(code, def.params == 0, None);
+            compiler.blocks[block].sc = Some(f);
+            entries.push(block);
+        }
+        TimCode {
+            blocks: compiler.blocks,
+            entries,
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mode::Arg(k) => write!(f, "Arg {}", k),
+            Mode::Label(sc) => write!(f, "Label {}", sc),
+            Mode::Code(c) => write!(f, "Code #{}", c),
+            Mode::Int(i) => write!(f, "Int {}", i),
+        }
+    }
+}
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instr::Take(n) => write!(f, "Take {}", n),
+            Instr::Push(mode) => write!(f, "Push ({})", mode),
+            Instr::PushCont(c) => write!(f, "PushCont #{}", c),
+            Instr::Enter(mode) => write!(f, "Enter ({})", mode),
+            Instr::PushV(i) => write!(f, "PushV {}", i),
+            Instr::Prim(p) => write!(f, "Prim {}", p),
+            Instr::Return => write!(f, "Return"),
+        }
+    }
+}
+
+impl Display for TimCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let code = block.code.iter().map(Instr::to_string);
+            let code = code.collect::<Vec<_>>().join("; ");
+            write!(f, "#{}", i)?;
+            if let Some(sc) = block.sc {
+                write!(f, " {}", sc)?;
+            }
+            write!(f, ": {}", code)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_sc_program;
+    use std::collections::HashMap;
+
+    #[test]
+    fn compiles_frames_and_continuations() {
+        let sc = parse_sc_program(
+            "
+            ADD x0 x1 = <builtin>
+            f x0 x1 = x1 (ADD x0 i1) (f x0)
+            ",
+        )
+        .unwrap();
+        let mut primops = HashMap::new();
+        primops.insert("ADD", Box::new(|_: &[i64]| None) as _);
+        let program = sc.attach_prim(&mut primops).unwrap();
+        let expected = "\
+#0: Prim 0; Return
+#1: PushCont #0; Enter (Arg 1)
+#2 0: Take 2; PushCont #1; Enter (Arg 0)
+#3: Push (Arg 0); Enter (Label 1)
+#4: PushV 1; Prim 0; Return
+#5: PushCont #4; Enter (Arg 0)
+#6 1: Take 2; Push (Code #3); Push (Code #5); Enter (Arg 1)";
+        assert_eq!(program.compile_tim().to_string(), expected);
+    }
+}
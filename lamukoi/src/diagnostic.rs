@@ -0,0 +1,236 @@
+// rustc-style rendering of errors and warnings against the source text
+// error: cannot find `foo` in definition `main`
+//  --> main.lmk:3:8
+//   |
+// 3 | main = foo 1;
+//   |        ^^^ not found
+//   = note: ...
+
+use crate::structures::Span;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+    // primary labels are underlined with `^`, secondary ones with `-`
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+// 1-based line and column (in chars) of a byte offset, along with the line's text
+fn locate(source: &str, offset: usize) -> (usize, usize, &str) {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[offset..]
+        .find('\n')
+        .map_or(source.len(), |i| offset + i);
+    let line_no = source[..line_start].matches('\n').count() + 1;
+    let col = source[line_start..offset].chars().count() + 1;
+    (line_no, col, &source[line_start..line_end])
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn with_label(mut self, span: Option<Span>, message: impl Into<String>) -> Self {
+        if let Some(span) = span {
+            self.labels.push(Label {
+                span,
+                message: message.into(),
+                primary: true,
+            });
+        }
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Option<Span>, message: impl Into<String>) -> Self {
+        if let Some(span) = span {
+            self.labels.push(Label {
+                span,
+                message: message.into(),
+                primary: false,
+            });
+        }
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut out = String::new();
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        writeln!(out, "{}: {}", severity, self.message).unwrap();
+        // in source order, whichever label is primary
+        let mut located = self
+            .labels
+            .iter()
+            .map(|label| (label, locate(source, label.span.start)))
+            .collect::<Vec<_>>();
+        located.sort_by_key(|(label, _)| label.span.start);
+        let gutter = located
+            .iter()
+            .map(|(_, (line_no, _, _))| line_no.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let pad = " ".repeat(gutter);
+        let primary = located
+            .iter()
+            .find(|(label, _)| label.primary)
+            .or(located.first());
+        if let Some((_, (line_no, col, _))) = primary {
+            writeln!(out, "{}--> {}:{}:{}", pad, file_name, line_no, col).unwrap();
+        }
+        if !located.is_empty() {
+            writeln!(out, "{} |", pad).unwrap();
+        }
+        for (label, (line_no, col, line)) in &located {
+            writeln!(out, "{:>width$} | {}", line_no, line, width = gutter).unwrap();
+            // underline up to the end of the line if the span covers several lines
+            let rest = line.chars().count() + 1 - col;
+            let len = source
+                .get(label.span.start..label.span.end.min(source.len()))
+                .map_or(1, |s| s.chars().count())
+                .clamp(1, rest.max(1));
+            let mark = if label.primary { "^" } else { "-" };
+            let underline = format!("{}{}", " ".repeat(col - 1), mark.repeat(len));
+            if label.message.is_empty() {
+                writeln!(out, "{} | {}", pad, underline).unwrap();
+            } else {
+                writeln!(out, "{} | {} {}", pad, underline, label.message).unwrap();
+            }
+        }
+        for note in &self.notes {
+            writeln!(out, "{} = note: {}", pad, note).unwrap();
+        }
+        out
+    }
+}
+
+// Levenshtein distance, in chars
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+// the closest candidate within a third of the name's length (at least one edit), for
+// "did you mean" notes; ties go to the alphabetically first candidate
+pub fn suggest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<String> {
+    let limit = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .filter(|candidate| *candidate != name && !candidate.contains('$'))
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_caret_under_span() {
+        let source = "id x = x;\nmain = foo 1;\n";
+        let start = source.find("foo").unwrap();
+        let diagnostic = Diagnostic::new(Severity::Error, "cannot find `foo`")
+            .with_label(Some(Span::new(start, start + 3)), "not found");
+        let expected = "\
+error: cannot find `foo`
+ --> main.lmk:2:8
+  |
+2 | main = foo 1;
+  |        ^^^ not found
+";
+        assert_eq!(diagnostic.render("main.lmk", source), expected);
+    }
+
+    #[test]
+    fn render_collision_with_previous_definition() {
+        let source = "id x = x;\nid y = y;\n";
+        let error = crate::parser::parse_program(source)
+            .unwrap()
+            .into_anon()
+            .unwrap_err();
+        let expected = "\
+error: the name `id` is defined multiple times
+ --> lib.lmk:2:1
+  |
+1 | id x = x;
+  | -- previous definition here
+2 | id y = y;
+  | ^^ redefined here
+";
+        assert_eq!(error.render("lib.lmk", source), expected);
+    }
+
+    #[test]
+    fn render_span_from_later_passes() {
+        let source = "#PUTC c;\nmain = PUTC 65;\n";
+        let sc = crate::parser::parse_program(source)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let Err(error) = sc.attach_prim(&mut Default::default()) else {
+            panic!("`PUTC` has no implementation");
+        };
+        let expected = "\
+error: no implementation for primop `PUTC`
+ --> main.lmk:1:2
+  |
+1 | #PUTC c;
+  |  ^^^^ declared here
+  = note: declared with `#` but no primop was supplied to `attach_prim`
+";
+        assert_eq!(error.render("main.lmk", source), expected);
+    }
+
+    #[test]
+    fn suggests_close_names() {
+        let names = ["length", "filter", "fold", "foldr"];
+        assert_eq!(suggest("lenght", names), Some("length".to_string()));
+        assert_eq!(suggest("folr", names), Some("fold".to_string()));
+        assert_eq!(suggest("map", names), None);
+    }
+}
@@ -1,21 +1,30 @@
+use crate::diagnostic::{Diagnostic, Severity};
 use crate::structures::*;
+use std::fmt::Display;
+use std::path::PathBuf;
 
 #[non_exhaustive]
 #[derive(Debug)]
 pub enum Error {
     TopLevelNameCollision {
         name: Ident,
+        span: Option<Span>,
+        previous: Option<Span>,
     },
     ScParamNameCollision {
         def_name: Ident,
         param_name: Ident,
+        span: Option<Span>,
     },
     UndefinedIdent {
         def_name: Ident,
         undefined_name: Ident,
+        span: Option<Span>,
+        suggestion: Option<Ident>,
     },
     UnexpectedLambda {
         def_name: Name,
+        span: Option<Span>,
     },
     UnexpectedPrimApp {
         prim_name: Ident,
@@ -23,6 +32,7 @@ pub enum Error {
     },
     UnknownPrimop {
         def_name: Ident,
+        span: Option<Span>,
     },
     PrimopFailure {
         def_name: Ident,
@@ -30,6 +40,588 @@ pub enum Error {
     },
     UnnamedPrimop {
         def_no: usize,
+        span: Option<Span>,
+    },
+    UnknownEntry {
+        name: Ident,
+        suggestion: Option<Ident>,
+    },
+    NoMatchingAlternative {
+        fn_name: Ident,
+        node: String,
+    },
+    MemoryLimit {
+        limit: usize,
+    },
+    Syntax {
+        message: String,
+        span: Span,
+    },
+    UnknownConstructor {
+        def_name: Ident,
+        ctor: Ident,
+        span: Option<Span>,
+    },
+    MissingCaseArm {
+        def_name: Ident,
+        data_name: Ident,
+        ctors: Vec<Ident>,
+        span: Option<Span>,
+    },
+    CaseArityMismatch {
+        def_name: Ident,
+        ctor: Ident,
+        expected: usize,
+        found: usize,
+        span: Option<Span>,
+    },
+    CaseArmMismatch {
+        def_name: Ident,
+        ctor: Ident,
+        data_name: Ident,
+        span: Option<Span>,
+    },
+    DuplicateCaseArm {
+        def_name: Ident,
+        ctor: Ident,
+        span: Option<Span>,
+    },
+    EquationArityMismatch {
+        def_name: Ident,
+        expected: usize,
+        found: usize,
+        span: Option<Span>,
+    },
+    MixedPatterns {
+        def_name: Ident,
+        span: Option<Span>,
+    },
+    NonLinearPattern {
+        def_name: Ident,
+        var: Ident,
+        span: Option<Span>,
+    },
+    LiteralPatternWithoutEq {
+        def_name: Ident,
+        span: Option<Span>,
+    },
+    UnknownModule {
+        importer: Ident,
+        name: Ident,
+        span: Option<Span>,
+    },
+    DuplicateModule {
+        name: Ident,
+        span: Option<Span>,
+    },
+    UndefinedExport {
+        module: Ident,
+        name: Ident,
+        span: Option<Span>,
+    },
+    NotExported {
+        module: Ident,
+        importer: Ident,
+        name: Ident,
+        span: Option<Span>,
+    },
+    NotImported {
+        importer: Ident,
+        name: Ident,
+        span: Option<Span>,
+    },
+    AmbiguousName {
+        def_name: Ident,
+        name: Ident,
+        candidates: Vec<Ident>,
+        span: Option<Span>,
+    },
+    TypeMismatch {
+        def_name: Ident,
+        left: Type,
+        right: Type,
+        span: Option<Span>,
+    },
+    InfiniteType {
+        def_name: Ident,
+        var: Type,
+        ty: Type,
+        span: Option<Span>,
+    },
+    MissingPrimopType {
+        def_name: Ident,
+        span: Option<Span>,
+    },
+    UnloweredExpr {
+        def_name: Ident,
+        form: &'static str,
+        span: Option<Span>,
+    },
+    NormalizeOutOfFuel {
+        def_name: Option<Name>,
+        fuel: u64,
+    },
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    // several independent errors from one pass, in source order
+    Multiple(Vec<Error>),
+}
+
+impl Error {
+    // attach a location to an error raised deeper down, unless it already has one
+    pub fn with_span(mut self, new_span: Span) -> Self {
+        let span = match &mut self {
+            Error::TopLevelNameCollision { span, .. }
+            | Error::ScParamNameCollision { span, .. }
+            | Error::UndefinedIdent { span, .. }
+            | Error::UnexpectedLambda { span, .. }
+            | Error::UnknownPrimop { span, .. }
+            | Error::UnnamedPrimop { span, .. }
+            | Error::UnknownConstructor { span, .. }
+            | Error::MissingCaseArm { span, .. }
+            | Error::CaseArityMismatch { span, .. }
+            | Error::CaseArmMismatch { span, .. }
+            | Error::DuplicateCaseArm { span, .. }
+            | Error::EquationArityMismatch { span, .. }
+            | Error::MixedPatterns { span, .. }
+            | Error::NonLinearPattern { span, .. }
+            | Error::LiteralPatternWithoutEq { span, .. }
+            | Error::UnknownModule { span, .. }
+            | Error::DuplicateModule { span, .. }
+            | Error::UndefinedExport { span, .. }
+            | Error::NotExported { span, .. }
+            | Error::NotImported { span, .. }
+            | Error::AmbiguousName { span, .. }
+            | Error::TypeMismatch { span, .. }
+            | Error::InfiniteType { span, .. }
+            | Error::MissingPrimopType { span, .. }
+            | Error::UnloweredExpr { span, .. } => span,
+            _ => return self,
+        };
+        span.get_or_insert(new_span);
+        self
+    }
+
+    // `Ok` if there are no errors, the error itself if there is one
+    pub fn collect(errors: Vec<Error>) -> Result<()> {
+        let mut errors: Vec<Error> = errors.into_iter().flat_map(Error::into_errors).collect();
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.pop().unwrap()),
+            _ => Err(Error::Multiple(errors)),
+        }
+    }
+
+    pub fn into_errors(self) -> Vec<Error> {
+        match self {
+            Error::Multiple(errors) => errors,
+            error => vec![error],
+        }
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            Error::Multiple(errors) => errors.iter().flat_map(Error::diagnostics).collect(),
+            error => vec![error.to_diagnostic()],
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::new(Severity::Error, self.to_string());
+        match self {
+            Error::TopLevelNameCollision { span, previous, .. } => diagnostic
+                .with_label(*span, "redefined here")
+                .with_secondary(*previous, "previous definition here"),
+            Error::ScParamNameCollision { span, .. } => {
+                diagnostic.with_label(*span, "used as a parameter more than once")
+            }
+            Error::UndefinedIdent {
+                span, suggestion, ..
+            } => {
+                let diagnostic = diagnostic.with_label(*span, "not found");
+                match suggestion {
+                    Some(name) => diagnostic.with_note(format!("did you mean `{}`?", name)),
+                    None => diagnostic,
+                }
+            }
+            Error::UnexpectedLambda { span, .. } => diagnostic
+                .with_label(*span, "in this definition")
+                .with_note("lambda lifting must run before lambda elimination"),
+            Error::UnexpectedPrimApp { .. } => {
+                diagnostic.with_note("primop arguments must reduce to integers")
+            }
+            Error::UnknownEntry {
+                suggestion: Some(name),
+                ..
+            } => diagnostic.with_note(format!("did you mean `{}`?", name)),
+            Error::NoMatchingAlternative { .. } => diagnostic.with_note(
+                "the points-to analysis assumes that primops return integers, IO results and \
+                 constructors without fields, and that integers are never applied",
+            ),
+            Error::MemoryLimit { .. } => diagnostic.with_note(
+                "the term is nested too deeply; raise `Budget::memory_limit` to allow more",
+            ),
+            Error::UnknownPrimop { span, .. } => diagnostic
+                .with_label(*span, "declared here")
+                .with_note("declared with `#` but no primop was supplied to `attach_prim`"),
+            Error::UnnamedPrimop { span, .. } => diagnostic.with_label(*span, "in this definition"),
+            Error::Syntax { span, .. } => diagnostic.with_label(Some(*span), ""),
+            Error::UnknownConstructor { span, .. } => {
+                diagnostic.with_label(*span, "not a constructor of any data type")
+            }
+            Error::MissingCaseArm { ctors, span, .. } => {
+                let missing = ctors.iter().map(|ctor| format!("`{}`", ctor));
+                let missing = missing.collect::<Vec<_>>().join(", ");
+                diagnostic
+                    .with_label(*span, format!("{} not covered", missing))
+                    .with_note("add the missing arms or a `_ -> ..` arm")
+            }
+            Error::CaseArityMismatch { expected, span, .. } => diagnostic.with_label(
+                *span,
+                format!(
+                    "expected {} field{}",
+                    expected,
+                    if *expected == 1 { "" } else { "s" }
+                ),
+            ),
+            Error::CaseArmMismatch {
+                data_name, span, ..
+            } => diagnostic.with_label(*span, format!("not a constructor of `{}`", data_name)),
+            Error::DuplicateCaseArm { span, .. } => {
+                diagnostic.with_label(*span, "this constructor is already matched")
+            }
+            Error::EquationArityMismatch { expected, span, .. } => diagnostic.with_label(
+                *span,
+                format!("expected {} pattern{}", expected, if *expected == 1 { "" } else { "s" }),
+            ),
+            Error::MixedPatterns { span, .. } => diagnostic.with_label(
+                *span,
+                "integer and constructor patterns cannot match the same argument",
+            ),
+            Error::NonLinearPattern { var, span, .. } => diagnostic
+                .with_label(*span, format!("`{}` is bound more than once", var))
+                .with_note("compare the values explicitly, e.g. with `EQ`"),
+            Error::LiteralPatternWithoutEq { span, .. } => diagnostic
+                .with_label(*span, "matches an integer literal")
+                .with_note("declare `#EQ x y;` returning `λt f. t` or `λt f. f`, or import it"),
+            Error::UnknownModule { span, .. } => {
+                diagnostic.with_label(*span, "not among the linked modules")
+            }
+            Error::DuplicateModule { span, .. } => {
+                diagnostic.with_label(*span, "another module has the same name")
+            }
+            Error::UndefinedExport { span, .. } => {
+                diagnostic.with_label(*span, "in the export list of this module")
+            }
+            Error::NotExported { span, .. } => diagnostic.with_label(*span, "imported here"),
+            Error::NotImported { span, .. } => diagnostic
+                .with_label(*span, "used here")
+                .with_note("import its module, without hiding the name or leaving it out of a list"),
+            Error::AmbiguousName {
+                name, candidates, span, ..
+            } => {
+                let candidates = candidates.iter().map(|c| format!("`{}`", c));
+                diagnostic
+                    .with_label(*span, "used here")
+                    .with_note(format!(
+                        "`{}` could be any of {}",
+                        name,
+                        candidates.collect::<Vec<_>>().join(", ")
+                    ))
+                    .with_note("use a qualified name, or restrict the imports")
+            }
+            Error::TypeMismatch { span, .. } => diagnostic.with_label(*span, "in this expression"),
+            Error::InfiniteType { span, .. } => diagnostic
+                .with_label(*span, "in this expression")
+                .with_note("recursive types are disabled"),
+            Error::MissingPrimopType { span, .. } => diagnostic
+                .with_label(*span, "declared here")
+                .with_note("add a signature, e.g. `#ADD x y : Int -> Int -> Int;`"),
+            Error::UnloweredExpr { span, .. } => diagnostic
+                .with_label(*span, "in this expression")
+                .with_note("`Program::into_anon` lowers `case` and `letrec` before anonymizing"),
+            Error::NormalizeOutOfFuel { .. } => diagnostic
+                .with_note("the term may have no normal form; otherwise raise `Normalize::fuel`"),
+            _ => diagnostic,
+        }
+    }
+
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let diagnostics = self.diagnostics();
+        let rendered = diagnostics.iter().map(|d| d.render(file_name, source));
+        rendered.collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TopLevelNameCollision { name, .. } => {
+                write!(f, "the name `{}` is defined multiple times", name)
+            }
+            Error::ScParamNameCollision {
+                def_name,
+                param_name,
+                ..
+            } => write!(
+                f,
+                "parameter `{}` is bound more than once in definition `{}`",
+                param_name, def_name
+            ),
+            Error::UndefinedIdent {
+                def_name,
+                undefined_name,
+                ..
+            } => write!(
+                f,
+                "cannot find `{}` in definition `{}`",
+                undefined_name, def_name
+            ),
+            Error::UnexpectedLambda { def_name, .. } => {
+                write!(f, "unexpected lambda in definition `{}`", def_name)
+            }
+            Error::UnexpectedPrimApp { prim_name, arg } => {
+                write!(
+                    f,
+                    "primop `{}` received a non-primitive argument `{}`",
+                    prim_name, arg
+                )
+            }
+            Error::UnknownPrimop { def_name, .. } => {
+                write!(f, "no implementation for primop `{}`", def_name)
+            }
+            Error::PrimopFailure { def_name, arg } => {
+                write!(f, "primop `{}` failed on arguments {}", def_name, arg)
+            }
+            Error::UnnamedPrimop { def_no, .. } => {
+                write!(f, "unnamed definition ?{} has no body", def_no)
+            }
+            Error::UnknownEntry { name, .. } => {
+                write!(f, "there is no definition `{}` to run", name)
+            }
+            Error::NoMatchingAlternative { fn_name, node } => write!(
+                f,
+                "no case alternative in GRIN function `{}` matches `{}`",
+                fn_name, node
+            ),
+            Error::MemoryLimit { limit } => write!(
+                f,
+                "reduction needs more than {} pending entries on the work stack",
+                limit
+            ),
+            Error::Syntax { message, .. } => write!(f, "{}", message),
+            Error::UnknownConstructor { def_name, ctor, .. } => write!(
+                f,
+                "unknown constructor `{}` in definition `{}`",
+                ctor, def_name
+            ),
+            Error::MissingCaseArm {
+                def_name,
+                data_name,
+                ..
+            } => write!(
+                f,
+                "non-exhaustive case over `{}` in definition `{}`",
+                data_name, def_name
+            ),
+            Error::CaseArityMismatch {
+                def_name,
+                ctor,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "constructor `{}` has {} fields but the arm in definition `{}` binds {}",
+                ctor, expected, def_name, found
+            ),
+            Error::CaseArmMismatch {
+                def_name,
+                ctor,
+                data_name,
+                ..
+            } => write!(
+                f,
+                "constructor `{}` does not belong to `{}` in definition `{}`",
+                ctor, data_name, def_name
+            ),
+            Error::DuplicateCaseArm { def_name, ctor, .. } => write!(
+                f,
+                "constructor `{}` is matched more than once in definition `{}`",
+                ctor, def_name
+            ),
+            Error::EquationArityMismatch { def_name, expected, found, .. } => write!(
+                f,
+                "equation of `{}` has {} patterns, but the first one has {}",
+                def_name, found, expected
+            ),
+            Error::MixedPatterns { def_name, .. } => write!(
+                f,
+                "mixed integer and constructor patterns in definition `{}`",
+                def_name
+            ),
+            Error::NonLinearPattern { def_name, var, .. } => write!(
+                f,
+                "variable `{}` appears twice in a pattern of definition `{}`",
+                var, def_name
+            ),
+            Error::LiteralPatternWithoutEq { def_name, .. } => write!(
+                f,
+                "definition `{}` matches integers, but there is no primop `EQ x y`",
+                def_name
+            ),
+            Error::UnknownModule { importer, name, .. } => {
+                write!(f, "module `{}` imports unknown module `{}`", importer, name)
+            }
+            Error::DuplicateModule { name, .. } => {
+                write!(f, "the module `{}` is defined multiple times", name)
+            }
+            Error::UndefinedExport { module, name, .. } => {
+                write!(f, "module `{}` exports `{}`, which it does not define", module, name)
+            }
+            Error::NotExported {
+                module,
+                importer,
+                name,
+                ..
+            } => write!(
+                f,
+                "module `{}` imports `{}` from `{}`, which does not export it",
+                importer, name, module
+            ),
+            Error::NotImported { importer, name, .. } => {
+                write!(f, "`{}` is not imported into module `{}`", name, importer)
+            }
+            Error::AmbiguousName { def_name, name, .. } => {
+                write!(f, "`{}` is ambiguous in definition `{}`", name, def_name)
+            }
+            Error::TypeMismatch {
+                def_name,
+                left,
+                right,
+                ..
+            } => write!(
+                f,
+                "type mismatch in definition `{}`: `{}` conflicts with `{}`",
+                def_name, left, right
+            ),
+            Error::InfiniteType {
+                def_name, var, ty, ..
+            } => write!(
+                f,
+                "infinite type in definition `{}`: `{}` occurs in `{}`",
+                def_name, var, ty
+            ),
+            Error::MissingPrimopType { def_name, .. } => {
+                write!(f, "primop `{}` has no type signature", def_name)
+            }
+            Error::UnloweredExpr { def_name, form, .. } => write!(
+                f,
+                "`{}` in definition `{}` cannot be anonymized on its own",
+                form, def_name
+            ),
+            Error::NormalizeOutOfFuel {
+                def_name: Some(def_name),
+                fuel,
+            } => write!(f, "normalising `{}` took more than {} steps", def_name, fuel),
+            Error::NormalizeOutOfFuel { fuel, .. } => {
+                write!(f, "normalisation took more than {} steps", fuel)
+            }
+            Error::Io { path, error } => write!(f, "cannot read `{}`: {}", path.display(), error),
+            Error::Multiple(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum Warning {
+    NonExhaustiveMatch {
+        def_name: Ident,
+        missing: String,
+        span: Option<Span>,
+    },
+    RedundantEquation {
+        def_name: Ident,
+        span: Option<Span>,
+    },
+    UnusedDef {
+        def_name: Ident,
+        span: Option<Span>,
+    },
+    UnusedParam {
+        def_name: Ident,
+        param: Ident,
+        span: Option<Span>,
+    },
+    ShadowsTopLevel {
+        def_name: Ident,
+        name: Ident,
+        span: Option<Span>,
+    },
+}
+
+impl Warning {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::new(Severity::Warning, self.to_string());
+        match self {
+            Warning::NonExhaustiveMatch { missing, span, .. } => diagnostic
+                .with_label(*span, format!("`{}` not covered", missing))
+                .with_note("evaluating an unmatched call fails at runtime"),
+            Warning::RedundantEquation { span, .. } => {
+                diagnostic.with_label(*span, "earlier equations match every input of this one")
+            }
+            Warning::UnusedDef { span, .. } => {
+                diagnostic.with_label(*span, "not reachable from any entry point")
+            }
+            Warning::UnusedParam { span, .. } => diagnostic
+                .with_label(*span, "never used")
+                .with_note("prefix the name with `_` to silence this warning"),
+            Warning::ShadowsTopLevel { name, span, .. } => diagnostic.with_label(
+                *span,
+                format!("`{}` refers to the local variable in here", name),
+            ),
+        }
+    }
+
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        self.to_diagnostic().render(file_name, source)
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::NonExhaustiveMatch { def_name, .. } => {
+                write!(f, "equations of `{}` do not cover every input", def_name)
+            }
+            Warning::RedundantEquation { def_name, .. } => {
+                write!(f, "unreachable equation of `{}`", def_name)
+            }
+            Warning::UnusedDef { def_name, .. } => {
+                write!(f, "definition `{}` is never used", def_name)
+            }
+            Warning::UnusedParam {
+                def_name, param, ..
+            } => write!(f, "unused parameter `{}` in definition `{}`", param, def_name),
+            Warning::ShadowsTopLevel { def_name, name, .. } => write!(
+                f,
+                "local variable `{}` in definition `{}` shadows a top-level definition",
+                name, def_name
+            ),
+        }
     }
 }
 
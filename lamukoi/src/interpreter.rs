@@ -1,2 +1,355 @@
 pub mod tree_reducer;
 pub mod graph_reducer;
+pub mod gmachine;
+pub mod grin;
+pub mod tim;
+pub mod stg;
+pub mod debugger;
+pub mod profile;
+pub mod readback;
+
+use crate::diagnostic::suggest;
+use crate::error::*;
+use crate::structures::*;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    // the step budget ran out
+    OutOfFuel,
+    // the cancellation flag was set
+    Cancelled,
+    // an observer asked to pause
+    Paused,
+}
+
+// how a reducer passes arguments to the supercombinators it unfolds; the tree and graph reducers
+// take any of them (`with_strategy`, `set_strategy`), the other backends are call-by-need
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    // unevaluated, reduced again wherever they are used
+    ByName,
+    // unevaluated and shared, reduced at most once
+    #[default]
+    ByNeed,
+    // reduced to WHNF before the unfolding
+    ByValue,
+}
+
+impl std::fmt::Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Strategy::ByName => write!(f, "call-by-name"),
+            Strategy::ByNeed => write!(f, "call-by-need"),
+            Strategy::ByValue => write!(f, "call-by-value"),
+        }
+    }
+}
+
+// a reduction step, reported to an observer right after it happens
+#[derive(Debug)]
+pub enum Event<'e, V> {
+    // supercombinator `sc` unfolded with `args` into `term`
+    Unfold { sc: usize, args: &'e [V], term: &'e V },
+    // primop `sc` returned `result` for `args`
+    Prim { sc: usize, args: &'e [i64], result: Atom },
+    // an IO result passed `value` to its continuation, giving `term`
+    Io { value: i64, term: &'e V },
+}
+
+pub trait Observer<V> {
+    // breaking pauses the reduction after this step
+    fn observe(&mut self, event: Event<'_, V>) -> ControlFlow<()>;
+
+    // the reduction now waits on a part of a term headed by supercombinator `sc`, if it is
+    // headed by one; each `enter` is matched by a `leave`
+    fn enter(&mut self, _sc: Option<usize>) {}
+
+    fn leave(&mut self) {}
+
+    // a step allocated `nodes` new nodes
+    fn alloc(&mut self, _nodes: usize) {}
+}
+
+// observes nothing
+impl<V> Observer<V> for () {
+    fn observe(&mut self, _: Event<'_, V>) -> ControlFlow<()> {
+        ControlFlow::Continue(())
+    }
+}
+
+// limits on a reduction: a number of steps, a flag another thread may set to stop it, and the
+// most entries the work stack may hold (`Error::MemoryLimit` beyond that)
+#[derive(Debug, Clone, Default)]
+pub struct Budget {
+    pub fuel: Option<u64>,
+    pub cancel: Option<Arc<AtomicBool>>,
+    pub memory_limit: Option<usize>,
+}
+
+impl Budget {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    pub fn fuel(fuel: u64) -> Self {
+        Self {
+            fuel: Some(fuel),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    // why the reduction must stop before the next step, if it must
+    pub fn stop(&self) -> Option<Outcome> {
+        if let Some(cancel) = &self.cancel {
+            if cancel.load(Ordering::Relaxed) {
+                return Some(Outcome::Cancelled);
+            }
+        }
+        match self.fuel {
+            Some(0) => Some(Outcome::OutOfFuel),
+            _ => None,
+        }
+    }
+
+    // fails if a work stack of `len` entries is over the limit
+    pub fn check_memory(&self, len: usize) -> Result<()> {
+        match self.memory_limit {
+            Some(limit) if len > limit => Err(Error::MemoryLimit { limit }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn consume(&mut self) {
+        if let Some(fuel) = &mut self.fuel {
+            *fuel -= 1;
+        }
+    }
+}
+
+// an evaluator that owns a program with its primops attached
+// a `Value` stands for an expression of the program, evaluated in place by the reduce methods
+pub trait Backend<'a>: Sized {
+    type Value: Clone;
+
+    fn new(program: ScPrimProgram<'a>) -> Self;
+
+    fn program(&self) -> &ScPrimProgram<'a>;
+
+    // the value of supercombinator `sc`
+    fn sc(&mut self, sc: usize) -> Self::Value;
+
+    fn prim(&mut self, i: i64) -> Self::Value;
+
+    // `fun` applied to `args`, not yet reduced
+    fn apply(&mut self, fun: Self::Value, args: Vec<Self::Value>) -> Self::Value;
+
+    // reduce until done, until the budget runs out or until `observer` pauses; the value stays
+    // valid either way, and calling again resumes where it stopped
+    fn reduce_to_whnf_observed(
+        &mut self,
+        value: &mut Self::Value,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Self::Value>,
+    ) -> Result<Outcome>;
+
+    fn reduce_to_nf_observed(
+        &mut self,
+        value: &mut Self::Value,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Self::Value>,
+    ) -> Result<Outcome>;
+
+    // the head of a value in WHNF and its arguments, the first one first
+    fn spine(&self, value: &Self::Value) -> (Atom, Vec<Self::Value>);
+
+    fn reduce_to_whnf_with(
+        &mut self,
+        value: &mut Self::Value,
+        budget: &mut Budget,
+    ) -> Result<Outcome> {
+        self.reduce_to_whnf_observed(value, budget, &mut ())
+    }
+
+    fn reduce_to_nf_with(
+        &mut self,
+        value: &mut Self::Value,
+        budget: &mut Budget,
+    ) -> Result<Outcome> {
+        self.reduce_to_nf_observed(value, budget, &mut ())
+    }
+
+    fn reduce_to_whnf(&mut self, value: &mut Self::Value) -> Result<()> {
+        self.reduce_to_whnf_with(value, &mut Budget::unlimited())?;
+        Ok(())
+    }
+
+    fn reduce_to_nf(&mut self, value: &mut Self::Value) -> Result<()> {
+        self.reduce_to_nf_with(value, &mut Budget::unlimited())?;
+        Ok(())
+    }
+
+    // `primops` builds the primops from the `def_indexes` of the program
+    fn load(
+        program: ScProgram,
+        primops: impl FnOnce(&HashMap<&str, usize>) -> HashMap<&'static str, Primop<'a>>,
+    ) -> Result<Self> {
+        let mut primops = primops(&program.def_indexes());
+        Ok(Self::new(program.attach_prim(&mut primops)?))
+    }
+
+    // the supercombinator of the definition called `name`
+    fn def_index(&self, name: &str) -> Result<usize> {
+        let table = self.program().def_indexes();
+        match table.get(name) {
+            Some(&sc) => Ok(sc),
+            None => Err(Error::UnknownEntry {
+                name: name.to_string(),
+                suggestion: suggest(name, table.keys().copied()),
+            }),
+        }
+    }
+
+    // the value of the definition called `name`
+    fn entry(&mut self, name: &str) -> Result<Self::Value> {
+        let sc = self.def_index(name)?;
+        Ok(self.sc(sc))
+    }
+
+    fn run_to_whnf(&mut self, name: &str) -> Result<Self::Value> {
+        let mut value = self.entry(name)?;
+        self.reduce_to_whnf(&mut value)?;
+        Ok(value)
+    }
+
+    fn run_to_nf(&mut self, name: &str) -> Result<Self::Value> {
+        let mut value = self.entry(name)?;
+        self.reduce_to_nf(&mut value)?;
+        Ok(value)
+    }
+
+    fn atom_to_string(&self, atom: Atom) -> String {
+        match atom {
+            Atom::Sc(i) => self.program().defs[i].name.to_string(),
+            Atom::Prim(i) => i.to_string(),
+            Atom::IoRes(i) => format!("IORes#({})", i),
+            Atom::World => "World#".to_string(),
+        }
+    }
+
+    fn whnf_to_string(&self, value: &Self::Value) -> String {
+        let (head, args) = self.spine(value);
+        let body = " (..)".repeat(args.len());
+        format!("{}{}", self.atom_to_string(head), body)
+    }
+
+    // the whole term with definition names, for backends whose `spine` also shows the parts not
+    // yet in WHNF
+    fn term_to_string(&self, value: &Self::Value) -> String {
+        // an explicit stack, since terms may be deeper than the Rust stack allows
+        enum Task<V> {
+            Term(V, bool),
+            Text(&'static str),
+        }
+        let mut out = String::new();
+        let mut tasks = vec![Task::Term(value.clone(), false)];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Text(text) => out.push_str(text),
+                Task::Term(value, parens) => {
+                    let (head, args) = self.spine(&value);
+                    if parens && !args.is_empty() {
+                        out.push('(');
+                        tasks.push(Task::Text(")"));
+                    }
+                    out.push_str(&self.atom_to_string(head));
+                    for arg in args.into_iter().rev() {
+                        tasks.push(Task::Term(arg, true));
+                        tasks.push(Task::Text(" "));
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+// reduce the parts of a value in WHNF to NF, the last part of `pending` first, for backends whose
+// values are shared, so that reducing a part in place also reduces it in the whole value
+// the parts left stay in `pending` when the reduction stops, to resume with the part it stopped in
+pub(crate) fn reduce_parts_to_nf<'a, B: Backend<'a>>(
+    backend: &mut B,
+    pending: &mut Vec<B::Value>,
+    budget: &mut Budget,
+    observer: &mut dyn Observer<B::Value>,
+) -> Result<Outcome> {
+    while let Some(mut part) = pending.pop() {
+        let outcome = backend.reduce_to_whnf_observed(&mut part, budget, observer)?;
+        if outcome != Outcome::Done {
+            pending.push(part);
+            return Ok(outcome);
+        }
+        let (_, args) = backend.spine(&part);
+        pending.extend(args.into_iter().rev());
+    }
+    Ok(Outcome::Done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::gmachine::GMachine;
+    use crate::interpreter::graph_reducer::Graph;
+    use crate::interpreter::grin::Grin;
+    use crate::interpreter::readback::readback_named;
+    use crate::interpreter::stg::Stg;
+    use crate::interpreter::tim::Tim;
+    use crate::interpreter::tree_reducer::TreeReducer;
+    use crate::library::{compile_with_prelude, prelude_primops};
+
+    // `main` in NF, reduced `fuel` steps at a time
+    fn run_in_steps<'a, B: Backend<'a>>(sc: ScProgram, fuel: u64) -> String {
+        let mut backend = B::load(sc, prelude_primops).unwrap();
+        let mut value = backend.entry("main").unwrap();
+        loop {
+            let mut budget = Budget::fuel(fuel);
+            match backend.reduce_to_nf_with(&mut value, &mut budget).unwrap() {
+                Outcome::Done => break,
+                outcome => assert_eq!(outcome, Outcome::OutOfFuel),
+            }
+        }
+        readback_named(&backend, &value).unwrap().to_string()
+    }
+
+    #[test]
+    fn every_backend_resumes_where_it_stopped() {
+        let main = "
+            import Prelude;
+            fib n = if (LT n 2) n (ADD (fib (SUB n 1)) (fib (SUB n 2)));
+            main = Pair (fib 10) (map (λx. MUL x x) (range 1 3));
+        ";
+        let sc = compile_with_prelude(main);
+        let expected = "Prelude.Pair 55 \
+            (Prelude.Cons 1 (Prelude.Cons 4 (Prelude.Cons 9 Prelude.Nil)))";
+        assert_eq!(run_in_steps::<TreeReducer>(sc.clone(), 1), expected);
+        assert_eq!(run_in_steps::<Graph>(sc.clone(), 1), expected);
+        assert_eq!(run_in_steps::<GMachine>(sc.clone(), 1), expected);
+        assert_eq!(run_in_steps::<Tim>(sc.clone(), 1), expected);
+        assert_eq!(run_in_steps::<Stg>(sc.clone(), 1), expected);
+        assert_eq!(run_in_steps::<Grin>(sc, 1), expected);
+    }
+}
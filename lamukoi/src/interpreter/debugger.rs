@@ -0,0 +1,212 @@
+// interpret: a stepping debugger over any backend
+// step: reduce once; resume: reduce until a breakpoint, that is, until one of the chosen
+// definitions unfolds
+// both describe the step they stopped after as `redex -> result`, with definition names
+
+use crate::error::*;
+use crate::interpreter::{Backend, Budget, Event, Observer, Outcome};
+use crate::structures::*;
+use std::collections::HashSet;
+use std::marker::PhantomData;
+use std::ops::ControlFlow;
+
+pub struct Debugger<'a, B: Backend<'a>> {
+    backend: B,
+    value: B::Value,
+    nf: bool,
+    breakpoints: HashSet<usize>,
+    _program: PhantomData<ScPrimProgram<'a>>,
+}
+
+// an owned copy of the event a reduction paused after
+enum Record<V> {
+    Unfold {
+        sc: usize,
+        args: Vec<V>,
+        term: V,
+    },
+    Prim {
+        sc: usize,
+        args: Vec<i64>,
+        result: Atom,
+    },
+    Io {
+        value: i64,
+        term: V,
+    },
+}
+
+// pauses after every step, or only on the breakpoints if there are any
+struct Recorder<'b, V> {
+    breakpoints: Option<&'b HashSet<usize>>,
+    record: Option<Record<V>>,
+}
+
+impl<V: Clone> Observer<V> for Recorder<'_, V> {
+    fn observe(&mut self, event: Event<'_, V>) -> ControlFlow<()> {
+        if let Some(breakpoints) = self.breakpoints {
+            match &event {
+                Event::Unfold { sc, .. } if breakpoints.contains(sc) => {}
+                _ => return ControlFlow::Continue(()),
+            }
+        }
+        self.record = Some(match event {
+            Event::Unfold { sc, args, term } => Record::Unfold {
+                sc,
+                args: args.to_vec(),
+                term: term.clone(),
+            },
+            Event::Prim { sc, args, result } => Record::Prim {
+                sc,
+                args: args.to_vec(),
+                result,
+            },
+            Event::Io { value, term } => Record::Io {
+                value,
+                term: term.clone(),
+            },
+        });
+        ControlFlow::Break(())
+    }
+}
+
+impl<'a, B: Backend<'a>> Debugger<'a, B> {
+    // debug the definition called `name`, up to WHNF, or up to NF if `nf`
+    pub fn new(mut backend: B, name: &str, nf: bool) -> Result<Self> {
+        let value = backend.entry(name)?;
+        Ok(Self {
+            backend,
+            value,
+            nf,
+            breakpoints: HashSet::new(),
+            _program: PhantomData,
+        })
+    }
+
+    pub fn break_on(&mut self, name: &str) -> Result<()> {
+        let sc = self.backend.def_index(name)?;
+        self.breakpoints.insert(sc);
+        Ok(())
+    }
+
+    // whether there was a breakpoint on `name`
+    pub fn remove_breakpoint(&mut self, name: &str) -> bool {
+        match self.backend.def_index(name) {
+            Ok(sc) => self.breakpoints.remove(&sc),
+            Err(_) => false,
+        }
+    }
+
+    // `None` once the value is reduced
+    pub fn step(&mut self) -> Result<Option<String>> {
+        self.run(None)
+    }
+
+    // `None` once the value is reduced without reaching a breakpoint
+    pub fn resume(&mut self) -> Result<Option<String>> {
+        let breakpoints = std::mem::take(&mut self.breakpoints);
+        let result = self.run(Some(&breakpoints));
+        self.breakpoints = breakpoints;
+        result
+    }
+
+    fn run(&mut self, breakpoints: Option<&HashSet<usize>>) -> Result<Option<String>> {
+        let mut recorder = Recorder {
+            breakpoints,
+            record: None,
+        };
+        let mut budget = Budget::unlimited();
+        let outcome = if self.nf {
+            self.backend
+                .reduce_to_nf_observed(&mut self.value, &mut budget, &mut recorder)?
+        } else {
+            self.backend
+                .reduce_to_whnf_observed(&mut self.value, &mut budget, &mut recorder)?
+        };
+        match (outcome, recorder.record) {
+            (Outcome::Paused, Some(record)) => Ok(Some(self.record_to_string(record))),
+            _ => Ok(None),
+        }
+    }
+
+    // the current term, with definition names
+    pub fn term(&self) -> String {
+        self.backend.term_to_string(&self.value)
+    }
+
+    pub fn value(&self) -> &B::Value {
+        &self.value
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    fn record_to_string(&self, record: Record<B::Value>) -> String {
+        let name = |sc: usize| self.backend.program().defs[sc].name.to_string();
+        match record {
+            Record::Unfold { sc, args, term } => {
+                let mut redex = name(sc);
+                for arg in &args {
+                    redex.push(' ');
+                    redex.push_str(&self.arg_to_string(arg));
+                }
+                format!("{} -> {}", redex, self.backend.term_to_string(&term))
+            }
+            Record::Prim { sc, args, result } => {
+                let mut redex = name(sc);
+                for arg in args {
+                    redex.push_str(&format!(" {}", arg));
+                }
+                format!("{} -> {}", redex, self.backend.atom_to_string(result))
+            }
+            Record::Io { value, term } => format!(
+                "IORes#({}) (..) -> {}",
+                value,
+                self.backend.term_to_string(&term)
+            ),
+        }
+    }
+
+    fn arg_to_string(&self, arg: &B::Value) -> String {
+        let term = self.backend.term_to_string(arg);
+        if self.backend.spine(arg).1.is_empty() {
+            term
+        } else {
+            format!("({})", term)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tree_reducer::TreeReducer;
+    use crate::library::{compile_with_prelude, prelude_primops};
+
+    #[test]
+    fn steps_and_stops_on_breakpoints() {
+        let main = "
+            import Prelude;
+            double x = ADD x x;
+            main = sum (map double (Cons 1 (Cons 2 Nil)));
+        ";
+        let sc = compile_with_prelude(main);
+        let reducer = TreeReducer::load(sc, prelude_primops).unwrap();
+        let mut debugger = Debugger::new(reducer, "main", false).unwrap();
+        assert_eq!(debugger.term(), "main");
+        let step = debugger.step().unwrap().unwrap();
+        assert!(step.starts_with("main -> Prelude.sum (Prelude.map double "));
+        assert_eq!(debugger.term(), step["main -> ".len()..]);
+
+        debugger.break_on("double").unwrap();
+        assert!(debugger.break_on("triple").is_err());
+        let mut hits = vec![];
+        while let Some(hit) = debugger.resume().unwrap() {
+            hits.push(hit);
+        }
+        assert_eq!(hits, ["double 1 -> ADD 1 1", "double 2 -> ADD 2 2"]);
+        assert_eq!(debugger.term(), "6");
+        assert_eq!(debugger.step().unwrap(), None);
+    }
+}
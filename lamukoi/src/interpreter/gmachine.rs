@@ -0,0 +1,444 @@
+// interpret: G-machine
+// runs the G-code of compiler::gmachine over a heap of graph nodes; as in the graph reducer,
+// arguments are shared and each redex root is overwritten with an indirection to its result
+// state: the current code, a stack of heap addresses, and a dump of the code and stacks
+// suspended by Eval, whose size `Budget::memory_limit` bounds
+// a step of the budget is an instruction; a stopped reduction keeps its state, so reducing the
+// same root again resumes it, while reducing another one drops it and starts over from the heap
+// a supercombinator node with an arity of 0 (a CAF) is allocated afresh at each use, so it is
+// reduced again every time, like in the tree reducer
+// primops receive their arguments as integers; World arguments are skipped
+
+use crate::compiler::gmachine::{GCode, Instr};
+use crate::error::*;
+use crate::interpreter::{reduce_parts_to_nf, Backend, Budget, Event, Observer, Outcome};
+use crate::structures::*;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+pub type Addr = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node {
+    Num(i64),
+    App(Addr, Addr),
+    Global(usize),
+    Ind(Addr),
+    IoRes(i64),
+    World,
+}
+
+// the code being run, and the supercombinator whose body it is, if any
+#[derive(Clone)]
+struct Code {
+    code: Rc<[Instr]>,
+    pc: usize,
+    sc: Option<usize>,
+}
+
+struct Frame {
+    code: Code,
+    stack: Vec<Addr>,
+}
+
+enum Unwound {
+    // enter the code of this supercombinator
+    Enter(usize),
+    // an IO result passed its value to the continuation at this application node
+    Io(i64, Addr),
+    Whnf,
+}
+
+pub struct GMachine<'a> {
+    program: ScPrimProgram<'a>,
+    code: Vec<Rc<[Instr]>>,
+    // the shared node of each global of arity 1 or more
+    globals: Vec<Option<Addr>>,
+    heap: Vec<Node>,
+    stack: Vec<Addr>,
+    dump: Vec<Frame>,
+    steps: usize,
+    // the root and the code of a stopped reduction to WHNF
+    suspended: Option<(Addr, Code)>,
+    // the root and the parts left of a stopped reduction to NF
+    nf: Option<(Addr, Vec<Addr>)>,
+}
+
+impl<'a> GMachine<'a> {
+    pub fn alloc(&mut self, node: Node) -> Addr {
+        self.heap.push(node);
+        self.heap.len() - 1
+    }
+
+    pub fn app(&mut self, f: Addr, arg: Addr) -> Addr {
+        self.alloc(Node::App(f, arg))
+    }
+
+    fn atom(&mut self, atom: Atom) -> Addr {
+        match atom {
+            Atom::Sc(sc) => self.sc(sc),
+            Atom::Prim(i) => self.prim(i),
+            Atom::IoRes(i) => self.alloc(Node::IoRes(i)),
+            Atom::World => self.alloc(Node::World),
+        }
+    }
+
+    // number of heap nodes allocated so far
+    pub fn heap_len(&self) -> usize {
+        self.heap.len()
+    }
+
+    // number of instructions executed so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn resolve(&self, mut addr: Addr) -> Addr {
+        while let Node::Ind(target) = self.heap[addr] {
+            addr = target;
+        }
+        addr
+    }
+
+    pub fn node(&self, addr: Addr) -> Node {
+        self.heap[self.resolve(addr)]
+    }
+
+    fn run(
+        &mut self,
+        code: &mut Code,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Addr>,
+    ) -> Result<Outcome> {
+        while let Some(&instr) = code.code.get(code.pc) {
+            if let Some(outcome) = budget.stop() {
+                return Ok(outcome);
+            }
+            budget.consume();
+            self.steps += 1;
+            code.pc += 1;
+            let len = self.heap.len();
+            let mut flow = ControlFlow::Continue(());
+            match instr {
+                Instr::Unwind => match self.unwind() {
+                    Unwound::Enter(sc) => {
+                        let body = matches!(self.program.defs[sc].body, ScBody::Body(_));
+                        *code = Code {
+                            code: self.code[sc].clone(),
+                            pc: 0,
+                            sc: body.then_some(sc),
+                        };
+                    }
+                    Unwound::Io(value, app) => {
+                        // unwind the rewritten application next
+                        code.pc -= 1;
+                        observer.alloc(self.heap.len() - len);
+                        flow = observer.observe(Event::Io { value, term: &app });
+                    }
+                    Unwound::Whnf => {
+                        // WHNF: return to the suspended evaluation, if any
+                        let Some(frame) = self.dump.pop() else {
+                            break;
+                        };
+                        let whnf = self.stack[0];
+                        self.stack = frame.stack;
+                        self.stack.push(whnf);
+                        *code = frame.code;
+                    }
+                },
+                Instr::PushGlobal(sc) => {
+                    let addr = self.sc(sc);
+                    self.stack.push(addr);
+                }
+                Instr::PushInt(i) => {
+                    let addr = self.prim(i);
+                    self.stack.push(addr);
+                }
+                Instr::Push(n) => {
+                    let addr = self.stack[self.stack.len() - 1 - n];
+                    self.stack.push(addr);
+                }
+                Instr::MkAp => {
+                    let f = self.stack.pop().unwrap();
+                    let arg = self.stack.pop().unwrap();
+                    let addr = self.app(f, arg);
+                    self.stack.push(addr);
+                }
+                Instr::Update(n) => {
+                    let addr = self.stack.pop().unwrap();
+                    let redex = self.stack[self.stack.len() - 1 - n];
+                    self.heap[redex] = Node::Ind(addr);
+                    if let Some(sc) = code.sc {
+                        // the arguments are right above the redex, the first one on top
+                        let args: Vec<_> = self.stack[self.stack.len() - n..]
+                            .iter()
+                            .rev()
+                            .copied()
+                            .collect();
+                        flow = observer.observe(Event::Unfold {
+                            sc,
+                            args: &args,
+                            term: &redex,
+                        });
+                    }
+                }
+                Instr::Pop(n) => {
+                    self.stack.truncate(self.stack.len() - n);
+                }
+                Instr::Eval => {
+                    budget.check_memory(self.dump.len() + 1)?;
+                    let addr = self.stack.pop().unwrap();
+                    let stack = std::mem::replace(&mut self.stack, vec![addr]);
+                    let frame = Code {
+                        code: Rc::new([Instr::Unwind]),
+                        pc: 0,
+                        sc: None,
+                    };
+                    let code = std::mem::replace(code, frame);
+                    self.dump.push(Frame { code, stack });
+                }
+                Instr::Prim(p) => {
+                    let addr;
+                    (addr, flow) = self.call_prim(p, observer)?;
+                    self.stack.push(addr);
+                }
+            }
+            if self.heap.len() > len && !matches!(instr, Instr::Unwind) {
+                observer.alloc(self.heap.len() - len);
+            }
+            if flow.is_break() {
+                return Ok(Outcome::Paused);
+            }
+        }
+        Ok(Outcome::Done)
+    }
+
+    // unwinds the spine on the stack up to the next step
+    fn unwind(&mut self) -> Unwound {
+        loop {
+            let top = *self.stack.last().unwrap();
+            match self.heap[top] {
+                Node::App(f, _) => self.stack.push(f),
+                Node::Ind(addr) => *self.stack.last_mut().unwrap() = addr,
+                Node::Global(sc) => {
+                    let params = self.program.defs[sc].params;
+                    if self.stack.len() - 1 < params {
+                        return Unwound::Whnf;
+                    }
+                    if params > 0 {
+                        // replace the application nodes with their arguments, keeping the root
+                        self.stack.pop();
+                        let len = self.stack.len();
+                        let args: Vec<_> = (0..params)
+                            .map(|k| match self.heap[self.stack[len - 1 - k]] {
+                                Node::App(_, arg) => arg,
+                                _ => unreachable!(),
+                            })
+                            .collect();
+                        self.stack.truncate(len - params + 1);
+                        self.stack.extend(args.into_iter().rev());
+                    }
+                    return Unwound::Enter(sc);
+                }
+                Node::IoRes(i) if self.stack.len() > 1 => {
+                    // IoRes i f -> f i World
+                    self.stack.pop();
+                    let app = *self.stack.last().unwrap();
+                    let Node::App(_, f) = self.heap[app] else {
+                        unreachable!()
+                    };
+                    let prim = self.prim(i);
+                    let world = self.alloc(Node::World);
+                    let f = self.app(f, prim);
+                    self.heap[app] = Node::App(f, world);
+                    return Unwound::Io(i, app);
+                }
+                Node::Num(_) | Node::IoRes(_) | Node::World => return Unwound::Whnf,
+            }
+        }
+    }
+
+    fn call_prim(
+        &mut self,
+        p: usize,
+        observer: &mut dyn Observer<Addr>,
+    ) -> Result<(Addr, ControlFlow<()>)> {
+        let params = self.program.defs[p].params;
+        let mut prim_arg = vec![];
+        for _ in 0..params {
+            let arg = self.stack.pop().unwrap();
+            match self.node(arg) {
+                Node::Num(i) => prim_arg.push(i),
+                // ignore World
+                Node::World => {}
+                _ => {
+                    let prim_name = self.program.defs[p].name.to_string();
+                    let arg = self.whnf_to_string(&arg);
+                    return Err(Error::UnexpectedPrimApp { prim_name, arg });
+                }
+            }
+        }
+        let ScBody::Prim(prim) = &mut self.program.defs[p].body else {
+            unreachable!()
+        };
+        let Some(result) = prim(&prim_arg) else {
+            return Err(Error::PrimopFailure {
+                def_name: self.program.defs[p].name.to_string(),
+                arg: format!("{:?}", prim_arg),
+            });
+        };
+        let flow = observer.observe(Event::Prim {
+            sc: p,
+            args: &prim_arg,
+            result,
+        });
+        Ok((self.atom(result), flow))
+    }
+}
+
+impl<'a> Backend<'a> for GMachine<'a> {
+    type Value = Addr;
+
+    fn new(program: ScPrimProgram<'a>) -> Self {
+        let GCode { globals } = program.compile_gmachine();
+        let code = globals
+            .into_iter()
+            .map(|global| global.code.into())
+            .collect();
+        let globals = vec![None; program.defs.len()];
+        Self {
+            program,
+            code,
+            globals,
+            heap: vec![],
+            stack: vec![],
+            dump: vec![],
+            steps: 0,
+            suspended: None,
+            nf: None,
+        }
+    }
+
+    fn program(&self) -> &ScPrimProgram<'a> {
+        &self.program
+    }
+
+    fn sc(&mut self, sc: usize) -> Addr {
+        if self.program.defs[sc].params == 0 {
+            return self.alloc(Node::Global(sc));
+        }
+        match self.globals[sc] {
+            Some(addr) => addr,
+            None => {
+                let addr = self.alloc(Node::Global(sc));
+                self.globals[sc] = Some(addr);
+                addr
+            }
+        }
+    }
+
+    fn prim(&mut self, i: i64) -> Addr {
+        self.alloc(Node::Num(i))
+    }
+
+    fn apply(&mut self, fun: Addr, args: Vec<Addr>) -> Addr {
+        args.into_iter().fold(fun, |f, arg| self.app(f, arg))
+    }
+
+    fn reduce_to_whnf_observed(
+        &mut self,
+        value: &mut Addr,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Addr>,
+    ) -> Result<Outcome> {
+        let mut code = match self.suspended.take() {
+            Some((root, code)) if root == *value => code,
+            _ => {
+                self.stack = vec![*value];
+                self.dump.clear();
+                Code {
+                    code: Rc::new([Instr::Unwind]),
+                    pc: 0,
+                    sc: None,
+                }
+            }
+        };
+        let outcome = self.run(&mut code, budget, observer)?;
+        if outcome != Outcome::Done {
+            self.suspended = Some((*value, code));
+        }
+        Ok(outcome)
+    }
+
+    fn reduce_to_nf_observed(
+        &mut self,
+        value: &mut Addr,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Addr>,
+    ) -> Result<Outcome> {
+        let mut pending = match self.nf.take() {
+            Some((root, pending)) if root == *value => pending,
+            _ => vec![*value],
+        };
+        let outcome = reduce_parts_to_nf(self, &mut pending, budget, observer)?;
+        if outcome != Outcome::Done {
+            self.nf = Some((*value, pending));
+        }
+        Ok(outcome)
+    }
+
+    // the head of the spine and its arguments, outermost last
+    fn spine(&self, addr: &Addr) -> (Atom, Vec<Addr>) {
+        let mut args = vec![];
+        let mut addr = self.resolve(*addr);
+        loop {
+            let head = match self.heap[addr] {
+                Node::App(f, arg) => {
+                    args.push(arg);
+                    addr = self.resolve(f);
+                    continue;
+                }
+                Node::Num(i) => Atom::Prim(i),
+                Node::Global(sc) => Atom::Sc(sc),
+                Node::IoRes(i) => Atom::IoRes(i),
+                Node::World => Atom::World,
+                Node::Ind(_) => unreachable!(),
+            };
+            args.reverse();
+            return (head, args);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tree_reducer;
+    use crate::library::{compile_with_prelude, prelude_primops};
+
+    #[test]
+    fn agrees_with_the_tree_reducer() {
+        let main = "
+            import Prelude;
+            fib n = if (LT n 2) n (ADD (fib (SUB n 1)) (fib (SUB n 2)));
+            primes = sieve (range 2 30);
+            sieve (Cons p xs) = Cons p (sieve (filter (λx. not (EQ (MOD x p) 0)) xs));
+            sieve Nil = Nil;
+            main = ADD (fib 15) (sum primes);
+        ";
+        let sc = compile_with_prelude(main);
+        let table = sc.def_indexes();
+        let main = table["main"];
+        let mut primops = prelude_primops(&table);
+        let mut tree_primops = prelude_primops(&table);
+        let mut tree = sc.clone().attach_prim(&mut tree_primops).unwrap();
+        let mut node = tree_reducer::Node::from_sc(main);
+        tree.reduce_to_whnf(&mut node).unwrap();
+
+        let mut machine = GMachine::new(sc.attach_prim(&mut primops).unwrap());
+        let mut root = machine.sc(main);
+        machine.reduce_to_whnf(&mut root).unwrap();
+        assert_eq!(machine.node(root), Node::Num(610 + 129));
+        assert_eq!(tree.whnf_to_string(&node), "739");
+    }
+}
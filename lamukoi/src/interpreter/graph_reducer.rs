@@ -1,8 +1,482 @@
-// use crate::structures::*;
-// use slotmap::SlotMap;
-
-// interpret: stack, heap, sc, prim
-// inputs:
-// SC defs (lambda lifting applied)
-// entrypoint (ident)
-// prim (a callback that takes ID and a forcing iterator, and forces and calculates as necessary)
+// interpret: graph reduction
+// the expression is a graph of application nodes in a heap; a supercombinator body is
+// instantiated with pointers to its arguments, so an argument used twice is shared, and
+// the root of each reduced redex is overwritten with its result (or an indirection to it),
+// so every redex is reduced at most once
+// primops are strict (forces the arguments), others are lazy, as in the tree reducer
+// strategy: as in the tree reducer, call-by-need is the default; call-by-name instantiates a
+// fresh copy of the argument for each use instead of a pointer, and call-by-value reduces the
+// arguments to WHNF before the unfolding, like the arguments of a primop
+// reduce_to_whnf: unwind the spine and reduce its head until it is not a redex; an argument to
+// force goes on an explicit stack of roots, whose size `Budget::memory_limit` bounds
+// reduce_to_nf: also reduce every argument on the spine
+// every step is written to the heap as it happens, so a stopped reduction resumes from the heap
+
+use crate::error::*;
+use crate::interpreter::{
+    reduce_parts_to_nf, Backend, Budget, Event, Observer, Outcome, Strategy,
+};
+use crate::structures::*;
+use slotmap::{new_key_type, SlotMap};
+use std::ops::ControlFlow;
+
+new_key_type! {
+    pub struct NodeId;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node {
+    Atom(Atom),
+    App(NodeId, NodeId),
+    // the node has been reduced to the target node
+    Ind(NodeId),
+}
+
+pub struct Graph<'a> {
+    program: ScPrimProgram<'a>,
+    strategy: Strategy,
+    heap: SlotMap<NodeId, Node>,
+    // the root and the parts left of a stopped reduction to NF
+    nf: Option<(NodeId, Vec<NodeId>)>,
+}
+
+enum Step {
+    Reduced(ControlFlow<()>),
+    // reduce this argument of a primop, or of an unfolding by value, to WHNF first
+    Force(NodeId),
+    Whnf,
+}
+
+impl<'a> Graph<'a> {
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    pub fn alloc(&mut self, node: Node) -> NodeId {
+        self.heap.insert(node)
+    }
+
+    pub fn app(&mut self, f: NodeId, arg: NodeId) -> NodeId {
+        self.alloc(Node::App(f, arg))
+    }
+
+    // number of live nodes in the heap
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    // the node at the end of the indirection chain starting at `id`
+    pub fn resolve(&self, mut id: NodeId) -> NodeId {
+        while let Node::Ind(target) = self.heap[id] {
+            id = target;
+        }
+        id
+    }
+
+    pub fn node(&self, id: NodeId) -> Node {
+        self.heap[self.resolve(id)]
+    }
+
+    // a fresh copy of the graph at `id`, so that reducing the copy updates nothing else
+    fn copy(&mut self, id: NodeId) -> NodeId {
+        enum Task {
+            Copy(NodeId),
+            App,
+        }
+        let mut tasks = vec![Task::Copy(id)];
+        let mut copies = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Copy(id) => match self.node(id) {
+                    Node::App(f, arg) => tasks.extend([Task::App, Task::Copy(arg), Task::Copy(f)]),
+                    node => copies.push(self.alloc(node)),
+                },
+                Task::App => {
+                    let arg = copies.pop().unwrap();
+                    let f = copies.pop().unwrap();
+                    copies.push(self.app(f, arg));
+                }
+            }
+        }
+        copies.pop().unwrap()
+    }
+
+    // the argument itself, or a copy of it by name
+    fn arg(&mut self, args: &[NodeId], i: usize) -> NodeId {
+        match self.strategy {
+            Strategy::ByName => self.copy(args[i]),
+            Strategy::ByNeed | Strategy::ByValue => args[i],
+        }
+    }
+
+    fn instantiate(&mut self, expr: &ScExpr, args: &[NodeId]) -> NodeId {
+        match expr {
+            ScExpr::ArgId(i) => self.arg(args, *i),
+            _ => {
+                let node = self.instantiate_node(expr, args);
+                self.alloc(node)
+            }
+        }
+    }
+
+    // an argument instantiates to itself, which is not a fresh node
+    fn instantiate_node(&mut self, expr: &ScExpr, args: &[NodeId]) -> Node {
+        match expr {
+            ScExpr::DefId(i) => Node::Atom(Atom::Sc(*i)),
+            ScExpr::ArgId(i) => Node::Ind(self.arg(args, *i)),
+            ScExpr::Prim(i) => Node::Atom(Atom::Prim(*i)),
+            ScExpr::App(e1, e2) => {
+                let f = self.instantiate(e1, args);
+                let arg = self.instantiate(e2, args);
+                Node::App(f, arg)
+            }
+        }
+    }
+
+    fn step(&mut self, root: NodeId, observer: &mut dyn Observer<NodeId>) -> Result<Step> {
+        // application nodes of the spine, innermost first
+        let mut spine = vec![];
+        let mut id = self.resolve(root);
+        let head = loop {
+            match self.heap[id] {
+                Node::Atom(atom) => break atom,
+                Node::App(f, _) => {
+                    spine.push(id);
+                    id = self.resolve(f);
+                }
+                Node::Ind(_) => unreachable!(),
+            }
+        };
+        spine.reverse();
+        let arg = |graph: &Self, app: NodeId| match graph.heap[app] {
+            Node::App(_, arg) => arg,
+            _ => unreachable!(),
+        };
+        let len = self.heap.len();
+        match head {
+            Atom::Sc(i) => {
+                let params = self.program.defs[i].params;
+                if spine.len() < params {
+                    // unable to reduce head
+                    return Ok(Step::Whnf);
+                }
+                let args: Vec<_> = spine[..params].iter().map(|&app| arg(self, app)).collect();
+                // a 0-arity supercombinator (CAF) is updated at its head node
+                let redex = if params == 0 { id } else { spine[params - 1] };
+                match &self.program.defs[i].body {
+                    ScBody::Body(body) => {
+                        if self.strategy == Strategy::ByValue {
+                            // by value, whnf the arguments first
+                            if let Some(&arg) = args.iter().find(|&&arg| !self.is_whnf(arg)) {
+                                return Ok(Step::Force(arg));
+                            }
+                        }
+                        let body = body.clone();
+                        let node = self.instantiate_node(&body, &args);
+                        self.heap[redex] = node;
+                        observer.alloc(self.heap.len() - len);
+                        let event = Event::Unfold {
+                            sc: i,
+                            args: &args,
+                            term: &redex,
+                        };
+                        Ok(Step::Reduced(observer.observe(event)))
+                    }
+                    ScBody::Prim(_) => {
+                        let mut prim_arg = vec![];
+                        for &arg in &args {
+                            match self.node(arg) {
+                                Node::Atom(Atom::Prim(i)) => prim_arg.push(i),
+                                // ignore World
+                                Node::Atom(Atom::World) => {}
+                                _ if !self.is_whnf(arg) => return Ok(Step::Force(arg)),
+                                _ => {
+                                    let prim_name = self.program.defs[i].name.to_string();
+                                    let arg = self.whnf_to_string(&arg);
+                                    return Err(Error::UnexpectedPrimApp { prim_name, arg });
+                                }
+                            }
+                        }
+                        let ScBody::Prim(prim) = &mut self.program.defs[i].body else {
+                            unreachable!()
+                        };
+                        let Some(result) = prim(&prim_arg) else {
+                            return Err(Error::PrimopFailure {
+                                def_name: self.program.defs[i].name.to_string(),
+                                arg: format!("{:?}", prim_arg),
+                            });
+                        };
+                        self.heap[redex] = Node::Atom(result);
+                        let event = Event::Prim {
+                            sc: i,
+                            args: &prim_arg,
+                            result,
+                        };
+                        Ok(Step::Reduced(observer.observe(event)))
+                    }
+                }
+            }
+            Atom::IoRes(i) => {
+                // IoRes i f -> f i World
+                let Some(&app) = spine.first() else {
+                    return Ok(Step::Whnf);
+                };
+                let f = arg(self, app);
+                let prim = self.prim(i);
+                let world = self.alloc(Node::Atom(Atom::World));
+                let f = self.app(f, prim);
+                self.heap[app] = Node::App(f, world);
+                observer.alloc(3);
+                let event = Event::Io {
+                    value: i,
+                    term: &app,
+                };
+                Ok(Step::Reduced(observer.observe(event)))
+            }
+            Atom::Prim(_) | Atom::World => Ok(Step::Whnf),
+        }
+    }
+
+    fn is_whnf(&self, id: NodeId) -> bool {
+        match self.spine(&id) {
+            (Atom::Sc(i), args) => args.len() < self.program.defs[i].params,
+            (Atom::IoRes(_), args) => args.is_empty(),
+            (Atom::Prim(_) | Atom::World, _) => true,
+        }
+    }
+
+    fn run(
+        &mut self,
+        roots: &mut Vec<NodeId>,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<NodeId>,
+    ) -> Result<Outcome> {
+        while let Some(&root) = roots.last() {
+            if let Some(outcome) = budget.stop() {
+                return Ok(outcome);
+            }
+            match self.step(root, observer)? {
+                Step::Reduced(flow) => {
+                    budget.consume();
+                    if flow.is_break() {
+                        return Ok(Outcome::Paused);
+                    }
+                }
+                Step::Force(arg) => {
+                    budget.check_memory(roots.len() + 1)?;
+                    observer.enter(match self.spine(&arg).0 {
+                        Atom::Sc(i) => Some(i),
+                        _ => None,
+                    });
+                    roots.push(arg);
+                }
+                Step::Whnf => {
+                    roots.pop();
+                    if !roots.is_empty() {
+                        observer.leave();
+                    }
+                }
+            }
+        }
+        Ok(Outcome::Done)
+    }
+}
+
+impl<'a> Backend<'a> for Graph<'a> {
+    type Value = NodeId;
+
+    fn new(program: ScPrimProgram<'a>) -> Self {
+        Self {
+            program,
+            strategy: Strategy::default(),
+            heap: SlotMap::with_key(),
+            nf: None,
+        }
+    }
+
+    fn program(&self) -> &ScPrimProgram<'a> {
+        &self.program
+    }
+
+    fn sc(&mut self, sc: usize) -> NodeId {
+        self.alloc(Node::Atom(Atom::Sc(sc)))
+    }
+
+    fn prim(&mut self, i: i64) -> NodeId {
+        self.alloc(Node::Atom(Atom::Prim(i)))
+    }
+
+    fn apply(&mut self, fun: NodeId, args: Vec<NodeId>) -> NodeId {
+        args.into_iter().fold(fun, |f, arg| self.app(f, arg))
+    }
+
+    fn reduce_to_whnf_observed(
+        &mut self,
+        value: &mut NodeId,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<NodeId>,
+    ) -> Result<Outcome> {
+        let mut roots = vec![*value];
+        let result = self.run(&mut roots, budget, observer);
+        // match the `enter` of every primop argument left
+        for _ in 1..roots.len() {
+            observer.leave();
+        }
+        result
+    }
+
+    fn reduce_to_nf_observed(
+        &mut self,
+        value: &mut NodeId,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<NodeId>,
+    ) -> Result<Outcome> {
+        let mut pending = match self.nf.take() {
+            Some((root, pending)) if root == *value => pending,
+            _ => vec![*value],
+        };
+        let outcome = reduce_parts_to_nf(self, &mut pending, budget, observer)?;
+        if outcome != Outcome::Done {
+            self.nf = Some((*value, pending));
+        }
+        Ok(outcome)
+    }
+
+    // the head of the spine and its arguments, outermost last
+    fn spine(&self, id: &NodeId) -> (Atom, Vec<NodeId>) {
+        let mut args = vec![];
+        let mut id = self.resolve(*id);
+        loop {
+            match self.heap[id] {
+                Node::Atom(atom) => {
+                    args.reverse();
+                    return (atom, args);
+                }
+                Node::App(f, arg) => {
+                    args.push(arg);
+                    id = self.resolve(f);
+                }
+                Node::Ind(_) => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::profile::Profile;
+    use crate::library::{compile_with_prelude, prelude_primops};
+    use crate::parser::parse_program;
+    use std::cell::Cell;
+    use std::collections::HashMap;
+
+    #[test]
+    fn shared_redexes_are_reduced_once() {
+        let src = "
+            #TICK x;
+            #ADD x y;
+            double x = ADD x x;
+            main = double (TICK 20);
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let main = sc.def_indexes()["main"];
+        let ticks = Cell::new(0);
+        let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+        primops.insert(
+            "TICK",
+            Box::new(|a: &[i64]| {
+                ticks.set(ticks.get() + 1);
+                Some(Atom::Prim(a[0] + 1))
+            }),
+        );
+        primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+        let mut graph = Graph::new(sc.attach_prim(&mut primops).unwrap());
+        let mut root = graph.sc(main);
+        graph.reduce_to_whnf(&mut root).unwrap();
+        assert_eq!(graph.node(root), Node::Atom(Atom::Prim(42)));
+        assert_eq!(ticks.get(), 1);
+    }
+
+    #[test]
+    fn reduces_prelude_programs() {
+        let main = "
+            import Prelude;
+            main = take 3 (map (λx. MUL x x) (iterate (ADD 1) 1));
+            total = sum (append main (range 4 10));
+        ";
+        let sc = compile_with_prelude(main);
+        let total = sc.def_indexes()["total"];
+        let mut graph = Graph::load(sc, prelude_primops).unwrap();
+        let mut root = graph.sc(total);
+        graph.reduce_to_whnf(&mut root).unwrap();
+        assert_eq!(graph.node(root), Node::Atom(Atom::Prim(1 + 4 + 9 + 49)));
+        let root = graph.run_to_nf("main").unwrap();
+        assert_eq!(graph.whnf_to_string(&root), "Prelude.Cons (..) (..)");
+    }
+
+    #[test]
+    fn follows_the_chosen_strategy() {
+        let src = "
+            #TICK x;
+            #ADD x y;
+            double x = ADD x x;
+            const x y = x;
+            loop = loop;
+            main = double (TICK 1);
+            lazy = const 1 loop;
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let mut graph = Graph::load(sc, |_| {
+            let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+            primops.insert("TICK", Box::new(|a: &[i64]| Some(Atom::Prim(a[0]))));
+            primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+            primops
+        })
+        .unwrap();
+        let tick = graph.program().def_indexes()["TICK"];
+        let mut ticks = vec![];
+        let mut lazy = vec![];
+        for strategy in [Strategy::ByName, Strategy::ByNeed, Strategy::ByValue] {
+            graph.set_strategy(strategy);
+            let mut value = graph.entry("main").unwrap();
+            let mut profile = Profile::new();
+            graph
+                .reduce_to_whnf_observed(&mut value, &mut Budget::unlimited(), &mut profile)
+                .unwrap();
+            assert_eq!(graph.whnf_to_string(&value), "2");
+            ticks.push(profile.prim_calls[&tick]);
+            let mut value = graph.entry("lazy").unwrap();
+            lazy.push(graph.reduce_to_whnf_with(&mut value, &mut Budget::fuel(1000)).unwrap());
+        }
+        assert_eq!(ticks, [2, 1, 1]);
+        assert_eq!(lazy, [Outcome::Done, Outcome::Done, Outcome::OutOfFuel]);
+    }
+}
@@ -0,0 +1,675 @@
+// interpret: GRIN
+// runs the first-order program of compiler::grin, by default after inlining eval and apply
+// variables hold pointers, basic integers or nodes; the heap holds nodes, updated in place
+// function bodies are flattened into instructions; calls, and the forcing and applying the runtime
+// does itself, push continuations on an explicit stack, whose size `Budget::memory_limit` bounds
+// a call in return position replaces its caller
+// results are read back through the runtime's own eval and apply, which force a thunk in place
+// the same way the program's `eval` does; the analysis assumes that the runtime may do so with
+// any stored thunk, so an entry is a supercombinator of arity 0 or an unapplied function
+// a case without a matching alternative means the analysis did not expect the node, which
+// can only happen when its assumptions on primops fail or when an integer is applied
+// a step of the budget is a simple expression; a stopped reduction keeps its stack, and reducing
+// the same value again resumes it; observers see the primop calls and may pause after one
+
+use crate::compiler::grin::*;
+use crate::error::*;
+use crate::interpreter::{reduce_parts_to_nf, Backend, Budget, Event, Observer, Outcome};
+use crate::structures::*;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+pub type Addr = usize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    pub tag: Tag,
+    pub fields: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Ptr(Addr),
+    Int(i64),
+    Node(Node),
+    Unit,
+}
+
+pub struct Grin<'a> {
+    program: ScPrimProgram<'a>,
+    grin: Rc<GrinProgram>,
+    // the instructions of each function
+    code: Rc<Vec<Vec<Instr>>>,
+    heap: Vec<Node>,
+    steps: usize,
+    // the continuations of the reduction under way, innermost last
+    stack: Vec<Frame>,
+    // a value returned to the innermost continuation, not yet delivered
+    returned: Option<Value>,
+    // the root of a stopped reduction to WHNF, which `stack` continues
+    suspended: Option<Addr>,
+    // the root and the parts left of a stopped reduction to NF
+    nf: Option<(Addr, Vec<Addr>)>,
+}
+
+// a function body with its binds and cases flattened
+enum Instr {
+    // run the simple expression and bind its value, or return it if there is no variable
+    Simple(SExpr, Option<usize>),
+    // the scrutinee, the tag, fields and start of each alternative, and the default
+    Case(usize, Vec<(Tag, Vec<usize>, usize)>, Option<(usize, usize)>),
+    Jump(usize),
+}
+
+enum Frame {
+    // a function waiting at `pc`, a call or primop instruction, for its value
+    Code {
+        f: usize,
+        pc: usize,
+        env: Vec<Value>,
+    },
+    // the runtime's eval: overwrite the forced thunk with its value
+    Update(Addr),
+    // the runtime's apply: apply the node returned to this argument
+    Apply(Value),
+}
+
+// why a reduction ends before its result
+enum Halt {
+    Error(Error),
+    Stop(Outcome),
+}
+
+impl From<Error> for Halt {
+    fn from(error: Error) -> Self {
+        Halt::Error(error)
+    }
+}
+
+type Run<T> = std::result::Result<T, Halt>;
+
+// what a reduction consults besides the program
+struct Context<'c> {
+    budget: &'c mut Budget,
+    observer: &'c mut dyn Observer<Addr>,
+}
+
+fn compile(expr: &GExpr, dest: Option<usize>, code: &mut Vec<Instr>) {
+    match expr {
+        GExpr::Simple(sexpr) => code.push(Instr::Simple(sexpr.clone(), dest)),
+        GExpr::Bind(v, e1, e2) => {
+            compile(e1, Some(*v), code);
+            compile(e2, dest, code);
+        }
+        GExpr::Case(n, alts, default) => {
+            let case = code.len();
+            code.push(Instr::Jump(case));
+            // a bound case continues after the alternative it took
+            let mut jumps = vec![];
+            let mut branch = |body: &GExpr, code: &mut Vec<Instr>| {
+                let start = code.len();
+                compile(body, dest, code);
+                if dest.is_some() {
+                    jumps.push(code.len());
+                    code.push(Instr::Jump(0));
+                }
+                start
+            };
+            let alts = alts
+                .iter()
+                .map(|alt| (alt.tag, alt.fields.clone(), branch(&alt.body, code)));
+            let alts = alts.collect();
+            let default = default.as_ref().map(|(v, body)| (*v, branch(body, code)));
+            let join = code.len();
+            for jump in jumps {
+                code[jump] = Instr::Jump(join);
+            }
+            code[case] = Instr::Case(*n, alts, default);
+        }
+    }
+}
+
+impl<'a> Grin<'a> {
+    // run `grin`, the lowering of `program` with or without further transformations
+    pub fn with_grin(program: ScPrimProgram<'a>, grin: GrinProgram) -> Self {
+        let code = grin.fns.iter().map(|function| {
+            let mut code = vec![];
+            compile(&function.body, None, &mut code);
+            code
+        });
+        Self {
+            program,
+            code: Rc::new(code.collect()),
+            grin: Rc::new(grin),
+            heap: vec![],
+            steps: 0,
+            stack: vec![],
+            returned: None,
+            suspended: None,
+            nf: None,
+        }
+    }
+
+    pub fn grin(&self) -> &GrinProgram {
+        &self.grin
+    }
+
+    pub fn alloc(&mut self, node: Node) -> Addr {
+        self.heap.push(node);
+        self.heap.len() - 1
+    }
+
+    pub fn node(&self, addr: Addr) -> &Node {
+        &self.heap[addr]
+    }
+
+    // number of heap nodes allocated so far
+    pub fn heap_len(&self) -> usize {
+        self.heap.len()
+    }
+
+    // number of simple expressions run so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn push(&mut self, frame: Frame, ctx: &mut Context) -> Run<()> {
+        ctx.budget.check_memory(self.stack.len() + 1)?;
+        self.stack.push(frame);
+        Ok(())
+    }
+
+    // return the WHNF of the node at `addr`, updating it if it is a thunk
+    fn eval(&mut self, mut addr: Addr, ctx: &mut Context) -> Run<()> {
+        loop {
+            let node = self.heap[addr].clone();
+            match node.tag {
+                Tag::F(f) => {
+                    self.push(Frame::Update(addr), ctx)?;
+                    return self.call(f, node.fields, ctx);
+                }
+                Tag::Ap => {
+                    self.push(Frame::Update(addr), ctx)?;
+                    self.push(Frame::Apply(node.fields[1].clone()), ctx)?;
+                    addr = ptr(&node.fields[0]);
+                }
+                _ => {
+                    self.returned = Some(Value::Node(node));
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, h: Node, x: Value, ctx: &mut Context) -> Run<()> {
+        let mut args = h.fields.clone();
+        args.push(x.clone());
+        match h.tag {
+            Tag::P(f, 1) => self.call(f, args, ctx),
+            Tag::P(f, k) => {
+                self.returned = Some(Value::Node(Node {
+                    tag: Tag::P(f, k - 1),
+                    fields: args,
+                }));
+                Ok(())
+            }
+            Tag::IoRes => {
+                // IoRes i f -> f i World
+                let i = self.alloc(Node {
+                    tag: Tag::Int,
+                    fields: h.fields,
+                });
+                let world = self.alloc(Node {
+                    tag: Tag::World,
+                    fields: vec![],
+                });
+                self.push(Frame::Apply(Value::Ptr(world)), ctx)?;
+                self.push(Frame::Apply(Value::Ptr(i)), ctx)?;
+                self.eval(ptr(&x), ctx)
+            }
+            _ => Err(Halt::Error(Error::NoMatchingAlternative {
+                fn_name: "apply".to_string(),
+                node: self.node_to_string(&h),
+            })),
+        }
+    }
+
+    fn call(&mut self, f: usize, args: Vec<Value>, ctx: &mut Context) -> Run<()> {
+        let mut env = args;
+        env.resize(self.grin.fns[f].vars, Value::Unit);
+        self.push(Frame::Code { f, pc: 0, env }, ctx)
+    }
+
+    // run until the stack is empty
+    fn run(&mut self, ctx: &mut Context) -> Run<()> {
+        let code = self.code.clone();
+        loop {
+            if let Some(value) = self.returned.take() {
+                match self.stack.pop() {
+                    None => return Ok(()),
+                    Some(Frame::Code { f, pc, mut env }) => {
+                        let Instr::Simple(_, Some(v)) = code[f][pc] else {
+                            unreachable!()
+                        };
+                        env[v] = value;
+                        self.stack.push(Frame::Code { f, pc: pc + 1, env });
+                    }
+                    Some(Frame::Update(addr)) => {
+                        self.heap[addr] = into_node(value.clone());
+                        self.returned = Some(value);
+                    }
+                    Some(Frame::Apply(x)) => self.apply(into_node(value), x, ctx)?,
+                }
+                continue;
+            }
+            let Some(Frame::Code { f, mut pc, mut env }) = self.stack.pop() else {
+                unreachable!()
+            };
+            match &code[f][pc] {
+                Instr::Jump(target) => pc = *target,
+                Instr::Case(n, alts, default) => {
+                    let Value::Node(node) = &env[*n] else {
+                        unreachable!()
+                    };
+                    if let Some((_, vars, start)) = alts.iter().find(|alt| alt.0 == node.tag) {
+                        let fields = node.fields.clone();
+                        for (v, field) in vars.iter().zip(fields) {
+                            env[*v] = field;
+                        }
+                        pc = *start;
+                    } else if let Some((v, start)) = default {
+                        env[*v] = env[*n].clone();
+                        pc = *start;
+                    } else {
+                        return Err(Halt::Error(Error::NoMatchingAlternative {
+                            fn_name: self.grin.fns[f].name.clone(),
+                            node: self.node_to_string(node),
+                        }));
+                    }
+                }
+                Instr::Simple(sexpr, dest) => {
+                    if let Some(outcome) = ctx.budget.stop() {
+                        self.stack.push(Frame::Code { f, pc, env });
+                        return Err(Halt::Stop(outcome));
+                    }
+                    ctx.budget.consume();
+                    self.steps += 1;
+                    let operands = |fields: &[Operand]| {
+                        let fields = fields.iter().map(|field| match field {
+                            Operand::Var(v) => env[*v].clone(),
+                            Operand::Lit(i) => Value::Int(*i),
+                        });
+                        fields.collect::<Vec<_>>()
+                    };
+                    let value = match sexpr {
+                        SExpr::Unit(tag, fields) => Value::Node(Node {
+                            tag: *tag,
+                            fields: operands(fields),
+                        }),
+                        SExpr::Return(v) => env[*v].clone(),
+                        SExpr::Store(_, tag, fields) => {
+                            let fields = operands(fields);
+                            Value::Ptr(self.alloc(Node { tag: *tag, fields }))
+                        }
+                        SExpr::Fetch(p) => Value::Node(self.heap[ptr(&env[*p])].clone()),
+                        SExpr::Update(p, n) => {
+                            self.heap[ptr(&env[*p])] = into_node(env[*n].clone());
+                            Value::Unit
+                        }
+                        SExpr::Call(g, args) => {
+                            let args = args.iter().map(|&v| env[v].clone()).collect();
+                            // a call in return position replaces its caller
+                            if dest.is_some() {
+                                self.stack.push(Frame::Code { f, pc, env });
+                            }
+                            self.call(*g, args, ctx)?;
+                            continue;
+                        }
+                        SExpr::Prim(p, args) => {
+                            let args = args.iter().map(|&v| into_node(env[v].clone())).collect();
+                            if dest.is_some() {
+                                self.stack.push(Frame::Code { f, pc, env });
+                            }
+                            if self.call_prim(*p, args, ctx)?.is_break() {
+                                return Err(Halt::Stop(Outcome::Paused));
+                            }
+                            continue;
+                        }
+                    };
+                    match dest {
+                        Some(v) => {
+                            env[*v] = value;
+                            pc += 1;
+                        }
+                        None => {
+                            self.returned = Some(value);
+                            continue;
+                        }
+                    }
+                }
+            }
+            self.stack.push(Frame::Code { f, pc, env });
+        }
+    }
+
+    fn call_prim(&mut self, p: usize, args: Vec<Node>, ctx: &mut Context) -> Run<ControlFlow<()>> {
+        let mut prim_arg = vec![];
+        for arg in args {
+            match (arg.tag, &arg.fields[..]) {
+                (Tag::Int, [Value::Int(i)]) => prim_arg.push(*i),
+                // ignore World
+                (Tag::World, _) => {}
+                _ => {
+                    let prim_name = self.program.defs[p].name.to_string();
+                    let arg = self.node_to_string(&arg);
+                    return Err(Halt::Error(Error::UnexpectedPrimApp { prim_name, arg }));
+                }
+            }
+        }
+        let ScBody::Prim(prim) = &mut self.program.defs[p].body else {
+            unreachable!()
+        };
+        let Some(result) = prim(&prim_arg) else {
+            return Err(Halt::Error(Error::PrimopFailure {
+                def_name: self.program.defs[p].name.to_string(),
+                arg: format!("{:?}", prim_arg),
+            }));
+        };
+        let flow = ctx.observer.observe(Event::Prim {
+            sc: p,
+            args: &prim_arg,
+            result,
+        });
+        let (tag, fields) = match result {
+            Atom::Prim(i) => (Tag::Int, vec![Value::Int(i)]),
+            Atom::IoRes(i) => (Tag::IoRes, vec![Value::Int(i)]),
+            Atom::World => (Tag::World, vec![]),
+            Atom::Sc(sc) => match self.grin.fns[sc].params {
+                0 => {
+                    self.call(sc, vec![], ctx)?;
+                    return Ok(flow);
+                }
+                arity => (Tag::P(sc, arity), vec![]),
+            },
+        };
+        self.returned = Some(Value::Node(Node { tag, fields }));
+        Ok(flow)
+    }
+
+    fn node_to_string(&self, node: &Node) -> String {
+        match (node.tag, &node.fields[..]) {
+            (Tag::Int, [Value::Int(i)]) => i.to_string(),
+            (Tag::IoRes, [Value::Int(i)]) => format!("IORes#({})", i),
+            (Tag::World, _) => "World#".to_string(),
+            (Tag::P(f, _), fields) => {
+                let name = self.program.defs[f].name.to_string();
+                format!("{}{}", name, " (..)".repeat(fields.len()))
+            }
+            (tag, _) => self.grin.tag_to_string(tag),
+        }
+    }
+}
+
+impl<'a> Backend<'a> for Grin<'a> {
+    type Value = Addr;
+
+    fn new(program: ScPrimProgram<'a>) -> Self {
+        let grin = program.compile_grin().inline_eval_apply();
+        Self::with_grin(program, grin)
+    }
+
+    fn program(&self) -> &ScPrimProgram<'a> {
+        &self.program
+    }
+
+    fn sc(&mut self, sc: usize) -> Addr {
+        let arity = self.grin.fns[sc].params;
+        let tag = if arity == 0 {
+            Tag::F(sc)
+        } else {
+            Tag::P(sc, arity)
+        };
+        let fields = vec![];
+        self.alloc(Node { tag, fields })
+    }
+
+    fn prim(&mut self, i: i64) -> Addr {
+        self.alloc(Node {
+            tag: Tag::Int,
+            fields: vec![Value::Int(i)],
+        })
+    }
+
+    fn apply(&mut self, fun: Addr, args: Vec<Addr>) -> Addr {
+        args.into_iter().fold(fun, |f, arg| {
+            self.alloc(Node {
+                tag: Tag::Ap,
+                fields: vec![Value::Ptr(f), Value::Ptr(arg)],
+            })
+        })
+    }
+
+    fn reduce_to_whnf_observed(
+        &mut self,
+        value: &mut Addr,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Addr>,
+    ) -> Result<Outcome> {
+        let mut ctx = Context { budget, observer };
+        let result = if self.suspended.take() == Some(*value) {
+            self.run(&mut ctx)
+        } else {
+            self.stack.clear();
+            self.returned = None;
+            self.eval(*value, &mut ctx)
+                .and_then(|()| self.run(&mut ctx))
+        };
+        match result {
+            Ok(()) => Ok(Outcome::Done),
+            Err(Halt::Stop(outcome)) => {
+                self.suspended = Some(*value);
+                Ok(outcome)
+            }
+            Err(Halt::Error(error)) => {
+                self.stack.clear();
+                self.returned = None;
+                Err(error)
+            }
+        }
+    }
+
+    fn reduce_to_nf_observed(
+        &mut self,
+        value: &mut Addr,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Addr>,
+    ) -> Result<Outcome> {
+        let mut pending = match self.nf.take() {
+            Some((root, pending)) if root == *value => pending,
+            _ => vec![*value],
+        };
+        let outcome = reduce_parts_to_nf(self, &mut pending, budget, observer)?;
+        if outcome != Outcome::Done {
+            self.nf = Some((*value, pending));
+        }
+        Ok(outcome)
+    }
+
+    fn spine(&self, addr: &Addr) -> (Atom, Vec<Addr>) {
+        let node = &self.heap[*addr];
+        match (node.tag, &node.fields[..]) {
+            (Tag::Int, [Value::Int(i)]) => (Atom::Prim(*i), vec![]),
+            (Tag::IoRes, [Value::Int(i)]) => (Atom::IoRes(*i), vec![]),
+            (Tag::World, _) => (Atom::World, vec![]),
+            // a suspended call shows as the call itself
+            (Tag::F(f) | Tag::P(f, _), fields) => (Atom::Sc(f), fields.iter().map(ptr).collect()),
+            (Tag::Ap, _) => {
+                // the arguments of a chain of applications, outermost first
+                let mut args = vec![];
+                let mut addr = *addr;
+                while let (Tag::Ap, [f, x]) = (self.heap[addr].tag, &self.heap[addr].fields[..]) {
+                    args.push(ptr(x));
+                    addr = ptr(f);
+                }
+                let (head, mut inner) = self.spine(&addr);
+                inner.extend(args.into_iter().rev());
+                (head, inner)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn ptr(value: &Value) -> Addr {
+    match value {
+        Value::Ptr(addr) => *addr,
+        _ => unreachable!(),
+    }
+}
+
+fn into_node(value: Value) -> Node {
+    match value {
+        Value::Node(node) => node,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{compile_with_prelude, prelude_primops};
+    use crate::parser::parse_program;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    #[test]
+    fn inlined_program_agrees_with_generic_one() {
+        let main = "
+            import Prelude;
+            fib n = if (LT n 2) n (ADD (fib (SUB n 1)) (fib (SUB n 2)));
+            twice f x = f (f x);
+            main = ADD (fib 12) (twice (λx. MUL x x) (sum (filter (λx. LT x 3) (range 1 10))));
+            list = map (λx. MUL x 2) (take 3 (repeat 4));
+        ";
+        let sc = compile_with_prelude(main);
+        let table = sc.def_indexes();
+        let mut steps = vec![];
+        for inline in [false, true] {
+            let mut primops = prelude_primops(&table);
+            let program = sc.clone().attach_prim(&mut primops).unwrap();
+            let grin = program.compile_grin();
+            let grin = if inline {
+                grin.inline_eval_apply()
+            } else {
+                grin
+            };
+            let mut machine = Grin::with_grin(program, grin);
+            let root = machine.run_to_whnf("main").unwrap();
+            assert_eq!(machine.whnf_to_string(&root), (144 + 81).to_string());
+            let root = machine.run_to_nf("list").unwrap();
+            assert_eq!(machine.whnf_to_string(&root), "Prelude.Cons (..) (..)");
+            let (_, args) = machine.spine(&root);
+            assert_eq!(machine.whnf_to_string(&args[0]), "8");
+            steps.push(machine.steps());
+        }
+        assert!(steps[1] < steps[0]);
+    }
+
+    #[test]
+    fn runs_io_actions() {
+        // READ yields `IoRes c`, which passes c and World to the continuation
+        let src = "
+            #READ;
+            #WRITE c w;
+            #ADD x y;
+            main = READ (λc w. WRITE (ADD c c) w (λ_ w. WRITE c w (λ_ w. w)));
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let main = sc.def_indexes()["main"];
+        let input = RefCell::new(vec![3]);
+        let output = RefCell::new(vec![]);
+        let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+        primops.insert(
+            "READ",
+            Box::new(|_: &[i64]| Some(Atom::IoRes(input.borrow_mut().pop()?))),
+        );
+        primops.insert(
+            "WRITE",
+            Box::new(|a: &[i64]| {
+                output.borrow_mut().push(a[0]);
+                Some(Atom::IoRes(0))
+            }),
+        );
+        primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+        let mut machine = Grin::new(sc.attach_prim(&mut primops).unwrap());
+        let mut root = machine.sc(main);
+        machine.reduce_to_whnf(&mut root).unwrap();
+        assert_eq!(machine.node(root).tag, Tag::World);
+        assert_eq!(*output.borrow(), [6, 3]);
+    }
+
+    #[test]
+    fn resumes_deep_calls_without_running_primops_again() {
+        let src = "
+            #LT x y;
+            #ADD x y;
+            #SUB x y;
+            true x y = x;
+            false x y = y;
+            sum n = LT n 1 0 (ADD n (sum (SUB n 1)));
+            main = sum 50000;
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let adds = Cell::new(0);
+        let load = || {
+            let table = sc.def_indexes();
+            let (t, f) = (table["true"], table["false"]);
+            let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+            primops.insert(
+                "LT",
+                Box::new(move |a: &[i64]| Some(Atom::Sc(if a[0] < a[1] { t } else { f }))),
+            );
+            primops.insert(
+                "ADD",
+                Box::new(|a: &[i64]| {
+                    adds.set(adds.get() + 1);
+                    Some(Atom::Prim(a[0] + a[1]))
+                }),
+            );
+            primops.insert("SUB", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] - a[1]))));
+            Grin::new(sc.clone().attach_prim(&mut primops).unwrap())
+        };
+        let mut machine = load();
+        let mut root = machine.entry("main").unwrap();
+        let mut budget = Budget::unlimited().with_memory_limit(1000);
+        let error = machine
+            .reduce_to_whnf_with(&mut root, &mut budget)
+            .unwrap_err();
+        assert!(matches!(error, Error::MemoryLimit { limit: 1000 }));
+
+        adds.set(0);
+        let mut machine = load();
+        let mut root = machine.entry("main").unwrap();
+        while machine
+            .reduce_to_whnf_with(&mut root, &mut Budget::fuel(1000))
+            .unwrap()
+            != Outcome::Done
+        {}
+        assert_eq!(machine.whnf_to_string(&root), "1250025000");
+        assert_eq!(adds.get(), 50000);
+    }
+}
@@ -0,0 +1,150 @@
+// interpret: profiling, as an observer of any backend
+// counts the unfoldings of each supercombinator, the calls of each primop, the allocated nodes,
+// the IO steps and the peak depth of the work stack
+// folded: the unfoldings and calls under each stack of waiting definitions, one `a;b;c count`
+// line per stack as flamegraph tools read them; a definition already on the stack is not
+// repeated, so that recursion, direct or not, folds into its outermost call
+
+use crate::interpreter::{Event, Observer};
+use crate::structures::*;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::ControlFlow;
+
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub unfolds: HashMap<usize, u64>,
+    pub prim_calls: HashMap<usize, u64>,
+    pub nodes: u64,
+    pub io_steps: u64,
+    pub peak_depth: usize,
+    // whether each `enter` pushed to `path`
+    entered: Vec<bool>,
+    // the definitions waiting on the current term, outermost first
+    path: Vec<usize>,
+    folded: HashMap<Vec<usize>, u64>,
+}
+
+impl<V> Observer<V> for Profile {
+    fn observe(&mut self, event: Event<'_, V>) -> ControlFlow<()> {
+        let sc = match event {
+            Event::Unfold { sc, .. } => {
+                *self.unfolds.entry(sc).or_default() += 1;
+                sc
+            }
+            Event::Prim { sc, .. } => {
+                *self.prim_calls.entry(sc).or_default() += 1;
+                sc
+            }
+            Event::Io { .. } => {
+                self.io_steps += 1;
+                return ControlFlow::Continue(());
+            }
+        };
+        let mut stack = self.path.clone();
+        if !stack.contains(&sc) {
+            stack.push(sc);
+        }
+        *self.folded.entry(stack).or_default() += 1;
+        ControlFlow::Continue(())
+    }
+
+    fn enter(&mut self, sc: Option<usize>) {
+        let push = sc.is_some_and(|sc| !self.path.contains(&sc));
+        if let (true, Some(sc)) = (push, sc) {
+            self.path.push(sc);
+        }
+        self.entered.push(push);
+        self.peak_depth = self.peak_depth.max(self.entered.len());
+    }
+
+    fn leave(&mut self) {
+        if self.entered.pop() == Some(true) {
+            self.path.pop();
+        }
+    }
+
+    fn alloc(&mut self, nodes: usize) {
+        self.nodes += nodes as u64;
+    }
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the unfoldings and calls of each definition, the most first, then the totals
+    pub fn report(&self, program: &ScPrimProgram) -> String {
+        let mut rows = self
+            .unfolds
+            .iter()
+            .map(|(&sc, &count)| (count, program.defs[sc].name.to_string()))
+            .chain(
+                self.prim_calls
+                    .iter()
+                    .map(|(&sc, &count)| (count, format!("{} (primop)", program.defs[sc].name))),
+            )
+            .collect::<Vec<_>>();
+        rows.sort_by(|(c1, n1), (c2, n2)| c2.cmp(c1).then(n1.cmp(n2)));
+        let mut out = String::new();
+        writeln!(out, "{:>10}  definition", "steps").unwrap();
+        for (count, name) in rows {
+            writeln!(out, "{:>10}  {}", count, name).unwrap();
+        }
+        writeln!(out, "nodes allocated: {}", self.nodes).unwrap();
+        writeln!(out, "IO steps: {}", self.io_steps).unwrap();
+        writeln!(out, "peak depth: {}", self.peak_depth).unwrap();
+        out
+    }
+
+    pub fn folded(&self, program: &ScPrimProgram) -> String {
+        let mut lines = self
+            .folded
+            .iter()
+            .map(|(stack, count)| {
+                let names = stack
+                    .iter()
+                    .map(|&sc| program.defs[sc].name.to_string())
+                    .collect::<Vec<_>>();
+                format!("{} {}\n", names.join(";"), count)
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tree_reducer::TreeReducer;
+    use crate::interpreter::{Backend, Budget};
+    use crate::library::{compile_with_prelude, prelude_primops};
+
+    #[test]
+    fn counts_steps_per_definition() {
+        let main = "
+            import Prelude;
+            double x = ADD x x;
+            main = sum (map double (Cons 1 (Cons 2 Nil)));
+        ";
+        let sc = compile_with_prelude(main);
+        let mut reducer = TreeReducer::load(sc, prelude_primops).unwrap();
+        let mut value = reducer.entry("main").unwrap();
+        let mut profile = Profile::new();
+        reducer
+            .reduce_to_whnf_observed(&mut value, &mut Budget::unlimited(), &mut profile)
+            .unwrap();
+        assert_eq!(reducer.whnf_to_string(&value), "6");
+        let table = reducer.program().def_indexes();
+        assert_eq!(profile.unfolds[&table["double"]], 2);
+        assert_eq!(profile.prim_calls[&table["ADD"]], 4);
+        assert_eq!(profile.io_steps, 0);
+        let report = profile.report(reducer.program());
+        assert_eq!(report.lines().nth(1).unwrap().trim(), "4  ADD (primop)");
+        assert!(profile
+            .folded(reducer.program())
+            .contains("\nADD;double 2\n"));
+    }
+}
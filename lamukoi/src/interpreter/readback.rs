@@ -0,0 +1,258 @@
+// interpret: read reduced values back as expressions
+// readback: a value in NF as an `ScExpr`, and with definition names as an `Expr`
+// both are built on an explicit stack, so that long results such as long lists read back safely
+// decode: a value of a known shape as an int, a bool or a list, found by applying it to probes
+// and reducing; probes are integers no program is expected to use
+// Church and Scott booleans select the first of two arguments for true, Scott lists take the
+// `Nil` case first, like the prelude's `data List = Nil | Cons x xs`
+
+use crate::error::*;
+use crate::interpreter::Backend;
+use crate::structures::*;
+use std::fmt::Display;
+
+const PROBE_NIL: i64 = i64::MIN;
+const PROBE_CONS: i64 = i64::MIN + 1;
+const PROBE_SUCC: i64 = i64::MIN + 2;
+
+impl ScPrimProgram<'_> {
+    // `expr` with definitions referred to by name
+    pub fn named_expr(&self, expr: &ScExpr) -> Expr {
+        // an explicit stack, since results may be deeper than the Rust stack allows
+        enum Task<'e> {
+            Expr(&'e ScExpr),
+            App,
+        }
+        let mut tasks = vec![Task::Expr(expr)];
+        let mut exprs = vec![];
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Expr(ScExpr::DefId(i)) => {
+                    exprs.push(Expr::Id(self.defs[*i].name.to_string()))
+                }
+                Task::Expr(ScExpr::ArgId(i)) => exprs.push(Expr::Id(format!("x{}", i))),
+                Task::Expr(ScExpr::Prim(i)) => exprs.push(Expr::Prim(*i)),
+                Task::Expr(ScExpr::App(e1, e2)) => {
+                    tasks.push(Task::App);
+                    tasks.push(Task::Expr(e2));
+                    tasks.push(Task::Expr(e1));
+                }
+                Task::App => {
+                    let e2 = exprs.pop().unwrap();
+                    let e1 = exprs.pop().unwrap();
+                    exprs.push(Expr::App(Box::new(e1), Box::new(e2)));
+                }
+            }
+        }
+        exprs.pop().unwrap()
+    }
+}
+
+// `value`, which must be in NF, as an expression; `None` if it holds an IO result or World
+pub fn readback<'a, B: Backend<'a>>(backend: &B, value: &B::Value) -> Option<ScExpr> {
+    // an explicit stack, as in `Backend::term_to_string`
+    enum Task<V> {
+        Value(V),
+        // apply the expression below the top to the top one
+        App,
+    }
+    let mut tasks = vec![Task::Value(value.clone())];
+    let mut exprs = vec![];
+    while let Some(task) = tasks.pop() {
+        match task {
+            Task::Value(value) => {
+                let (head, args) = backend.spine(&value);
+                exprs.push(match head {
+                    Atom::Sc(i) => ScExpr::DefId(i),
+                    Atom::Prim(i) => ScExpr::Prim(i),
+                    Atom::IoRes(_) | Atom::World => return None,
+                });
+                for arg in args.into_iter().rev() {
+                    tasks.push(Task::App);
+                    tasks.push(Task::Value(arg));
+                }
+            }
+            Task::App => {
+                let arg = exprs.pop().unwrap();
+                let fun = exprs.pop().unwrap();
+                exprs.push(ScExpr::App(Box::new(fun), Box::new(arg)));
+            }
+        }
+    }
+    exprs.pop()
+}
+
+pub fn readback_named<'a, B: Backend<'a>>(backend: &B, value: &B::Value) -> Option<Expr> {
+    Some(backend.program().named_expr(&readback(backend, value)?))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    Int,
+    // a Church or Scott boolean
+    Bool,
+    Church,
+    // a Scott list of elements of the given shape
+    List(Box<Shape>),
+    // any value in NF, read back with `readback_named`
+    Term,
+}
+
+#[derive(Debug, Clone)]
+pub enum Decoded {
+    Int(i64),
+    Bool(bool),
+    List(Vec<Decoded>),
+    Term(Expr),
+}
+
+impl Display for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Decoded::Int(i) => write!(f, "{}", i),
+            Decoded::Bool(b) => write!(f, "{}", b),
+            Decoded::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Decoded::Term(expr) => write!(f, "{}", expr),
+        }
+    }
+}
+
+// reduce `value` as far as `shape` needs and decode it; `None` if it does not have the shape
+pub fn decode<'a, B: Backend<'a>>(
+    backend: &mut B,
+    value: B::Value,
+    shape: &Shape,
+) -> Result<Option<Decoded>> {
+    match shape {
+        Shape::Int => Ok(decode_int(backend, value)?.map(Decoded::Int)),
+        Shape::Bool => {
+            let (t, f) = (backend.prim(1), backend.prim(0));
+            let value = backend.apply(value, vec![t, f]);
+            Ok(match decode_int(backend, value)? {
+                Some(1) => Some(Decoded::Bool(true)),
+                Some(0) => Some(Decoded::Bool(false)),
+                _ => None,
+            })
+        }
+        Shape::Church => {
+            let (succ, zero) = (backend.prim(PROBE_SUCC), backend.prim(0));
+            let mut value = backend.apply(value, vec![succ, zero]);
+            let mut n = 0;
+            loop {
+                backend.reduce_to_whnf(&mut value)?;
+                match backend.spine(&value) {
+                    (Atom::Prim(0), args) if args.is_empty() => return Ok(Some(Decoded::Int(n))),
+                    (Atom::Prim(PROBE_SUCC), mut args) if args.len() == 1 => {
+                        value = args.pop().unwrap();
+                        n += 1;
+                    }
+                    _ => return Ok(None),
+                }
+            }
+        }
+        Shape::List(item) => {
+            let mut items = vec![];
+            let mut value = value;
+            loop {
+                let (nil, cons) = (backend.prim(PROBE_NIL), backend.prim(PROBE_CONS));
+                let mut cell = backend.apply(value, vec![nil, cons]);
+                backend.reduce_to_whnf(&mut cell)?;
+                match backend.spine(&cell) {
+                    (Atom::Prim(PROBE_NIL), args) if args.is_empty() => {
+                        return Ok(Some(Decoded::List(items)));
+                    }
+                    (Atom::Prim(PROBE_CONS), mut args) if args.len() == 2 => {
+                        value = args.pop().unwrap();
+                        match decode(backend, args.pop().unwrap(), item)? {
+                            Some(decoded) => items.push(decoded),
+                            None => return Ok(None),
+                        }
+                    }
+                    _ => return Ok(None),
+                }
+            }
+        }
+        Shape::Term => {
+            let mut value = value;
+            backend.reduce_to_nf(&mut value)?;
+            Ok(readback_named(backend, &value).map(Decoded::Term))
+        }
+    }
+}
+
+fn decode_int<'a, B: Backend<'a>>(backend: &mut B, mut value: B::Value) -> Result<Option<i64>> {
+    backend.reduce_to_whnf(&mut value)?;
+    match backend.spine(&value) {
+        (Atom::Prim(i), args) if args.is_empty() => Ok(Some(i)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tree_reducer::TreeReducer;
+    use crate::library::{compile_with_prelude, prelude_primops};
+
+    #[test]
+    fn reads_back_and_decodes_values() {
+        let main = "
+            import Prelude;
+            pair = Pair (ADD 1 2) (Just 4);
+            zero f x = x;
+            succ n f x = f (n f x);
+            three = succ (succ (succ zero));
+            flags = map (λx. LT x 2 True False) (range 0 3);
+            churches = Cons zero (Cons three Nil);
+        ";
+        let sc = compile_with_prelude(main);
+        let mut reducer = TreeReducer::load(sc, prelude_primops).unwrap();
+        let pair = reducer.run_to_nf("pair").unwrap();
+        let expr = readback_named(&reducer, &pair).unwrap();
+        assert_eq!(expr.to_string(), "Prelude.Pair 3 (Prelude.Just 4)");
+
+        let decode_entry = |reducer: &mut TreeReducer, name: &str, shape: &Shape| {
+            let value = reducer.entry(name).unwrap();
+            decode(reducer, value, shape)
+                .unwrap()
+                .map(|d| d.to_string())
+        };
+        let three = decode_entry(&mut reducer, "three", &Shape::Church);
+        assert_eq!(three.as_deref(), Some("3"));
+        let flags = decode_entry(&mut reducer, "flags", &Shape::List(Box::new(Shape::Bool)));
+        assert_eq!(flags.as_deref(), Some("[true, true, false, false]"));
+        let churches = Shape::List(Box::new(Shape::Church));
+        let churches = decode_entry(&mut reducer, "churches", &churches);
+        assert_eq!(churches.as_deref(), Some("[0, 3]"));
+        assert_eq!(decode_entry(&mut reducer, "pair", &Shape::Int), None);
+    }
+
+    #[test]
+    fn reads_back_long_results() {
+        let main = "
+            import Prelude;
+            main = range 1 50000;
+        ";
+        let sc = compile_with_prelude(main);
+        let mut reducer = TreeReducer::load(sc, prelude_primops).unwrap();
+        let main = reducer.run_to_nf("main").unwrap();
+        let list = readback_named(&reducer, &main).unwrap();
+        let mut expr = &list;
+        let mut items = 0;
+        while let Expr::App(_, rest) = expr {
+            items += 1;
+            expr = rest;
+        }
+        assert_eq!(items, 50000);
+        assert_eq!(expr.to_string(), "Prelude.Nil");
+    }
+}
@@ -0,0 +1,485 @@
+// interpret: STG machine, eval/apply
+// runs the lambda forms of compiler::stg; the state is the expression under evaluation with its
+// variables, or a value being returned, and a stack of continuations: a case waiting for the
+// value of its scrutinee, an update of a thunk, or the arguments to apply the value to
+// eval/apply: a function value is applied by its caller, which knows the number of arguments,
+// so a call with too few arguments builds a partial application and one with too many pushes
+// the rest as an apply continuation
+// a thunk pushes an update continuation when entered, so its value is computed at most once
+// a global of arity 0 (a CAF) is a fresh thunk at each use, like in the tree reducer
+// values are integers, IoRes, World and heap addresses; `IoRes i f` continues with
+// `f i World` as in the other reducers, and World arguments of primops are skipped
+// a step of the budget is a transition; a stopped reduction keeps its state, so reducing the same
+// value again resumes it, while reducing another one drops it; observers see the primop calls
+// `spine` shows a thunk not yet evaluated as the global whose body it comes from
+
+use crate::compiler::stg::{StgArg, StgExpr, StgProgram};
+use crate::error::*;
+use crate::interpreter::{reduce_parts_to_nf, Backend, Budget, Event, Observer, Outcome};
+use crate::structures::*;
+use std::ops::ControlFlow;
+use std::rc::Rc;
+
+pub type Addr = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Value {
+    Addr(Addr),
+    Int(i64),
+    IoRes(i64),
+    World,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Object {
+    Fun { form: usize, env: Vec<Value> },
+    Thunk { form: usize, env: Vec<Value> },
+    // a function or constructor applied to too few arguments, or an integer applied to some
+    Pap { fun: Value, args: Vec<Value> },
+    Con { ctor: usize, fields: Vec<Value> },
+    // an application built by `apply`, updated with its value like a thunk
+    Ap { fun: Value, args: Vec<Value> },
+    // a thunk updated with its value
+    Ind(Value),
+}
+
+enum Cont {
+    Case(Rc<StgExpr>, Vec<Value>),
+    Update(Addr),
+    Apply(Vec<Value>),
+}
+
+enum Next {
+    Eval(Rc<StgExpr>, Vec<Value>),
+    Apply(Value, Vec<Value>),
+    Return(Value),
+}
+
+pub struct Stg<'a> {
+    program: ScPrimProgram<'a>,
+    stg: StgProgram,
+    // the shared object of each global of arity 1 or more, and of each constructor without fields
+    globals: Vec<Option<Addr>>,
+    heap: Vec<Object>,
+    stack: Vec<Cont>,
+    steps: usize,
+    // the value and the next transition of a stopped reduction to WHNF
+    suspended: Option<(Value, Next)>,
+    // the value and the parts left of a stopped reduction to NF
+    nf: Option<(Value, Vec<Value>)>,
+}
+
+impl<'a> Stg<'a> {
+    pub fn alloc(&mut self, object: Object) -> Addr {
+        self.heap.push(object);
+        self.heap.len() - 1
+    }
+
+    // number of heap objects allocated so far
+    pub fn heap_len(&self) -> usize {
+        self.heap.len()
+    }
+
+    // number of transitions taken so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn resolve(&self, mut value: Value) -> Value {
+        while let Value::Addr(addr) = value {
+            match self.heap[addr] {
+                Object::Ind(target) => value = target,
+                _ => break,
+            }
+        }
+        value
+    }
+
+    pub fn object(&self, addr: Addr) -> &Object {
+        &self.heap[addr]
+    }
+
+    fn arg(&mut self, arg: StgArg, locals: &[Value]) -> Value {
+        match arg {
+            StgArg::Local(i) => locals[i],
+            StgArg::Global(g) => self.sc(g),
+            StgArg::Int(i) => Value::Int(i),
+        }
+    }
+
+    fn args(&mut self, args: &[StgArg], locals: &[Value]) -> Vec<Value> {
+        args.iter().map(|&arg| self.arg(arg, locals)).collect()
+    }
+
+    fn run(
+        &mut self,
+        mut next: Next,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Value>,
+    ) -> Result<(Outcome, Next)> {
+        loop {
+            if let (Next::Return(_), true) = (&next, self.stack.is_empty()) {
+                return Ok((Outcome::Done, next));
+            }
+            if let Some(outcome) = budget.stop() {
+                return Ok((outcome, next));
+            }
+            budget.check_memory(self.stack.len())?;
+            budget.consume();
+            self.steps += 1;
+            let mut flow = ControlFlow::Continue(());
+            next = match next {
+                Next::Eval(expr, locals) => self.eval(&expr, locals, &mut flow, observer)?,
+                Next::Apply(f, args) => self.enter(f, args),
+                Next::Return(value) => match self.stack.pop() {
+                    None => unreachable!(),
+                    Some(Cont::Case(body, mut locals)) => {
+                        locals.push(value);
+                        Next::Eval(body, locals)
+                    }
+                    Some(Cont::Update(addr)) => {
+                        self.heap[addr] = Object::Ind(value);
+                        Next::Return(value)
+                    }
+                    Some(Cont::Apply(args)) => Next::Apply(value, args),
+                },
+            };
+            if flow.is_break() {
+                return Ok((Outcome::Paused, next));
+            }
+        }
+    }
+
+    // `flow` breaks if the observer asks to pause after a primop call
+    fn eval(
+        &mut self,
+        expr: &StgExpr,
+        mut locals: Vec<Value>,
+        flow: &mut ControlFlow<()>,
+        observer: &mut dyn Observer<Value>,
+    ) -> Result<Next> {
+        Ok(match expr {
+            StgExpr::App(f, args) => {
+                let f = self.arg(*f, &locals);
+                Next::Apply(f, self.args(args, &locals))
+            }
+            StgExpr::Con(ctor, args) => {
+                let fields = self.args(args, &locals);
+                let ctor = *ctor;
+                Next::Return(Value::Addr(self.alloc(Object::Con { ctor, fields })))
+            }
+            StgExpr::Prim(p, args) => {
+                let args = self.args(args, &locals);
+                match self.call_prim(*p, args, flow, observer)? {
+                    Atom::Sc(sc) => Next::Apply(self.sc(sc), vec![]),
+                    Atom::Prim(i) => Next::Return(Value::Int(i)),
+                    Atom::IoRes(i) => Next::Return(Value::IoRes(i)),
+                    Atom::World => Next::Return(Value::World),
+                }
+            }
+            StgExpr::Lit(i) => Next::Return(Value::Int(*i)),
+            StgExpr::Let(forms, body) => {
+                for &form in forms {
+                    let lambda = &self.stg.forms[form];
+                    let env = lambda.free.iter().map(|&v| locals[v]).collect();
+                    let object = if lambda.params == 0 {
+                        Object::Thunk { form, env }
+                    } else {
+                        Object::Fun { form, env }
+                    };
+                    locals.push(Value::Addr(self.alloc(object)));
+                }
+                Next::Eval(body.clone(), locals)
+            }
+            StgExpr::Case(scrutinee, body) => {
+                self.stack.push(Cont::Case(body.clone(), locals.clone()));
+                Next::Eval(scrutinee.clone(), locals)
+            }
+        })
+    }
+
+    fn enter(&mut self, f: Value, mut args: Vec<Value>) -> Next {
+        let addr = match f {
+            Value::Addr(addr) => addr,
+            Value::IoRes(i) if !args.is_empty() => {
+                // IoRes i f -> f i World
+                let f = args.remove(0);
+                args.splice(0..0, [Value::Int(i), Value::World]);
+                return Next::Apply(f, args);
+            }
+            _ if args.is_empty() => return Next::Return(f),
+            // unable to reduce head
+            _ => return Next::Return(Value::Addr(self.alloc(Object::Pap { fun: f, args }))),
+        };
+        match &self.heap[addr] {
+            Object::Ind(value) => Next::Apply(*value, args),
+            Object::Thunk { form, env } => {
+                if !args.is_empty() {
+                    self.stack.push(Cont::Apply(args));
+                }
+                let body = self.stg.forms[*form].body.clone();
+                let env = env.clone();
+                self.stack.push(Cont::Update(addr));
+                Next::Eval(body, env)
+            }
+            Object::Ap { fun, args: applied } => {
+                if !args.is_empty() {
+                    self.stack.push(Cont::Apply(args));
+                }
+                let (fun, applied) = (*fun, applied.clone());
+                self.stack.push(Cont::Update(addr));
+                Next::Apply(fun, applied)
+            }
+            Object::Fun { form, env } => {
+                let form = &self.stg.forms[*form];
+                if args.len() < form.params {
+                    return self.partial(f, args);
+                }
+                let rest = args.split_off(form.params);
+                if !rest.is_empty() {
+                    self.stack.push(Cont::Apply(rest));
+                }
+                let mut locals = env.clone();
+                locals.extend(args);
+                Next::Eval(form.body.clone(), locals)
+            }
+            Object::Pap { fun, args: applied } => {
+                if args.is_empty() {
+                    return Next::Return(f);
+                }
+                let mut all = applied.clone();
+                all.extend(args);
+                Next::Apply(*fun, all)
+            }
+            Object::Con { ctor, fields } => {
+                let ctor = self.stg.ctors[*ctor].unwrap();
+                if args.len() < ctor.alts {
+                    return self.partial(f, args);
+                }
+                let fields = fields.clone();
+                let rest = args.split_off(ctor.alts);
+                if !rest.is_empty() {
+                    self.stack.push(Cont::Apply(rest));
+                }
+                Next::Apply(args[ctor.index], fields)
+            }
+        }
+    }
+
+    fn partial(&mut self, fun: Value, args: Vec<Value>) -> Next {
+        if args.is_empty() {
+            return Next::Return(fun);
+        }
+        Next::Return(Value::Addr(self.alloc(Object::Pap { fun, args })))
+    }
+
+    fn call_prim(
+        &mut self,
+        p: usize,
+        args: Vec<Value>,
+        flow: &mut ControlFlow<()>,
+        observer: &mut dyn Observer<Value>,
+    ) -> Result<Atom> {
+        let mut prim_arg = vec![];
+        for arg in args {
+            match self.resolve(arg) {
+                Value::Int(i) => prim_arg.push(i),
+                // ignore World
+                Value::World => {}
+                arg => {
+                    let prim_name = self.program.defs[p].name.to_string();
+                    let arg = self.whnf_to_string(&arg);
+                    return Err(Error::UnexpectedPrimApp { prim_name, arg });
+                }
+            }
+        }
+        let ScBody::Prim(prim) = &mut self.program.defs[p].body else {
+            unreachable!()
+        };
+        let result = prim(&prim_arg).ok_or_else(|| Error::PrimopFailure {
+            def_name: self.program.defs[p].name.to_string(),
+            arg: format!("{:?}", prim_arg),
+        })?;
+        let event = Event::Prim {
+            sc: p,
+            args: &prim_arg,
+            result,
+        };
+        if observer.observe(event).is_break() {
+            *flow = ControlFlow::Break(());
+        }
+        Ok(result)
+    }
+}
+
+impl<'a> Backend<'a> for Stg<'a> {
+    type Value = Value;
+
+    fn new(program: ScPrimProgram<'a>) -> Self {
+        let stg = program.compile_stg();
+        let globals = vec![None; program.defs.len()];
+        Self {
+            program,
+            stg,
+            globals,
+            heap: vec![],
+            stack: vec![],
+            steps: 0,
+            suspended: None,
+            nf: None,
+        }
+    }
+
+    fn program(&self) -> &ScPrimProgram<'a> {
+        &self.program
+    }
+
+    fn sc(&mut self, sc: usize) -> Value {
+        if let Some(addr) = self.globals[sc] {
+            return Value::Addr(addr);
+        }
+        let object = match self.stg.ctors[sc] {
+            Some(ctor) if ctor.fields == 0 => Object::Con {
+                ctor: sc,
+                fields: vec![],
+            },
+            _ if self.stg.forms[sc].params == 0 => {
+                let env = vec![];
+                return Value::Addr(self.alloc(Object::Thunk { form: sc, env }));
+            }
+            _ => Object::Fun {
+                form: sc,
+                env: vec![],
+            },
+        };
+        let addr = self.alloc(object);
+        self.globals[sc] = Some(addr);
+        Value::Addr(addr)
+    }
+
+    fn prim(&mut self, i: i64) -> Value {
+        Value::Int(i)
+    }
+
+    fn apply(&mut self, fun: Value, args: Vec<Value>) -> Value {
+        if args.is_empty() {
+            return fun;
+        }
+        Value::Addr(self.alloc(Object::Ap { fun, args }))
+    }
+
+    // the value is replaced with the one it evaluates to
+    fn reduce_to_whnf_observed(
+        &mut self,
+        value: &mut Value,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Value>,
+    ) -> Result<Outcome> {
+        let next = match self.suspended.take() {
+            Some((root, next)) if root == *value => next,
+            _ => {
+                self.stack.clear();
+                Next::Apply(*value, vec![])
+            }
+        };
+        match self.run(next, budget, observer)? {
+            (Outcome::Done, Next::Return(result)) => *value = self.resolve(result),
+            (outcome, next) => {
+                self.suspended = Some((*value, next));
+                return Ok(outcome);
+            }
+        }
+        Ok(Outcome::Done)
+    }
+
+    fn reduce_to_nf_observed(
+        &mut self,
+        value: &mut Value,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Value>,
+    ) -> Result<Outcome> {
+        let mut pending = match self.nf.take() {
+            Some((root, pending)) if root == *value => pending,
+            _ => {
+                let outcome = self.reduce_to_whnf_observed(value, budget, observer)?;
+                if outcome != Outcome::Done {
+                    return Ok(outcome);
+                }
+                let (_, mut args) = self.spine(value);
+                args.reverse();
+                args
+            }
+        };
+        let outcome = reduce_parts_to_nf(self, &mut pending, budget, observer)?;
+        if outcome != Outcome::Done {
+            self.nf = Some((*value, pending));
+        }
+        Ok(outcome)
+    }
+
+    fn spine(&self, value: &Value) -> (Atom, Vec<Value>) {
+        match self.resolve(*value) {
+            Value::Int(i) => (Atom::Prim(i), vec![]),
+            Value::IoRes(i) => (Atom::IoRes(i), vec![]),
+            Value::World => (Atom::World, vec![]),
+            Value::Addr(addr) => match &self.heap[addr] {
+                Object::Fun { form, .. } | Object::Thunk { form, .. } => {
+                    (Atom::Sc(self.stg.owners[*form]), vec![])
+                }
+                Object::Pap { fun, args } | Object::Ap { fun, args } => {
+                    let (head, mut all) = self.spine(fun);
+                    all.extend(args);
+                    (head, all)
+                }
+                Object::Con { ctor, fields } => (Atom::Sc(*ctor), fields.clone()),
+                Object::Ind(_) => unreachable!(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{compile_with_prelude, prelude_primops};
+
+    #[test]
+    fn evaluates_with_constructors_and_updates() {
+        let main = "
+            import Prelude;
+            fib n = if (LT n 2) n (ADD (fib (SUB n 1)) (fib (SUB n 2)));
+            main = ADD (fib 15) (length (filter (λx. EQ (MOD x 3) 0) (range 1 30)));
+            pair = Pair (head (Cons 1 Nil)) (fib 10);
+            shared = let x = fib 20 in ADD x x;
+        ";
+        let sc = compile_with_prelude(main);
+        let table = sc.def_indexes();
+        let (main, pair, shared, fib) =
+            (table["main"], table["pair"], table["shared"], table["fib"]);
+        let mut primops = prelude_primops(&table);
+        let mut machine = Stg::new(sc.attach_prim(&mut primops).unwrap());
+        let mut value = machine.sc(main);
+        machine.reduce_to_whnf(&mut value).unwrap();
+        assert_eq!(value, Value::Int(610 + 10));
+
+        let mut value = machine.sc(pair);
+        machine.reduce_to_whnf(&mut value).unwrap();
+        let Value::Addr(addr) = value else { panic!() };
+        assert!(matches!(machine.object(addr), Object::Con { .. }));
+        assert_eq!(machine.whnf_to_string(&value), "Prelude.Pair (..) (..)");
+        machine.reduce_to_nf(&mut value).unwrap();
+        let (_, fields) = machine.spine(&value);
+        assert_eq!(machine.resolve(fields[1]), Value::Int(55));
+
+        let mut value = machine.sc(shared);
+        let steps = machine.steps();
+        machine.reduce_to_whnf(&mut value).unwrap();
+        assert_eq!(value, Value::Int(13530));
+        let shared_steps = machine.steps() - steps;
+        let fib = machine.sc(fib);
+        let mut value = machine.apply(fib, vec![Value::Int(20)]);
+        let steps = machine.steps();
+        machine.reduce_to_whnf(&mut value).unwrap();
+        assert!(shared_steps < 2 * (machine.steps() - steps));
+    }
+}
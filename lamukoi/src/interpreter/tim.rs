@@ -0,0 +1,599 @@
+// interpret: Three Instruction Machine
+// runs the code of compiler::tim; the state is the current block and frame, a stack of argument
+// closures, continuations and update markers, and a stack of values for primops
+// entering an updatable closure from a frame slot pushes an update marker for that slot; a value
+// returned to the marker, or a function that finds the marker among its arguments, is written
+// back to the slot, so a thunk is evaluated at most once
+// a partial application written back to a slot is a new frame holding the function and its
+// arguments, with a block that pushes the arguments again and enters the function
+// values are integers, IoRes and World; `IoRes i f` continues with `f i World` as in the other
+// reducers, and World arguments of primops are skipped
+// a step of the budget is entering a closure or returning a value; a stopped reduction keeps its
+// state, so reducing the same closure again resumes it, while reducing another one drops it
+// `spine` shows a closure not yet evaluated as the supercombinator whose body it comes from
+
+use crate::compiler::tim::{Block, Instr, Mode, TimCode};
+use crate::error::*;
+use crate::interpreter::{reduce_parts_to_nf, Backend, Budget, Event, Observer, Outcome};
+use crate::structures::*;
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+pub type FrameId = usize;
+
+// frame 0 is empty, for supercombinators
+const NO_FRAME: FrameId = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Closure {
+    Code { block: usize, frame: FrameId },
+    Int(i64),
+    IoRes(i64),
+    World,
+}
+
+enum Item {
+    Arg(Closure),
+    Cont(Closure),
+    Update(FrameId, usize),
+}
+
+// a value in weak head normal form: a head applied to closures, the first one first
+struct Whnf {
+    head: Atom,
+    args: Vec<Closure>,
+}
+
+pub struct Tim<'a> {
+    program: ScPrimProgram<'a>,
+    code: TimCode,
+    // the number of blocks compiled from the program, before those created on demand
+    compiled: usize,
+    // blocks `Enter (Arg k)` and partial applications of `n` arguments, created on demand
+    indirections: HashMap<usize, usize>,
+    partials: HashMap<usize, usize>,
+    frames: Vec<Vec<Closure>>,
+    stack: Vec<Item>,
+    values: Vec<Atom>,
+    steps: usize,
+    // the closure and the next transition of a stopped reduction to WHNF
+    suspended: Option<(Closure, Next)>,
+    // the closure and the parts left of a stopped reduction to NF
+    nf: Option<(Closure, Vec<Closure>)>,
+}
+
+enum Next {
+    Enter(Closure),
+    Return(Atom),
+    Halt(Whnf),
+}
+
+impl<'a> Tim<'a> {
+    fn sc_closure(&self, sc: usize) -> Closure {
+        Closure::Code {
+            block: self.code.entries[sc],
+            frame: NO_FRAME,
+        }
+    }
+
+    pub fn alloc(&mut self, slots: Vec<Closure>) -> FrameId {
+        self.frames.push(slots);
+        self.frames.len() - 1
+    }
+
+    // number of frames allocated so far
+    pub fn frames_len(&self) -> usize {
+        self.frames.len()
+    }
+
+    // number of instructions executed so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn new_block(&mut self, code: Vec<Instr>) -> usize {
+        self.code.blocks.push(Block {
+            code,
+            updatable: false,
+            sc: None,
+            prim: None,
+        });
+        self.code.blocks.len() - 1
+    }
+
+    fn indirection(&mut self, k: usize) -> usize {
+        if let Some(&block) = self.indirections.get(&k) {
+            return block;
+        }
+        let block = self.new_block(vec![Instr::Enter(Mode::Arg(k))]);
+        self.indirections.insert(k, block);
+        block
+    }
+
+    // `f a0 .. an-1` as a closure, from the frame `[f, a0, .., an-1]`
+    fn partial(&mut self, slots: Vec<Closure>) -> Closure {
+        let n = slots.len() - 1;
+        let block = match self.partials.get(&n) {
+            Some(&block) => block,
+            None => {
+                let mut code: Vec<_> = (1..=n).rev().map(|k| Instr::Push(Mode::Arg(k))).collect();
+                code.push(Instr::Enter(Mode::Arg(0)));
+                let block = self.new_block(code);
+                self.partials.insert(n, block);
+                // for `spine`, which shares the slots the same way
+                for k in 1..=n {
+                    self.indirection(k);
+                }
+                block
+            }
+        };
+        let frame = self.alloc(slots);
+        Closure::Code { block, frame }
+    }
+
+    fn is_thunk(&self, closure: Closure) -> bool {
+        matches!(closure, Closure::Code { block, .. } if self.code.blocks[block].updatable)
+    }
+
+    fn closure(&mut self, mode: Mode, frame: FrameId) -> Closure {
+        match mode {
+            Mode::Arg(k) => {
+                let closure = self.frames[frame][k];
+                if self.is_thunk(closure) {
+                    // share the slot, so it is updated at most once
+                    let block = self.indirection(k);
+                    Closure::Code { block, frame }
+                } else {
+                    closure
+                }
+            }
+            Mode::Label(sc) => self.sc_closure(sc),
+            Mode::Code(block) => Closure::Code { block, frame },
+            Mode::Int(i) => Closure::Int(i),
+        }
+    }
+
+    fn atom_closure(&self, atom: Atom) -> Closure {
+        match atom {
+            Atom::Sc(sc) => self.sc_closure(sc),
+            Atom::Prim(i) => Closure::Int(i),
+            Atom::IoRes(i) => Closure::IoRes(i),
+            Atom::World => Closure::World,
+        }
+    }
+
+    // the arguments on top of the stack, the first one first
+    fn take_args(&mut self) -> Vec<Closure> {
+        let mut args = vec![];
+        while let Some(Item::Arg(closure)) = self.stack.last() {
+            args.push(*closure);
+            self.stack.pop();
+        }
+        args
+    }
+
+    // the shared closure of slot `k` of `frame`
+    fn slot(&self, frame: FrameId, k: usize) -> Closure {
+        let closure = self.frames[frame][k];
+        if self.is_thunk(closure) {
+            let block = self.indirections[&k];
+            Closure::Code { block, frame }
+        } else {
+            closure
+        }
+    }
+
+    fn run(
+        &mut self,
+        next: &mut Next,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Closure>,
+    ) -> Result<Outcome> {
+        loop {
+            if let Next::Halt(_) = next {
+                return Ok(Outcome::Done);
+            }
+            if let Some(outcome) = budget.stop() {
+                return Ok(outcome);
+            }
+            budget.check_memory(self.stack.len())?;
+            budget.consume();
+            let mut flow = ControlFlow::Continue(());
+            *next = match *next {
+                Next::Enter(closure) => self.enter(closure, &mut flow, observer)?,
+                Next::Return(atom) => self.ret(atom)?,
+                Next::Halt(_) => unreachable!(),
+            };
+            if flow.is_break() {
+                return Ok(Outcome::Paused);
+            }
+        }
+    }
+
+    // `flow` breaks if the observer asks to pause after a primop call
+    fn enter(
+        &mut self,
+        closure: Closure,
+        flow: &mut ControlFlow<()>,
+        observer: &mut dyn Observer<Closure>,
+    ) -> Result<Next> {
+        let (block, frame) = match closure {
+            Closure::Code { block, frame } => (block, frame),
+            Closure::Int(i) => return Ok(Next::Return(Atom::Prim(i))),
+            Closure::IoRes(i) => return Ok(Next::Return(Atom::IoRes(i))),
+            Closure::World => return Ok(Next::Return(Atom::World)),
+        };
+        let mut frame = frame;
+        for pc in 0.. {
+            self.steps += 1;
+            let instr = self.code.blocks[block].code[pc];
+            match instr {
+                Instr::Take(n) => {
+                    let top = self.stack.iter().rev().take(n);
+                    if top.take_while(|item| matches!(item, Item::Arg(_))).count() < n {
+                        let sc = self.code.blocks[block].sc.unwrap();
+                        let args = self.take_args();
+                        return self.partial_application(sc, args);
+                    }
+                    let args = self.stack.drain(self.stack.len() - n..).rev();
+                    let slots = args.map(|item| match item {
+                        Item::Arg(closure) => closure,
+                        _ => unreachable!(),
+                    });
+                    let slots = slots.collect();
+                    frame = self.alloc(slots);
+                }
+                Instr::Push(mode) => {
+                    let closure = self.closure(mode, frame);
+                    self.stack.push(Item::Arg(closure));
+                }
+                Instr::PushCont(block) => {
+                    self.stack.push(Item::Cont(Closure::Code { block, frame }));
+                }
+                Instr::Enter(mode) => {
+                    if let Mode::Arg(k) = mode {
+                        let closure = self.frames[frame][k];
+                        if self.is_thunk(closure) {
+                            self.stack.push(Item::Update(frame, k));
+                        }
+                        return Ok(Next::Enter(closure));
+                    }
+                    return Ok(Next::Enter(self.closure(mode, frame)));
+                }
+                Instr::PushV(i) => self.values.push(Atom::Prim(i)),
+                Instr::Prim(p) => {
+                    let result = self.call_prim(p, flow, observer)?;
+                    self.values.push(result);
+                }
+                Instr::Return => return Ok(Next::Return(self.values.pop().unwrap())),
+            }
+        }
+        unreachable!()
+    }
+
+    // supercombinator `sc` found only `args` on the stack
+    fn partial_application(&mut self, sc: usize, args: Vec<Closure>) -> Result<Next> {
+        match self.stack.pop() {
+            None => Ok(Next::Halt(Whnf {
+                head: Atom::Sc(sc),
+                args,
+            })),
+            Some(Item::Update(frame, k)) => {
+                let mut slots = vec![self.sc_closure(sc)];
+                slots.extend(&args);
+                self.frames[frame][k] = self.partial(slots);
+                for &arg in args.iter().rev() {
+                    self.stack.push(Item::Arg(arg));
+                }
+                Ok(Next::Enter(self.sc_closure(sc)))
+            }
+            Some(Item::Cont(cont)) => {
+                let prim = self.cont_prim(cont);
+                let head = self.atom_to_string(Atom::Sc(sc));
+                let arg = format!("{}{}", head, " (..)".repeat(args.len()));
+                Err(Error::UnexpectedPrimApp {
+                    prim_name: self.program.defs[prim].name.to_string(),
+                    arg,
+                })
+            }
+            Some(Item::Arg(_)) => unreachable!(),
+        }
+    }
+
+    fn ret(&mut self, atom: Atom) -> Result<Next> {
+        loop {
+            match self.stack.pop() {
+                None => {
+                    return Ok(Next::Halt(Whnf {
+                        head: atom,
+                        args: vec![],
+                    }))
+                }
+                Some(Item::Update(frame, k)) => {
+                    self.frames[frame][k] = self.atom_closure(atom);
+                }
+                Some(Item::Cont(cont)) => {
+                    if let Atom::Sc(_) = atom {
+                        self.stack.push(Item::Cont(cont));
+                        return Ok(Next::Enter(self.atom_closure(atom)));
+                    }
+                    if let Atom::IoRes(_) = atom {
+                        let prim = self.cont_prim(cont);
+                        return Err(Error::UnexpectedPrimApp {
+                            prim_name: self.program.defs[prim].name.to_string(),
+                            arg: self.atom_to_string(atom),
+                        });
+                    }
+                    self.values.push(atom);
+                    return Ok(Next::Enter(cont));
+                }
+                Some(Item::Arg(arg)) => match atom {
+                    Atom::Sc(_) => {
+                        self.stack.push(Item::Arg(arg));
+                        return Ok(Next::Enter(self.atom_closure(atom)));
+                    }
+                    Atom::IoRes(i) => {
+                        // IoRes i f -> f i World
+                        self.stack.push(Item::Arg(Closure::World));
+                        self.stack.push(Item::Arg(Closure::Int(i)));
+                        return Ok(Next::Enter(arg));
+                    }
+                    Atom::Prim(_) | Atom::World => {
+                        // unable to reduce head
+                        self.stack.push(Item::Arg(arg));
+                        let args = self.take_args();
+                        return Ok(Next::Halt(Whnf { head: atom, args }));
+                    }
+                },
+            }
+        }
+    }
+
+    fn cont_prim(&self, cont: Closure) -> usize {
+        match cont {
+            Closure::Code { block, .. } => self.code.blocks[block].prim.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    // `flow` breaks if the observer asks to pause after the call
+    fn call_prim(
+        &mut self,
+        p: usize,
+        flow: &mut ControlFlow<()>,
+        observer: &mut dyn Observer<Closure>,
+    ) -> Result<Atom> {
+        let params = self.program.defs[p].params;
+        let args = self.values.split_off(self.values.len() - params);
+        let mut prim_arg = vec![];
+        for arg in args {
+            match arg {
+                Atom::Prim(i) => prim_arg.push(i),
+                // ignore World
+                Atom::World => {}
+                _ => {
+                    let prim_name = self.program.defs[p].name.to_string();
+                    let arg = self.atom_to_string(arg);
+                    return Err(Error::UnexpectedPrimApp { prim_name, arg });
+                }
+            }
+        }
+        let ScBody::Prim(prim) = &mut self.program.defs[p].body else {
+            unreachable!()
+        };
+        let result = prim(&prim_arg).ok_or_else(|| Error::PrimopFailure {
+            def_name: self.program.defs[p].name.to_string(),
+            arg: format!("{:?}", prim_arg),
+        })?;
+        let event = Event::Prim {
+            sc: p,
+            args: &prim_arg,
+            result,
+        };
+        if observer.observe(event).is_break() {
+            *flow = ControlFlow::Break(());
+        }
+        Ok(result)
+    }
+
+    fn is_indirection(&self, closure: Closure) -> bool {
+        match closure {
+            Closure::Code { block, .. } => self.indirections.values().any(|&b| b == block),
+            _ => false,
+        }
+    }
+
+    fn whnf_closure(&mut self, whnf: Whnf) -> Closure {
+        let head = self.atom_closure(whnf.head);
+        self.apply(head, whnf.args)
+    }
+}
+
+impl<'a> Backend<'a> for Tim<'a> {
+    type Value = Closure;
+
+    fn new(program: ScPrimProgram<'a>) -> Self {
+        let code = program.compile_tim();
+        Self {
+            program,
+            compiled: code.blocks.len(),
+            code,
+            indirections: HashMap::new(),
+            partials: HashMap::new(),
+            frames: vec![vec![]],
+            stack: vec![],
+            values: vec![],
+            steps: 0,
+            suspended: None,
+            nf: None,
+        }
+    }
+
+    fn program(&self) -> &ScPrimProgram<'a> {
+        &self.program
+    }
+
+    fn sc(&mut self, sc: usize) -> Closure {
+        self.sc_closure(sc)
+    }
+
+    fn prim(&mut self, i: i64) -> Closure {
+        Closure::Int(i)
+    }
+
+    fn apply(&mut self, fun: Closure, args: Vec<Closure>) -> Closure {
+        if args.is_empty() {
+            return fun;
+        }
+        let mut slots = vec![fun];
+        slots.extend(args);
+        self.partial(slots)
+    }
+
+    // the value is replaced with the closure of its WHNF, unless it shares a frame slot, which
+    // holds that closure already
+    fn reduce_to_whnf_observed(
+        &mut self,
+        value: &mut Closure,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Closure>,
+    ) -> Result<Outcome> {
+        let mut next = match self.suspended.take() {
+            Some((root, next)) if root == *value => next,
+            _ => {
+                self.stack.clear();
+                self.values.clear();
+                Next::Enter(*value)
+            }
+        };
+        let outcome = self.run(&mut next, budget, observer)?;
+        match next {
+            Next::Halt(_) if self.is_indirection(*value) => {}
+            Next::Halt(whnf) => *value = self.whnf_closure(whnf),
+            next => self.suspended = Some((*value, next)),
+        }
+        Ok(outcome)
+    }
+
+    fn reduce_to_nf_observed(
+        &mut self,
+        value: &mut Closure,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Closure>,
+    ) -> Result<Outcome> {
+        // the parts are shared slots, but the value itself is replaced by its WHNF first
+        let mut pending = match self.nf.take() {
+            Some((root, pending)) if root == *value => pending,
+            _ => {
+                let outcome = self.reduce_to_whnf_observed(value, budget, observer)?;
+                if outcome != Outcome::Done {
+                    return Ok(outcome);
+                }
+                let (_, mut args) = self.spine(value);
+                args.reverse();
+                args
+            }
+        };
+        let outcome = reduce_parts_to_nf(self, &mut pending, budget, observer)?;
+        if outcome != Outcome::Done {
+            self.nf = Some((*value, pending));
+        }
+        Ok(outcome)
+    }
+
+    fn spine(&self, value: &Closure) -> (Atom, Vec<Closure>) {
+        let mut closure = *value;
+        let mut args = vec![];
+        loop {
+            let (block, frame) = match closure {
+                Closure::Code { block, frame } => (block, frame),
+                Closure::Int(i) => return (Atom::Prim(i), args),
+                Closure::IoRes(i) => return (Atom::IoRes(i), args),
+                Closure::World => return (Atom::World, args),
+            };
+            if block < self.compiled {
+                // the blocks of a supercombinator come right before its own
+                let sc = self.code.entries.partition_point(|&entry| entry < block);
+                return (Atom::Sc(sc), args);
+            }
+            // an indirection or a partial application: push some slots and enter another one
+            let code = &self.code.blocks[block].code;
+            let (last, pushes) = code.split_last().unwrap();
+            let pushed = pushes.iter().rev().map(|instr| match instr {
+                Instr::Push(Mode::Arg(k)) => self.slot(frame, *k),
+                _ => unreachable!(),
+            });
+            args.splice(0..0, pushed);
+            closure = match last {
+                Instr::Enter(Mode::Arg(k)) => self.frames[frame][*k],
+                _ => unreachable!(),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{compile_with_prelude, prelude_primops};
+    use crate::parser::parse_program;
+    use std::cell::RefCell;
+
+    #[test]
+    fn runs_prelude_programs() {
+        let main = "
+            import Prelude;
+            fib n = if (LT n 2) n (ADD (fib (SUB n 1)) (fib (SUB n 2)));
+            twice f x = f (f x);
+            main = ADD (fib 15) (twice (λx. MUL x x) (sum (take 3 (repeat 1))));
+            pair = Pair 1 (head Nil);
+        ";
+        let sc = compile_with_prelude(main);
+        let mut tim = Tim::load(sc, prelude_primops).unwrap();
+        let value = tim.run_to_whnf("main").unwrap();
+        assert_eq!(value, Closure::Int(610 + 81));
+        let value = tim.run_to_whnf("pair").unwrap();
+        assert_eq!(tim.whnf_to_string(&value), "Prelude.Pair (..) (..)");
+    }
+
+    #[test]
+    fn runs_io_actions() {
+        // READ yields `IoRes c`, which passes c and World to the continuation
+        let src = "
+            #READ w;
+            #WRITE c w;
+            #ADD x y;
+            main w = READ w (λc w. WRITE (ADD c c) w (λ_ w. WRITE c w (λ_ w. w)));
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let main = sc.def_indexes()["main"];
+        let input = RefCell::new(vec![3, 2, 1]);
+        let output = RefCell::new(vec![]);
+        let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+        primops.insert(
+            "READ",
+            Box::new(|_: &[i64]| Some(Atom::IoRes(input.borrow_mut().pop()?))),
+        );
+        primops.insert(
+            "WRITE",
+            Box::new(|a: &[i64]| {
+                output.borrow_mut().push(a[0]);
+                Some(Atom::IoRes(0))
+            }),
+        );
+        primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+        let mut tim = Tim::new(sc.attach_prim(&mut primops).unwrap());
+        let main = tim.sc(main);
+        let mut value = tim.apply(main, vec![Closure::World]);
+        tim.reduce_to_whnf(&mut value).unwrap();
+        assert_eq!(value, Closure::World);
+        assert_eq!(*output.borrow(), [2, 1]);
+    }
+}
@@ -2,113 +2,510 @@
 // primops are strict (forces the arguments), others are lazy
 // run: run upto WHNF
 // reduce: reduce once
+// strategy: how the arguments of an unfolded supercombinator are passed
+// - call-by-need (the default): as shared thunks, so a parameter used twice points to the same
+//   node, which is reduced to WHNF once and updated in place
+// - call-by-name: as copies, each reduced on its own
+// - call-by-value: reduced to WHNF before the unfolding, then shared
+// CAFs: with caching on (off by default, see `Cafs`), a supercombinator without parameters is
+// expanded once into a shared thunk, which is reduced once and updated in place like a shared
+// argument
+// no Rust recursion: nodes waiting for a part to be reduced go on an explicit work stack, whose
+// size `Budget::memory_limit` bounds
 
 use crate::structures::*;
 use crate::error::*;
-use std::collections::VecDeque;
+use crate::interpreter::{Backend, Budget, Event, Observer, Outcome, Strategy};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ops::ControlFlow;
+use std::rc::Rc;
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Node {
-    head: Atom,
+    head: Head,
     stack: VecDeque<Node>,
 }
 
+#[derive(Debug, Clone)]
+enum Head {
+    Atom(Atom),
+    Thunk(Rc<RefCell<Node>>),
+}
+
 impl Node {
     pub fn from_sc(sc: usize) -> Self {
+        Self::atom(Atom::Sc(sc))
+    }
+
+    pub fn world() -> Self {
+        Self::atom(Atom::World)
+    }
+
+    pub fn prim(i: i64) -> Self {
+        Self::atom(Atom::Prim(i))
+    }
+
+    fn atom(atom: Atom) -> Self {
         Self {
-            head: Atom::Sc(sc),
+            head: Head::Atom(atom),
             stack: VecDeque::new(),
         }
     }
 
-    pub fn world() -> Self {
+    // a node that shares `self` with its copies; atoms and thunks are already cheap to copy
+    fn share(self) -> Self {
+        if self.stack.is_empty() {
+            return self;
+        }
         Self {
-            head: Atom::World,
+            head: Head::Thunk(Rc::new(RefCell::new(self))),
             stack: VecDeque::new(),
         }
     }
 
-    pub fn prim(i: i64) -> Self {
+    // what a thunk holds while its node is taken out to be reduced: the thunk itself
+    fn hole(cell: &Rc<RefCell<Node>>) -> Self {
         Self {
-            head: Atom::Prim(i),
+            head: Head::Thunk(cell.clone()),
             stack: VecDeque::new(),
         }
     }
 
-    fn substitute(expr: &ScExpr, args: &[Node]) -> Self {
-        let mut node = Node { head: Atom::Prim(0), stack: VecDeque::new() };
-        node.substitute_into(expr, args);
+    fn is_hole(cell: &Rc<RefCell<Node>>) -> bool {
+        matches!(&cell.borrow().head, Head::Thunk(inner) if Rc::ptr_eq(inner, cell))
+    }
+
+    fn into_parts(mut self) -> (Head, VecDeque<Node>) {
+        let head = std::mem::replace(&mut self.head, Head::Atom(Atom::World));
+        (head, std::mem::take(&mut self.stack))
+    }
+
+    // the head and the arguments, looking through thunks
+    pub fn spine(&self) -> (Atom, Vec<Node>) {
+        let (head, mut args) = match &self.head {
+            Head::Atom(atom) => (*atom, vec![]),
+            Head::Thunk(thunk) => thunk.borrow().spine(),
+        };
+        args.extend(self.stack.iter().cloned());
+        (head, args)
+    }
+
+    // the integer or World this node is, if it is one
+    fn prim_arg(&self) -> Option<Option<i64>> {
+        if !self.stack.is_empty() {
+            return None;
+        }
+        match &self.head {
+            Head::Atom(Atom::Prim(i)) => Some(Some(*i)),
+            Head::Atom(Atom::World) => Some(None),
+            Head::Atom(_) => None,
+            Head::Thunk(thunk) => thunk.borrow().prim_arg(),
+        }
+    }
+
+    // counts the new nodes in `nodes`
+    fn substitute(expr: &ScExpr, args: &[Node], nodes: &mut usize) -> Self {
+        let mut node = Node::prim(0);
+        *nodes += 1;
+        node.substitute_into(expr, args, nodes);
         node
     }
 
-    fn substitute_into(&mut self, expr: &ScExpr, args: &[Node]) {
+    fn substitute_into(&mut self, expr: &ScExpr, args: &[Node], nodes: &mut usize) {
         match expr {
             ScExpr::DefId(i) => {
-                self.head = Atom::Sc(*i);
+                self.head = Head::Atom(Atom::Sc(*i));
             }
             ScExpr::ArgId(i) => {
                 let cur = args[*i].clone();
-                let Node { head, stack } = cur;
+                let (head, stack) = cur.into_parts();
                 for node in stack.into_iter().rev() {
                     self.stack.push_front(node);
                 }
                 self.head = head;
             }
             ScExpr::Prim(i) => {
-                self.head = Atom::Prim(*i);
+                self.head = Head::Atom(Atom::Prim(*i));
             }
             ScExpr::App(e1, e2) => {
-                self.stack.push_front(Node::substitute(e2, args));
-                self.substitute_into(e1, args);
+                self.stack.push_front(Node::substitute(e2, args, nodes));
+                self.substitute_into(e1, args, nodes);
             }
         }
     }
 }
 
+// dropping a deep tree recursively would overflow the stack, so the parts are collected first
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut parts = vec![];
+        let mut node = self;
+        let mut owned;
+        loop {
+            parts.extend(node.stack.drain(..));
+            if let Head::Thunk(cell) = std::mem::replace(&mut node.head, Head::Atom(Atom::World)) {
+                // a thunk still shared elsewhere is left to its last owner
+                if let Ok(cell) = Rc::try_unwrap(cell) {
+                    parts.push(cell.into_inner());
+                }
+            }
+            match parts.pop() {
+                Some(part) => {
+                    owned = part;
+                    node = &mut owned;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+// the result of one reduction step at the root
+enum Step {
+    Reduced(ControlFlow<()>),
+    Whnf,
+    // the argument at this index must be in WHNF first
+    ForceArg(usize),
+    // the node shared by the head thunk must be in WHNF first
+    ForceThunk(Rc<RefCell<Node>>),
+}
+
+// a node whose reduction waits for one of its parts, which is taken out while it is reduced
+// the work stack holds these in place of Rust recursion
+enum Frame {
+    // the argument at `index` of `root`, a primop application or, by value, a supercombinator
+    // application
+    Arg { root: Node, index: usize },
+    // the node shared by `cell`, the head of `root`
+    Thunk { root: Node, cell: Rc<RefCell<Node>> },
+    // the argument at `index` of `root`, which is in WHNF and is being reduced to NF
+    Child { root: Node, index: usize },
+    // the same for an argument that is a thunk: the shared node is reduced to NF in its cell,
+    // so that the result stays shared and shallow to copy
+    Shared { root: Node, index: usize, cell: Rc<RefCell<Node>> },
+}
+
+impl Frame {
+    // push `self`, telling `observer` which supercombinator heads the waiting node
+    fn push(self, frames: &mut Vec<Frame>, observer: &mut dyn Observer<Node>) {
+        let (Frame::Arg { root, .. }
+        | Frame::Thunk { root, .. }
+        | Frame::Child { root, .. }
+        | Frame::Shared { root, .. }) = &self;
+        match root.head {
+            Head::Atom(Atom::Sc(i)) => observer.enter(Some(i)),
+            _ => observer.enter(None),
+        }
+        frames.push(self);
+    }
+}
+
+// what a reduction consults besides the program and the budget
+struct Context<'c> {
+    strategy: Strategy,
+    // the cache and the CAFs never cached
+    cafs: Option<(&'c mut Cafs, &'c HashSet<usize>)>,
+    observer: &'c mut dyn Observer<Node>,
+}
+
+impl<'c> Context<'c> {
+    fn new(strategy: Strategy, observer: &'c mut dyn Observer<Node>) -> Self {
+        Self { strategy, cafs: None, observer }
+    }
+}
+
 impl<'a> ScPrimProgram<'a> {
     pub fn reduce_to_nf(&mut self, root: &mut Node) -> Result<()> {
-        self.reduce_to_whnf(root)?;
-        for child in &mut root.stack {
-            self.reduce_to_nf(child)?;
-        }
+        self.reduce_to_nf_with(root, &mut Budget::unlimited())?;
         Ok(())
     }
 
     pub fn reduce_to_whnf(&mut self, root: &mut Node) -> Result<()> {
-        while self.reduce_head_once(root)? {}
+        self.reduce_to_whnf_with(root, &mut Budget::unlimited())?;
         Ok(())
     }
 
+    // reduce until done or until the budget runs out; the node stays valid either way, and
+    // calling again with a new budget resumes where it stopped
+    pub fn reduce_to_nf_with(&mut self, root: &mut Node, budget: &mut Budget) -> Result<Outcome> {
+        self.run(root, true, budget, &mut Context::new(Strategy::default(), &mut ()))
+    }
+
+    pub fn reduce_to_whnf_with(&mut self, root: &mut Node, budget: &mut Budget) -> Result<Outcome> {
+        self.run(root, false, budget, &mut Context::new(Strategy::default(), &mut ()))
+    }
+
+    // also choose the strategy and report every step to `observer`, which may pause the reduction
+    pub fn reduce_to_nf_observed(
+        &mut self,
+        root: &mut Node,
+        strategy: Strategy,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Node>,
+    ) -> Result<Outcome> {
+        self.run(root, true, budget, &mut Context::new(strategy, observer))
+    }
+
+    pub fn reduce_to_whnf_observed(
+        &mut self,
+        root: &mut Node,
+        strategy: Strategy,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Node>,
+    ) -> Result<Outcome> {
+        self.run(root, false, budget, &mut Context::new(strategy, observer))
+    }
+
+    // reduce once, possibly inside an argument that has to be forced first
     pub fn reduce_head_once(&mut self, root: &mut Node) -> Result<bool> {
-        match root.head {
+        let mut budget = Budget::fuel(1);
+        self.run(root, false, &mut budget, &mut Context::new(Strategy::default(), &mut ()))?;
+        Ok(budget.fuel == Some(0))
+    }
+
+    fn run(
+        &mut self,
+        root: &mut Node,
+        nf: bool,
+        budget: &mut Budget,
+        ctx: &mut Context,
+    ) -> Result<Outcome> {
+        let mut cur = std::mem::replace(root, Node::prim(0));
+        let mut frames = vec![];
+        let result = self.run_frames(&mut cur, &mut frames, nf, budget, ctx);
+        // put every part taken out back into its place, so that `root` can be resumed
+        while let Some(frame) = frames.pop() {
+            ctx.observer.leave();
+            cur = match frame {
+                Frame::Arg { mut root, index } | Frame::Child { mut root, index } => {
+                    root.stack[index] = cur;
+                    root
+                }
+                Frame::Thunk { root, cell } | Frame::Shared { root, cell, .. } => {
+                    *cell.borrow_mut() = cur;
+                    root
+                }
+            };
+        }
+        *root = cur;
+        result
+    }
+
+    fn run_frames(
+        &mut self,
+        cur: &mut Node,
+        frames: &mut Vec<Frame>,
+        nf: bool,
+        budget: &mut Budget,
+        ctx: &mut Context,
+    ) -> Result<Outcome> {
+        loop {
+            if let Some(outcome) = budget.stop() {
+                return Ok(outcome);
+            }
+            match self.step(cur, ctx)? {
+                Step::Reduced(flow) => {
+                    budget.consume();
+                    if flow.is_break() {
+                        return Ok(Outcome::Paused);
+                    }
+                }
+                Step::ForceArg(index) => {
+                    budget.check_memory(frames.len() + 1)?;
+                    let arg = std::mem::replace(&mut cur.stack[index], Node::prim(0));
+                    let root = std::mem::replace(cur, arg);
+                    Frame::Arg { root, index }.push(frames, ctx.observer);
+                }
+                Step::ForceThunk(cell) => {
+                    budget.check_memory(frames.len() + 1)?;
+                    let node = cell.replace(Node::hole(&cell));
+                    let root = std::mem::replace(cur, node);
+                    Frame::Thunk { root, cell }.push(frames, ctx.observer);
+                }
+                Step::Whnf => {
+                    let to_nf = match frames.last() {
+                        Some(Frame::Child { .. } | Frame::Shared { .. }) => true,
+                        Some(_) => false,
+                        None => nf,
+                    };
+                    if to_nf && !cur.stack.is_empty() {
+                        self.enter_child(cur, frames, 0, budget, ctx.observer)?;
+                        continue;
+                    }
+                    // `cur` is done; hand it back to the frames waiting for it
+                    loop {
+                        let frame = frames.pop();
+                        if frame.is_some() {
+                            ctx.observer.leave();
+                        }
+                        match frame {
+                            None => return Ok(Outcome::Done),
+                            Some(Frame::Arg { root, index }) => {
+                                let arg = std::mem::replace(cur, root);
+                                cur.stack[index] = arg;
+                                break;
+                            }
+                            Some(Frame::Thunk { root, cell }) => {
+                                let mut node = std::mem::replace(cur, root);
+                                let mut nodes = node.stack.len();
+                                for arg in node.stack.iter_mut() {
+                                    nodes += !arg.stack.is_empty() as usize;
+                                    *arg = std::mem::replace(arg, Node::prim(0)).share();
+                                }
+                                ctx.observer.alloc(nodes);
+                                for arg in node.stack.iter().rev() {
+                                    cur.stack.push_front(arg.clone());
+                                }
+                                cur.head = node.head.clone();
+                                *cell.borrow_mut() = node;
+                                budget.consume();
+                                break;
+                            }
+                            Some(Frame::Child { root, index }) => {
+                                let arg = std::mem::replace(cur, root);
+                                cur.stack[index] = arg;
+                                if index + 1 < cur.stack.len() {
+                                    self.enter_child(cur, frames, index + 1, budget, ctx.observer)?;
+                                    break;
+                                }
+                            }
+                            Some(Frame::Shared { root, index, cell }) => {
+                                *cell.borrow_mut() = std::mem::replace(cur, root);
+                                if index + 1 < cur.stack.len() {
+                                    self.enter_child(cur, frames, index + 1, budget, ctx.observer)?;
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn enter_child(
+        &mut self,
+        cur: &mut Node,
+        frames: &mut Vec<Frame>,
+        index: usize,
+        budget: &Budget,
+        observer: &mut dyn Observer<Node>,
+    ) -> Result<()> {
+        budget.check_memory(frames.len() + 1)?;
+        let child = &mut cur.stack[index];
+        match &child.head {
+            Head::Thunk(cell) if child.stack.is_empty() => {
+                let cell = cell.clone();
+                let root = std::mem::replace(cur, cell.replace(Node::hole(&cell)));
+                Frame::Shared { root, index, cell }.push(frames, observer);
+            }
+            _ => {
+                let child = std::mem::replace(child, Node::prim(0));
+                let root = std::mem::replace(cur, child);
+                Frame::Child { root, index }.push(frames, observer);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_whnf(&self, node: &Node) -> bool {
+        match node.head {
+            Head::Atom(Atom::Sc(i)) => node.stack.len() < self.defs[i].params,
+            Head::Atom(Atom::IoRes(_)) => node.stack.is_empty(),
+            Head::Atom(Atom::Prim(_) | Atom::World) => true,
+            Head::Thunk(_) => false,
+        }
+    }
+
+    fn step(&mut self, root: &mut Node, ctx: &mut Context) -> Result<Step> {
+        let head = match &root.head {
+            Head::Atom(atom) => *atom,
+            // a cached CAF needing its own value never reaches WHNF; spin, using up the fuel
+            Head::Thunk(thunk) if Node::is_hole(thunk) => {
+                return Ok(Step::Reduced(ControlFlow::Continue(())))
+            }
+            Head::Thunk(thunk) => return Ok(Step::ForceThunk(thunk.clone())),
+        };
+        match head {
             Atom::Sc(i) => {
+                let def = &self.defs[i];
+                if ctx.strategy == Strategy::ByValue
+                    && matches!(def.body, ScBody::Body(_))
+                    && root.stack.len() >= def.params
+                {
+                    // by value, whnf the arguments first
+                    if let Some(index) =
+                        (0..def.params).find(|&index| !self.is_whnf(&root.stack[index]))
+                    {
+                        return Ok(Step::ForceArg(index));
+                    }
+                }
                 let ScPrimDef { params, ref mut body, .. } = self.defs[i];
+                if let (0, ScBody::Body(body), Some((cafs, excluded))) =
+                    (params, &body, &mut ctx.cafs)
+                {
+                    if let Some(cell) = cafs.cells.get(&i) {
+                        // a CAF re-entered while it is reduced never reaches WHNF, so it is
+                        // expanded again below, diverging as it would without caching
+                        if !Node::is_hole(cell) {
+                            root.head = Head::Thunk(cell.clone());
+                            return Ok(Step::Reduced(ControlFlow::Continue(())));
+                        }
+                    } else if !excluded.contains(&i) {
+                        let mut nodes = 0;
+                        let cell = Rc::new(RefCell::new(Node::substitute(body, &[], &mut nodes)));
+                        cafs.cells.insert(i, cell.clone());
+                        root.head = Head::Thunk(cell);
+                        ctx.observer.alloc(nodes + 1);
+                        let flow = ctx.observer.observe(Event::Unfold {
+                            sc: i,
+                            args: &[],
+                            term: root,
+                        });
+                        return Ok(Step::Reduced(flow));
+                    }
+                }
                 if root.stack.len() >= params {
                     match body {
                         ScBody::Body(body) => {
                             // if sc, reduce using its body
                             let mut args = vec![];
+                            let mut nodes = 0;
                             for _ in 0..params {
-                                args.push(root.stack.pop_front().unwrap());
+                                let arg = root.stack.pop_front().unwrap();
+                                if ctx.strategy == Strategy::ByName {
+                                    args.push(arg);
+                                    continue;
+                                }
+                                nodes += !arg.stack.is_empty() as usize;
+                                args.push(arg.share());
                             }
-                            let node = Node::substitute(body, &args);
-                            let Node { head, stack } = node;
+                            let node = Node::substitute(body, &args, &mut nodes);
+                            ctx.observer.alloc(nodes);
+                            let (head, stack) = node.into_parts();
                             for node in stack.into_iter().rev() {
                                 root.stack.push_front(node);
                             }
                             root.head = head;
-                            Ok(true)
+                            let flow = ctx.observer.observe(Event::Unfold {
+                                sc: i,
+                                args: &args,
+                                term: root,
+                            });
+                            Ok(Step::Reduced(flow))
                         }
                         ScBody::Prim(_) => {
                             // if primop, whnf its arguments first, check all args are prim without args, and
                             // reduce using the given primop
                             let mut prim_arg = vec![];
-                            for arg in root.stack.iter_mut().take(params) {
-                                self.reduce_to_whnf(arg)?;
-                                if let (Atom::Prim(i), true) = (&arg.head, arg.stack.is_empty()) {
-                                    prim_arg.push(*i);
-                                } else if let (Atom::World, true) = (&arg.head, arg.stack.is_empty()) {
+                            for (index, arg) in root.stack.iter().take(params).enumerate() {
+                                if let Some(Some(i)) = arg.prim_arg() {
+                                    prim_arg.push(i);
+                                } else if let Some(None) = arg.prim_arg() {
                                     // ignore World
+                                } else if !self.is_whnf(arg) {
+                                    return Ok(Step::ForceArg(index));
                                 } else {
                                     let prim_name = self.defs[i].name.to_string();
                                     let arg = self.whnf_to_string(arg);
@@ -120,47 +517,450 @@ impl<'a> ScPrimProgram<'a> {
                                 _ => unreachable!()
                             };
                             let Some(result) = (prim)(&prim_arg) else {
-                                return Err(Error::PrimopFailure { def_name: self.defs[i].name.to_string(), arg: format!("{:?}", prim_arg) });
+                                return Err(Error::PrimopFailure {
+                                    def_name: self.defs[i].name.to_string(),
+                                    arg: format!("{:?}", prim_arg),
+                                });
                             };
                             for _ in 0..params {
                                 root.stack.pop_front();
                             }
-                            root.head = result;
-                            Ok(true)
+                            root.head = Head::Atom(result);
+                            let flow = ctx.observer.observe(Event::Prim {
+                                sc: i,
+                                args: &prim_arg,
+                                result,
+                            });
+                            Ok(Step::Reduced(flow))
                         }
                     }
                 } else {
                     // unable to reduce head
-                    Ok(false)
+                    Ok(Step::Whnf)
                 }
             }
-            Atom::Prim(_) => Ok(false),
+            Atom::Prim(_) => Ok(Step::Whnf),
             Atom::IoRes(i) => {
                 if let Some(f) = root.stack.pop_front() {
                     root.stack.push_front(Node::world());
                     root.stack.push_front(Node::prim(i));
-                    let Node { head, stack } = f;
+                    ctx.observer.alloc(2);
+                    let (head, stack) = f.into_parts();
                     for f_node in stack.into_iter().rev() {
                         root.stack.push_front(f_node);
                     }
                     root.head = head;
-                    Ok(true)
+                    let flow = ctx.observer.observe(Event::Io { value: i, term: root });
+                    Ok(Step::Reduced(flow))
                 } else {
-                    Ok(false)
+                    Ok(Step::Whnf)
                 }
             },
-            Atom::World => Ok(false),
+            Atom::World => Ok(Step::Whnf),
         }
     }
 
-    fn whnf_to_string(&self, node: &Node) -> String {
-        let head = match node.head {
+    pub fn whnf_to_string(&self, node: &Node) -> String {
+        let (head, args) = node.spine();
+        let head = match head {
             Atom::Sc(i) => self.defs[i].name.to_string(),
             Atom::Prim(i) => i.to_string(),
             Atom::IoRes(i) => format!("IORes#({})", i),
             Atom::World => "World#".to_string(),
         };
-        let body = " (..)".repeat(node.stack.len());
+        let body = " (..)".repeat(args.len());
         format!("{}{}", head, body)
     }
 }
+
+// the cached CAFs of a program
+// a cached CAF keeps its value, and whatever that value holds, alive until it is evicted
+#[derive(Default)]
+pub struct Cafs {
+    cells: HashMap<usize, Rc<RefCell<Node>>>,
+}
+
+// the tree reducer as a backend
+pub struct TreeReducer<'a> {
+    program: ScPrimProgram<'a>,
+    strategy: Strategy,
+    // `None` if CAFs are not cached
+    cafs: Option<Cafs>,
+    // CAFs never cached, expanded again at each use; kept while caching is off
+    caf_excluded: HashSet<usize>,
+}
+
+impl TreeReducer<'_> {
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    // caching is off by default; turning it off also evicts every cached CAF
+    pub fn with_caf_caching(mut self, on: bool) -> Self {
+        self.cafs = match (on, self.cafs.take()) {
+            (true, cafs) => Some(cafs.unwrap_or_default()),
+            (false, _) => None,
+        };
+        self
+    }
+
+    // do not cache the CAFs that may call one of the `primops`, directly or through other
+    // definitions, so that effects like reading input happen at each use
+    pub fn exclude_effectful_cafs(&mut self, primops: &[&str]) -> Result<()> {
+        let mut effectful = HashSet::new();
+        for name in primops {
+            effectful.insert(self.def_index(name)?);
+        }
+        loop {
+            let before = effectful.len();
+            for (i, def) in self.program.defs.iter().enumerate() {
+                if let ScBody::Body(body) = &def.body {
+                    if body.uses_any(&effectful) {
+                        effectful.insert(i);
+                    }
+                }
+            }
+            if effectful.len() == before {
+                break;
+            }
+        }
+        if let Some(cafs) = &mut self.cafs {
+            cafs.cells.retain(|i, _| !effectful.contains(i));
+        }
+        self.caf_excluded.extend(effectful);
+        Ok(())
+    }
+
+    // forget the value of the CAF called `name`; whether it was cached
+    pub fn evict_caf(&mut self, name: &str) -> Result<bool> {
+        let sc = self.def_index(name)?;
+        Ok(self.cafs.as_mut().is_some_and(|cafs| cafs.cells.remove(&sc).is_some()))
+    }
+
+    // forget the values of every CAF
+    pub fn reset_cafs(&mut self) {
+        if let Some(cafs) = &mut self.cafs {
+            cafs.cells.clear();
+        }
+    }
+
+    pub fn cached_cafs(&self) -> usize {
+        self.cafs.as_ref().map_or(0, |cafs| cafs.cells.len())
+    }
+}
+
+impl ScExpr {
+    fn uses_any(&self, defs: &HashSet<usize>) -> bool {
+        match self {
+            ScExpr::DefId(i) => defs.contains(i),
+            ScExpr::App(e1, e2) => e1.uses_any(defs) || e2.uses_any(defs),
+            ScExpr::ArgId(_) | ScExpr::Prim(_) => false,
+        }
+    }
+}
+
+impl<'a> Backend<'a> for TreeReducer<'a> {
+    type Value = Node;
+
+    fn new(program: ScPrimProgram<'a>) -> Self {
+        Self {
+            program,
+            strategy: Strategy::default(),
+            cafs: None,
+            caf_excluded: HashSet::new(),
+        }
+    }
+
+    fn program(&self) -> &ScPrimProgram<'a> {
+        &self.program
+    }
+
+    fn sc(&mut self, sc: usize) -> Node {
+        Node::from_sc(sc)
+    }
+
+    fn prim(&mut self, i: i64) -> Node {
+        Node::prim(i)
+    }
+
+    fn apply(&mut self, mut fun: Node, args: Vec<Node>) -> Node {
+        fun.stack.extend(args);
+        fun
+    }
+
+    fn reduce_to_whnf_observed(
+        &mut self,
+        value: &mut Node,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Node>,
+    ) -> Result<Outcome> {
+        let mut ctx = Context {
+            strategy: self.strategy,
+            cafs: self.cafs.as_mut().map(|cafs| (cafs, &self.caf_excluded)),
+            observer,
+        };
+        self.program.run(value, false, budget, &mut ctx)
+    }
+
+    fn reduce_to_nf_observed(
+        &mut self,
+        value: &mut Node,
+        budget: &mut Budget,
+        observer: &mut dyn Observer<Node>,
+    ) -> Result<Outcome> {
+        let mut ctx = Context {
+            strategy: self.strategy,
+            cafs: self.cafs.as_mut().map(|cafs| (cafs, &self.caf_excluded)),
+            observer,
+        };
+        self.program.run(value, true, budget, &mut ctx)
+    }
+
+    fn spine(&self, value: &Node) -> (Atom, Vec<Node>) {
+        value.spine()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::profile::Profile;
+    use crate::library::{compile_with_prelude, prelude_primops};
+    use crate::parser::parse_program;
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn shares_arguments() {
+        // each argument is used twice, so without sharing TICK would run 2^3 times
+        let src = "
+            #TICK x;
+            #ADD x y;
+            double x = ADD x x;
+            main = double (double (double (TICK 1)));
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let ticks = Cell::new(0);
+        let mut reducer = TreeReducer::load(sc, |_| {
+            let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+            primops.insert(
+                "TICK",
+                Box::new(|a: &[i64]| {
+                    ticks.set(ticks.get() + 1);
+                    Some(Atom::Prim(a[0]))
+                }),
+            );
+            primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+            primops
+        })
+        .unwrap();
+        let value = reducer.run_to_whnf("main").unwrap();
+        assert_eq!(reducer.whnf_to_string(&value), "8");
+        assert_eq!(ticks.get(), 1);
+    }
+
+    #[test]
+    fn runs_entries_by_name() {
+        let main = "
+            import Prelude;
+            main = sum (map (λx. MUL x x) (range 1 4));
+            list = Cons 1 (Cons 2 Nil);
+        ";
+        let sc = compile_with_prelude(main);
+        let mut reducer = TreeReducer::load(sc, prelude_primops).unwrap();
+        let value = reducer.run_to_whnf("main").unwrap();
+        assert_eq!(reducer.spine(&value).0, Atom::Prim(30));
+        let value = reducer.run_to_nf("list").unwrap();
+        assert_eq!(reducer.whnf_to_string(&value), "Prelude.Cons (..) (..)");
+        let (_, args) = reducer.spine(&value);
+        assert_eq!(reducer.whnf_to_string(&args[0]), "1");
+        let error = reducer.entry("man").unwrap_err();
+        assert!(matches!(
+            error,
+            Error::UnknownEntry { suggestion: Some(name), .. } if name == "main"
+        ));
+    }
+
+    #[test]
+    fn reduces_deep_terms_without_recursion() {
+        // each `count` waits for the next one inside an `ADD`, and the result is a long list
+        let main = "
+            import Prelude;
+            count n = EQ n 0 0 (ADD 1 (count (SUB n 1)));
+            main = count 10000;
+            list = take 10000 (repeat 1);
+        ";
+        let sc = compile_with_prelude(main);
+        let mut reducer = TreeReducer::load(sc, prelude_primops).unwrap();
+        let value = reducer.run_to_whnf("main").unwrap();
+        assert_eq!(reducer.whnf_to_string(&value), "10000");
+        let value = reducer.run_to_nf("list").unwrap();
+        assert_eq!(reducer.whnf_to_string(&value), "Prelude.Cons (..) (..)");
+        drop(value);
+
+        let mut value = reducer.entry("main").unwrap();
+        let mut budget = Budget::unlimited().with_memory_limit(1000);
+        let error = reducer.reduce_to_whnf_with(&mut value, &mut budget).unwrap_err();
+        assert!(matches!(error, Error::MemoryLimit { limit: 1000 }));
+    }
+
+    #[test]
+    fn stops_and_resumes_on_budget() {
+        let main = "
+            import Prelude;
+            main = sum (range 1 100);
+            loop n = loop (ADD n 1);
+            diverge = loop 0;
+        ";
+        let sc = compile_with_prelude(main);
+        let mut reducer = TreeReducer::load(sc, prelude_primops).unwrap();
+        let mut value = reducer.entry("diverge").unwrap();
+        let outcome = reducer.reduce_to_whnf_with(&mut value, &mut Budget::fuel(1000));
+        assert_eq!(outcome.unwrap(), Outcome::OutOfFuel);
+
+        // feed the reduction a little fuel at a time until it finishes
+        let mut value = reducer.entry("main").unwrap();
+        let mut rounds = 0;
+        while reducer
+            .reduce_to_whnf_with(&mut value, &mut Budget::fuel(10))
+            .unwrap()
+            != Outcome::Done
+        {
+            rounds += 1;
+        }
+        assert!(rounds > 1);
+        assert_eq!(reducer.whnf_to_string(&value), "5050");
+
+        let cancel = Arc::new(AtomicBool::new(true));
+        let mut budget = Budget::unlimited().with_cancel(cancel.clone());
+        let mut value = reducer.entry("main").unwrap();
+        let outcome = reducer.reduce_to_whnf_with(&mut value, &mut budget);
+        assert_eq!(outcome.unwrap(), Outcome::Cancelled);
+        cancel.store(false, Ordering::Relaxed);
+        let outcome = reducer.reduce_to_whnf_with(&mut value, &mut budget);
+        assert_eq!(outcome.unwrap(), Outcome::Done);
+        assert_eq!(reducer.whnf_to_string(&value), "5050");
+    }
+
+    #[test]
+    fn follows_the_chosen_strategy() {
+        let src = "
+            #TICK x;
+            #ADD x y;
+            double x = ADD x x;
+            const x y = x;
+            loop = loop;
+            main = double (TICK 1);
+            lazy = const 1 loop;
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let mut reducer = TreeReducer::load(sc, |_| {
+            let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+            primops.insert("TICK", Box::new(|a: &[i64]| Some(Atom::Prim(a[0]))));
+            primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+            primops
+        })
+        .unwrap();
+        let tick = reducer.program().def_indexes()["TICK"];
+        let strategies = [Strategy::ByName, Strategy::ByNeed, Strategy::ByValue];
+        let mut ticks = vec![];
+        let mut lazy = vec![];
+        for strategy in strategies {
+            reducer.set_strategy(strategy);
+            let mut value = reducer.entry("main").unwrap();
+            let mut profile = Profile::new();
+            reducer
+                .reduce_to_whnf_observed(&mut value, &mut Budget::unlimited(), &mut profile)
+                .unwrap();
+            assert_eq!(reducer.whnf_to_string(&value), "2");
+            ticks.push(profile.prim_calls[&tick]);
+            let mut value = reducer.entry("lazy").unwrap();
+            lazy.push(reducer.reduce_to_whnf_with(&mut value, &mut Budget::fuel(1000)).unwrap());
+        }
+        assert_eq!(ticks, [2, 1, 1]);
+        assert_eq!(lazy, [Outcome::Done, Outcome::Done, Outcome::OutOfFuel]);
+    }
+
+    #[test]
+    fn caches_cafs_unless_effectful() {
+        let src = "
+            #TICK x;
+            #READ x;
+            #ADD x y;
+            ticked = TICK 1;
+            read = READ 0;
+            main = ADD ticked (ADD ticked ticked);
+            both = ADD read read;
+            loop = loop;
+        ";
+        let sc = parse_program(src)
+            .unwrap()
+            .into_anon()
+            .unwrap()
+            .lambda_lift()
+            .lambda_elim()
+            .unwrap()
+            .compress();
+        let mut reducer = TreeReducer::load(sc, |_| {
+            let mut primops: HashMap<&'static str, Primop> = HashMap::new();
+            primops.insert("TICK", Box::new(|a: &[i64]| Some(Atom::Prim(a[0]))));
+            primops.insert("READ", Box::new(|a: &[i64]| Some(Atom::Prim(a[0]))));
+            primops.insert("ADD", Box::new(|a: &[i64]| Some(Atom::Prim(a[0] + a[1]))));
+            primops
+        })
+        .unwrap();
+        // exclusions made while caching is off still apply once it is on
+        reducer.exclude_effectful_cafs(&["READ"]).unwrap();
+        let mut reducer = reducer.with_caf_caching(true);
+        let tick = reducer.program().def_indexes()["TICK"];
+        let read = reducer.program().def_indexes()["READ"];
+        let mut profile = Profile::new();
+        for name in ["main", "both"] {
+            let mut value = reducer.entry(name).unwrap();
+            reducer
+                .reduce_to_whnf_observed(&mut value, &mut Budget::unlimited(), &mut profile)
+                .unwrap();
+        }
+        assert_eq!(
+            (profile.prim_calls[&tick], profile.prim_calls[&read]),
+            (1, 2)
+        );
+        assert_eq!(reducer.cached_cafs(), 2);
+        assert!(reducer.evict_caf("ticked").unwrap());
+        assert!(!reducer.evict_caf("read").unwrap());
+        reducer.reset_cafs();
+        assert_eq!(reducer.cached_cafs(), 0);
+        let mut value = reducer.entry("loop").unwrap();
+        assert_eq!(
+            reducer
+                .reduce_to_whnf_with(&mut value, &mut Budget::fuel(1000))
+                .unwrap(),
+            Outcome::OutOfFuel
+        );
+    }
+}
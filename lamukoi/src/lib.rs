@@ -1,5 +1,10 @@
+pub mod blc;
 pub mod compiler;
+pub mod diagnostic;
 pub mod error;
 pub mod interpreter;
+pub mod library;
+pub mod parser;
 pub mod structures;
 pub mod transform;
+pub mod typecheck;
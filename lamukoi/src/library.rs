@@ -0,0 +1,58 @@
+// modules shipped with the crate, written in the surface syntax
+// prelude: combinators, `Bool`, `Pair`, `Maybe` and Scott-encoded `List` with the usual functions,
+// over the arithmetic and comparison primops implemented by `prelude_primops`
+
+use crate::parser::parse_module;
+use crate::structures::*;
+use std::collections::HashMap;
+
+pub const PRELUDE_SOURCE: &str = include_str!("library/prelude.lmk");
+
+pub fn prelude() -> Module {
+    parse_module(PRELUDE_SOURCE).expect("the bundled prelude is well-formed")
+}
+
+// `table` is the `def_indexes` of the linked program, used to find `Prelude.True` and `Prelude.False`
+pub fn prelude_primops<'a>(table: &HashMap<&str, usize>) -> HashMap<&'static str, Primop<'a>> {
+    let (t, f) = (table["Prelude.True"], table["Prelude.False"]);
+    let bool = move |b: bool| Some(Atom::Sc(if b { t } else { f }));
+    let mut primops: HashMap<&'static str, Primop<'a>> = HashMap::new();
+    primops.insert(
+        "ADD",
+        Box::new(|a: &[i64]| Some(Atom::Prim(a[0].wrapping_add(a[1])))),
+    );
+    primops.insert(
+        "SUB",
+        Box::new(|a: &[i64]| Some(Atom::Prim(a[0].wrapping_sub(a[1])))),
+    );
+    primops.insert(
+        "MUL",
+        Box::new(|a: &[i64]| Some(Atom::Prim(a[0].wrapping_mul(a[1])))),
+    );
+    primops.insert(
+        "DIV",
+        Box::new(|a: &[i64]| a[0].checked_div(a[1]).map(Atom::Prim)),
+    );
+    primops.insert(
+        "MOD",
+        Box::new(|a: &[i64]| a[0].checked_rem(a[1]).map(Atom::Prim)),
+    );
+    primops.insert("EQ", Box::new(move |a: &[i64]| bool(a[0] == a[1])));
+    primops.insert("LT", Box::new(move |a: &[i64]| bool(a[0] < a[1])));
+    primops
+}
+
+// compiles a test module against the prelude, down to supercombinators
+#[cfg(test)]
+pub(crate) fn compile_with_prelude(main: &str) -> ScProgram {
+    parse_module(main)
+        .unwrap()
+        .link(vec![prelude()])
+        .unwrap()
+        .into_anon()
+        .unwrap()
+        .lambda_lift()
+        .lambda_elim()
+        .unwrap()
+        .compress()
+}
@@ -0,0 +1,11 @@
+// text frontend
+// ir: parses the Display formats of `AnonProgram` and `ScProgram` back
+// lexer: turns source text into tokens with byte positions
+// surface: parses the same syntax accepted by `program!`/`lambda!`/`expr!` into `Program`, and module files into `Module`
+
+pub mod ir;
+mod lexer;
+pub mod surface;
+
+pub use ir::{parse_anon_program, parse_sc_program};
+pub use surface::{parse_expr, parse_file, parse_module, parse_module_file, parse_program, parse_type};
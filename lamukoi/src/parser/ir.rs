@@ -0,0 +1,348 @@
+// parsers for the Display formats of AnonProgram and ScProgram, one definition per line:
+// `name x0 x1 = body`, `name x0 x1 = <builtin>` or, for a match-failure primitive, `name = <no match>`
+// in a body, `x3` is parameter 3, `i-7` the integer -7, `?4` the unnamed definition 4, and
+// (AnonProgram only) `v2` the variable bound by `λv2.`, lambdas being numbered by depth
+// any other word refers to the definition of that name, so names that look like `x0`, `v0`,
+// `i0` or `?0` do not survive a round trip
+// spans are byte offsets into the whole text
+
+use crate::error::{Error, Result};
+use crate::structures::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+struct Defs {
+    named: HashMap<String, usize>,
+    unnamed: HashMap<usize, usize>,
+}
+
+struct Line<'a> {
+    src: &'a str,
+    pos: usize,
+    defs: &'a Defs,
+    params: usize,
+    lambdas: bool,
+}
+
+fn number(digits: &str) -> Option<usize> {
+    if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+        digits.parse().ok()
+    } else {
+        None
+    }
+}
+
+impl<'a> Line<'a> {
+    fn error(&self, message: impl Into<String>, start: usize) -> Error {
+        Error::Syntax {
+            message: message.into(),
+            span: Span::new(start, self.pos.max(start + 1).min(self.src.len())),
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = &self.src[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn word(&mut self) -> (&'a str, usize) {
+        self.skip_spaces();
+        let start = self.pos;
+        let rest = &self.src[start..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == '(' || c == ')')
+            .unwrap_or(rest.len());
+        self.pos += len;
+        (&rest[..len], start)
+    }
+
+    fn expr(&mut self, depth: usize) -> Result<AnonExpr> {
+        self.skip_spaces();
+        if self.peek() == Some('λ') {
+            return self.lambda(depth);
+        }
+        let mut expr = self.atom(depth)?;
+        loop {
+            self.skip_spaces();
+            let arg = match self.peek() {
+                None | Some(')') => return Ok(expr),
+                Some('λ') => self.lambda(depth)?,
+                Some(_) => self.atom(depth)?,
+            };
+            expr = AnonExpr::App(Box::new(expr), Box::new(arg));
+        }
+    }
+
+    fn lambda(&mut self, depth: usize) -> Result<AnonExpr> {
+        let start = self.pos;
+        self.pos += 'λ'.len_utf8();
+        if !self.lambdas {
+            return Err(self.error("unexpected lambda in a supercombinator", start));
+        }
+        let (binder, binder_start) = self.word();
+        let level = binder
+            .strip_prefix('v')
+            .and_then(|binder| binder.strip_suffix('.'))
+            .and_then(number);
+        if level != Some(depth) {
+            return Err(self.error(format!("expected `λv{}.`", depth), binder_start));
+        }
+        Ok(AnonExpr::Lam(Box::new(self.expr(depth + 1)?)))
+    }
+
+    fn atom(&mut self, depth: usize) -> Result<AnonExpr> {
+        self.skip_spaces();
+        let start = self.pos;
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            let expr = self.expr(depth)?;
+            if self.peek() != Some(')') {
+                return Err(self.error("expected `)`", self.pos));
+            }
+            self.pos += 1;
+            return Ok(expr);
+        }
+        let (word, _) = self.word();
+        if word.is_empty() {
+            return Err(self.error("expected expression", start));
+        }
+        if let Some(i) = word.strip_prefix('x').and_then(number) {
+            if i >= self.params {
+                return Err(self.error(format!("`{}` is not a parameter", word), start));
+            }
+            return Ok(AnonExpr::ArgId(i));
+        }
+        if let Some(level) = word.strip_prefix('v').and_then(number) {
+            if self.lambdas {
+                if level >= depth {
+                    return Err(self.error(format!("`{}` is not bound", word), start));
+                }
+                return Ok(AnonExpr::DeBruijn(depth - 1 - level));
+            }
+        }
+        if let Some(int) = word.strip_prefix('i') {
+            let digits = int.strip_prefix('-').unwrap_or(int);
+            if number(digits).is_some() {
+                let int = int
+                    .parse()
+                    .map_err(|_| self.error("integer out of range", start))?;
+                return Ok(AnonExpr::Prim(int));
+            }
+        }
+        let def = match word.strip_prefix('?').and_then(number) {
+            Some(i) => self.defs.unnamed.get(&i),
+            None => self.defs.named.get(word),
+        };
+        match def {
+            Some(&def) => Ok(AnonExpr::DefId(def)),
+            None => Err(self.error(format!("unknown definition `{}`", word), start)),
+        }
+    }
+}
+
+fn def_name(word: &str) -> Name {
+    match word.strip_prefix('?').and_then(number) {
+        Some(i) => Name::Unnamed(i),
+        None => Name::Named(word.to_string()),
+    }
+}
+
+// the definition on each line; `lambdas` allows `λ` and `v` variables
+fn parse_defs(src: &str, lambdas: bool) -> Result<Vec<AnonDef>> {
+    let mut lines = vec![];
+    let mut offset = 0;
+    for line in src.split('\n') {
+        if !line.trim().is_empty() {
+            lines.push((offset, line));
+        }
+        offset += line.len() + 1;
+    }
+    let mut defs = Defs {
+        named: HashMap::new(),
+        unnamed: HashMap::new(),
+    };
+    let mut spans: Vec<Span> = vec![];
+    for (i, &(offset, line)) in lines.iter().enumerate() {
+        let word = line.split_whitespace().next().unwrap();
+        let start = offset + line.find(word).unwrap();
+        let span = Span::new(start, start + word.len());
+        let previous = match def_name(word) {
+            Name::Named(name) | Name::MatchFail(name) => defs.named.insert(name, i),
+            Name::Unnamed(id) => defs.unnamed.insert(id, i),
+        };
+        if let Some(previous) = previous {
+            return Err(Error::TopLevelNameCollision {
+                name: word.to_string(),
+                span: Some(span),
+                previous: Some(spans[previous]),
+            });
+        }
+        spans.push(span);
+    }
+    let mut out = vec![];
+    for ((offset, line), span) in lines.into_iter().zip(spans) {
+        let mut parser = Line {
+            src: &src[..offset + line.len()],
+            pos: offset,
+            defs: &defs,
+            params: 0,
+            lambdas,
+        };
+        let (name, _) = parser.word();
+        loop {
+            let (word, start) = parser.word();
+            if word == "=" {
+                break;
+            }
+            if word.strip_prefix('x').and_then(number) != Some(parser.params) {
+                let expected = format!("expected `x{}` or `=`", parser.params);
+                return Err(parser.error(expected, start));
+            }
+            parser.params += 1;
+        }
+        let mut name = def_name(name);
+        let rest = parser.src[parser.pos..].trim();
+        let body = if rest == "<builtin>" {
+            None
+        } else if let ("<no match>", Name::Named(named)) = (rest, &mut name) {
+            name = Name::MatchFail(std::mem::take(named));
+            None
+        } else {
+            let body = parser.expr(0)?;
+            if parser.pos < parser.src.len() {
+                return Err(parser.error("unexpected `)`", parser.pos));
+            }
+            Some(body)
+        };
+        out.push(AnonDef {
+            name,
+            params: parser.params,
+            body,
+            span: Some(span),
+        });
+    }
+    Ok(out)
+}
+
+fn into_sc(expr: AnonExpr) -> ScExpr {
+    match expr {
+        AnonExpr::DefId(i) => ScExpr::DefId(i),
+        AnonExpr::ArgId(i) => ScExpr::ArgId(i),
+        AnonExpr::Prim(i) => ScExpr::Prim(i),
+        AnonExpr::App(e1, e2) => ScExpr::App(Box::new(into_sc(*e1)), Box::new(into_sc(*e2))),
+        AnonExpr::DeBruijn(_) | AnonExpr::Lam(_) => unreachable!(),
+    }
+}
+
+pub fn parse_anon_program(src: &str) -> Result<AnonProgram> {
+    Ok(AnonProgram {
+        defs: parse_defs(src, true)?,
+    })
+}
+
+pub fn parse_sc_program(src: &str) -> Result<ScProgram> {
+    let defs = parse_defs(src, false)?;
+    Ok(ScProgram {
+        defs: defs
+            .into_iter()
+            .map(|def| ScDef {
+                name: def.name,
+                params: def.params,
+                body: def.body.map(into_sc),
+                span: def.span,
+            })
+            .collect(),
+    })
+}
+
+impl FromStr for AnonProgram {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_anon_program(s)
+    }
+}
+
+impl FromStr for ScProgram {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_sc_program(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::prelude;
+
+    #[test]
+    fn pass_outputs_round_trip() {
+        let main = "
+            import Prelude;
+            twice f x = f (f x);
+            main = twice (λx. ADD x -1) (sum (map (λy. MUL y y) (range 1 4)));
+        ";
+        let program = crate::parser::parse_module(main)
+            .unwrap()
+            .link(vec![prelude()])
+            .unwrap();
+        let anon = program.into_anon().unwrap();
+        assert_eq!(anon.to_string().parse::<AnonProgram>().unwrap(), anon);
+        let lifted = anon.lambda_lift();
+        assert_eq!(lifted.to_string().parse::<AnonProgram>().unwrap(), lifted);
+        let sc = lifted.lambda_elim().unwrap();
+        assert_eq!(sc.to_string().parse::<ScProgram>().unwrap(), sc);
+        let compressed = sc.compress();
+        let text = compressed.to_string();
+        assert_eq!(text.parse::<ScProgram>().unwrap(), compressed);
+    }
+
+    #[test]
+    fn parses_hand_written_supercombinators() {
+        let sc: ScProgram = "
+            K x0 x1 = x0
+            ADD x0 x1 = <builtin>
+            main = K (ADD i2 i-3) ?7
+            ?7 = main
+        "
+        .parse()
+        .unwrap();
+        assert_eq!(
+            sc.defs[2].body,
+            Some(ScExpr::App(
+                Box::new(ScExpr::App(
+                    Box::new(ScExpr::DefId(0)),
+                    Box::new(ScExpr::App(
+                        Box::new(ScExpr::App(
+                            Box::new(ScExpr::DefId(1)),
+                            Box::new(ScExpr::Prim(2))
+                        )),
+                        Box::new(ScExpr::Prim(-3))
+                    ))
+                )),
+                Box::new(ScExpr::DefId(3))
+            ))
+        );
+        let Err(Error::Syntax { span, .. }) = "f x0 = x1".parse::<ScProgram>() else {
+            panic!("expected a syntax error");
+        };
+        assert_eq!(span, Span::new(7, 9));
+        assert!("f = λv0. v0".parse::<ScProgram>().is_err());
+        // later passes report errors at the definition they come from
+        let anon: AnonProgram = "id = λv0. v0\nf = id (λv0. v0)".parse().unwrap();
+        let Err(Error::UnexpectedLambda { span, .. }) = anon.lambda_elim() else {
+            panic!("expected a lambda left by lambda lifting");
+        };
+        assert_eq!(span, Some(Span::new(14, 15)));
+        let sc: ScProgram = "main = ?0\n?0 = <builtin>".parse().unwrap();
+        let Err(Error::UnnamedPrimop { span, .. }) = sc.attach_prim(&mut HashMap::new()) else {
+            panic!("expected an unnamed primop");
+        };
+        assert_eq!(span, Some(Span::new(10, 12)));
+    }
+}
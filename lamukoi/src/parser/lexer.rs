@@ -0,0 +1,203 @@
+// tokens of the surface syntax
+// identifiers: alphabetic or `_`, followed by alphanumerics or `_` (`λ` is never part of an identifier)
+// qualified identifiers: capitalized module segments joined by `.` with no spaces, e.g. `Prelude.map`
+// integers: optional `-` followed by decimal digits
+// `let`, `letrec`, `in`, `data`, `case`, `of`, `module` and `import` are keywords
+// `//` starts a comment that runs to the end of the line
+
+use crate::error::{Error, Result};
+use crate::structures::Span;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Tok {
+    Ident(String),
+    Int(i64),
+    Hash,
+    Semi,
+    Eq,
+    Lambda,
+    Dot,
+    Comma,
+    Colon,
+    Bar,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Arrow,
+    Let,
+    LetRec,
+    In,
+    Data,
+    Case,
+    Of,
+    Module,
+    Import,
+    Eof,
+}
+
+impl Tok {
+    pub fn describe(&self) -> String {
+        match self {
+            Tok::Ident(ident) => format!("identifier `{}`", ident),
+            Tok::Int(i) => format!("integer `{}`", i),
+            Tok::Hash => "`#`".to_string(),
+            Tok::Semi => "`;`".to_string(),
+            Tok::Eq => "`=`".to_string(),
+            Tok::Lambda => "`λ`".to_string(),
+            Tok::Dot => "`.`".to_string(),
+            Tok::Comma => "`,`".to_string(),
+            Tok::Colon => "`:`".to_string(),
+            Tok::Bar => "`|`".to_string(),
+            Tok::LParen => "`(`".to_string(),
+            Tok::RParen => "`)`".to_string(),
+            Tok::LBrace => "`{`".to_string(),
+            Tok::RBrace => "`}`".to_string(),
+            Tok::Arrow => "`->`".to_string(),
+            Tok::Let => "`let`".to_string(),
+            Tok::LetRec => "`letrec`".to_string(),
+            Tok::In => "`in`".to_string(),
+            Tok::Data => "`data`".to_string(),
+            Tok::Case => "`case`".to_string(),
+            Tok::Of => "`of`".to_string(),
+            Tok::Module => "`module`".to_string(),
+            Tok::Import => "`import`".to_string(),
+            Tok::Eof => "end of input".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub tok: Tok,
+    pub span: Span,
+}
+
+fn is_ident_start(c: char) -> bool {
+    (c.is_alphabetic() && c != 'λ') || c == '_'
+}
+
+fn is_ident_continue(c: char) -> bool {
+    (c.is_alphanumeric() && c != 'λ') || c == '_'
+}
+
+// `Mod.name` continues an identifier only if the segment so far is capitalized
+fn continues_qualified(segment: &str, rest: &str) -> bool {
+    segment.starts_with(char::is_uppercase)
+        && rest.starts_with('.')
+        && rest[1..].starts_with(is_ident_start)
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = src.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if src[start..].starts_with("//") {
+            while let Some(&(_, c)) = chars.peek() {
+                if c == '\n' {
+                    break;
+                }
+                chars.next();
+            }
+            continue;
+        }
+        let single = match c {
+            '#' => Some(Tok::Hash),
+            ';' => Some(Tok::Semi),
+            '=' => Some(Tok::Eq),
+            'λ' | '\\' => Some(Tok::Lambda),
+            '.' => Some(Tok::Dot),
+            ',' => Some(Tok::Comma),
+            ':' => Some(Tok::Colon),
+            '|' => Some(Tok::Bar),
+            '(' => Some(Tok::LParen),
+            ')' => Some(Tok::RParen),
+            '{' => Some(Tok::LBrace),
+            '}' => Some(Tok::RBrace),
+            _ => None,
+        };
+        if let Some(tok) = single {
+            chars.next();
+            tokens.push(Token {
+                tok,
+                span: Span::new(start, start + c.len_utf8()),
+            });
+            continue;
+        }
+        if src[start..].starts_with("->") {
+            chars.next();
+            chars.next();
+            tokens.push(Token {
+                tok: Tok::Arrow,
+                span: Span::new(start, start + 2),
+            });
+            continue;
+        }
+        let negative = c == '-' && src[start + 1..].starts_with(|c: char| c.is_ascii_digit());
+        if c.is_ascii_digit() || negative {
+            chars.next();
+            let mut end = start + 1;
+            while let Some(&(i, c)) = chars.peek() {
+                if !c.is_ascii_digit() {
+                    break;
+                }
+                end = i + 1;
+                chars.next();
+            }
+            let Ok(int) = src[start..end].parse() else {
+                return Err(Error::Syntax {
+                    message: format!("integer literal `{}` is out of range", &src[start..end]),
+                    span: Span::new(start, end),
+                });
+            };
+            tokens.push(Token {
+                tok: Tok::Int(int),
+                span: Span::new(start, end),
+            });
+            continue;
+        }
+        if is_ident_start(c) {
+            chars.next();
+            let mut end = start + c.len_utf8();
+            let mut segment = start;
+            while let Some(&(i, c)) = chars.peek() {
+                if c == '.' && continues_qualified(&src[segment..end], &src[end..]) {
+                    segment = i + 1;
+                } else if !is_ident_continue(c) {
+                    break;
+                }
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let tok = match &src[start..end] {
+                "let" => Tok::Let,
+                "letrec" => Tok::LetRec,
+                "in" => Tok::In,
+                "data" => Tok::Data,
+                "case" => Tok::Case,
+                "of" => Tok::Of,
+                "module" => Tok::Module,
+                "import" => Tok::Import,
+                ident => Tok::Ident(ident.to_string()),
+            };
+            tokens.push(Token {
+                tok,
+                span: Span::new(start, end),
+            });
+            continue;
+        }
+        return Err(Error::Syntax {
+            message: format!("unexpected character `{}`", c),
+            span: Span::new(start, start + c.len_utf8()),
+        });
+    }
+    tokens.push(Token {
+        tok: Tok::Eof,
+        span: Span::new(src.len(), src.len()),
+    });
+    Ok(tokens)
+}
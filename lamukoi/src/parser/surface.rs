@@ -0,0 +1,683 @@
+// recursive descent parser for the surface syntax
+// module := (`module` ident names? `;`)? import* program
+// import := `import` `qualified`? ident (`as` ident)? names? (`hiding` names)? `;`
+// names := `(` (ident (`,` ident)*)? `)`
+// program := item*
+// item := `#` ident ident* (`:` type)? `;` | ident pattern_atom* `=` expr `;` | data `;`
+// type := type_atom (`->` type)?
+// type_atom := ident | `(` type `)`, where capitalized identifiers are base types like `Int`
+// consecutive equations of the same name form one pattern-matching definition if one of them has
+// a literal or constructor pattern; otherwise each is a plain definition, and repeating a name is
+// an error (equations that only match nullary constructors are grouped by `compile_matches`)
+// pattern_atom := ident | int | `(` pattern `)`
+// pattern := ident pattern_atom+ | pattern_atom
+// data := `data` ident `=` ctor (`|` ctor)*
+// ctor := ident ident*
+// expr := block | atom+ block?
+// block := lambda | let
+// lambda := (`λ` | `\`) ident+ `.` expr | `|` ident+ `|` expr
+// let := (`let` | `letrec`) binding (`;` binding)* `in` expr
+// binding := ident ident* `=` expr
+// atom := ident | int | `(` expr `)` | case
+// case := `case` expr `of` `{` arm (`;` arm)* `;`? `}`
+// arm := ident ident* `->` expr
+// a block extends as far to the right as possible, so it may only appear as the last argument
+
+use super::lexer::{tokenize, Tok, Token};
+use crate::error::{Error, Result};
+use crate::structures::*;
+use crate::transform::module_resolve::MAIN_MODULE;
+use std::path::Path;
+use std::str::FromStr;
+
+struct Clause {
+    name: Ident,
+    name_span: Span,
+    patterns: Vec<Pattern>,
+    pattern_spans: Vec<Span>,
+    lhs_span: Span,
+    body: Expr,
+}
+
+// clauses with variable patterns only are plain definitions
+fn group_clauses(clauses: Vec<Clause>) -> Vec<Item> {
+    let plain = clauses.iter().all(|clause| {
+        clause
+            .patterns
+            .iter()
+            .all(|pattern| matches!(pattern, Pattern::Var(_)))
+    });
+    if plain {
+        let plain_def = |clause: Clause| {
+            let params = clause
+                .patterns
+                .into_iter()
+                .map(|pattern| match pattern {
+                    Pattern::Var(ident) => ident,
+                    _ => unreachable!(),
+                })
+                .collect();
+            Item::Def(Def {
+                name: clause.name,
+                params,
+                body: Some(clause.body),
+                ty: None,
+                span: Some(DefSpan {
+                    name: clause.name_span,
+                    params: clause.pattern_spans,
+                }),
+                match_fail: false,
+            })
+        };
+        return clauses.into_iter().map(plain_def).collect();
+    }
+    let span = Some(clauses[0].name_span);
+    vec![Item::Match(MatchDef {
+        name: clauses[0].name.clone(),
+        equations: clauses
+            .into_iter()
+            .map(|clause| Equation {
+                patterns: clause.patterns,
+                body: clause.body,
+                span: Some(clause.lhs_span),
+            })
+            .collect(),
+        span,
+    })]
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn new(src: &str) -> Result<Self> {
+        Ok(Self {
+            tokens: tokenize(src)?,
+            pos: 0,
+        })
+    }
+
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.tok != Tok::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn span(&self) -> Span {
+        self.tokens[self.pos].span
+    }
+
+    // span from the start of token `start` to the end of the last consumed token
+    fn span_from(&self, start: usize) -> Span {
+        self.tokens[start]
+            .span
+            .to(self.tokens[self.pos.max(start + 1) - 1].span)
+    }
+
+    fn error(&self, expected: &str) -> Error {
+        let token = &self.tokens[self.pos];
+        Error::Syntax {
+            message: format!("expected {}, found {}", expected, token.tok.describe()),
+            span: token.span,
+        }
+    }
+
+    fn expect(&mut self, tok: Tok) -> Result<Token> {
+        if *self.peek() == tok {
+            Ok(self.next())
+        } else {
+            Err(self.error(&tok.describe()))
+        }
+    }
+
+    fn ident(&mut self) -> Result<(Ident, Span)> {
+        if let Tok::Ident(ident) = self.peek() {
+            let ident = ident.clone();
+            Ok((ident, self.next().span))
+        } else {
+            Err(self.error("identifier"))
+        }
+    }
+
+    fn idents(&mut self) -> (Vec<Ident>, Vec<Span>) {
+        let mut idents = vec![];
+        let mut spans = vec![];
+        while let Tok::Ident(ident) = self.peek() {
+            idents.push(ident.clone());
+            spans.push(self.next().span);
+        }
+        (idents, spans)
+    }
+
+    // `qualified`, `as` and `hiding` are only special inside an import
+    fn keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Tok::Ident(ident) if ident == keyword) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn names(&mut self) -> Result<(Vec<Ident>, Vec<Span>)> {
+        self.expect(Tok::LParen)?;
+        let mut names = vec![];
+        let mut spans = vec![];
+        if *self.peek() != Tok::RParen {
+            loop {
+                let (name, span) = self.ident()?;
+                names.push(name);
+                spans.push(span);
+                if *self.peek() != Tok::Comma {
+                    break;
+                }
+                self.next();
+            }
+        }
+        self.expect(Tok::RParen)?;
+        Ok((names, spans))
+    }
+
+    fn module(&mut self) -> Result<Module> {
+        let mut module = Module::new(MAIN_MODULE, Program::default());
+        if *self.peek() == Tok::Module {
+            self.next();
+            let (name, span) = self.ident()?;
+            module.name = name;
+            module.span = Some(span);
+            if *self.peek() == Tok::LParen {
+                let (exports, spans) = self.names()?;
+                module.exports = Some(exports);
+                module.export_spans = spans;
+            }
+            self.expect(Tok::Semi)?;
+        }
+        while *self.peek() == Tok::Import {
+            module.imports.push(self.import()?);
+        }
+        module.program = self.program()?;
+        Ok(module)
+    }
+
+    fn import(&mut self) -> Result<Import> {
+        let start = self.pos;
+        self.expect(Tok::Import)?;
+        let qualified = self.keyword("qualified");
+        let mut import = Import::new(self.ident()?.0);
+        import.qualified = qualified;
+        if self.keyword("as") {
+            import.alias = Some(self.ident()?.0);
+        }
+        if *self.peek() == Tok::LParen {
+            import.names = Some(self.names()?.0);
+        }
+        if self.keyword("hiding") {
+            import.hiding = self.names()?.0;
+        }
+        import.span = Some(self.span_from(start));
+        self.expect(Tok::Semi)?;
+        Ok(import)
+    }
+
+    fn program(&mut self) -> Result<Program> {
+        let mut program = Program::default();
+        let mut clauses: Vec<Clause> = vec![];
+        let flush = |program: &mut Program, clauses: &mut Vec<Clause>| {
+            if !clauses.is_empty() {
+                for item in group_clauses(std::mem::take(clauses)) {
+                    program.push(item);
+                }
+            }
+        };
+        while *self.peek() != Tok::Eof {
+            match self.peek() {
+                Tok::Data => {
+                    flush(&mut program, &mut clauses);
+                    program.push(self.data()?);
+                }
+                Tok::Hash => {
+                    flush(&mut program, &mut clauses);
+                    program.push(self.primitive()?);
+                }
+                _ => {
+                    let clause = self.clause()?;
+                    if clauses.first().is_some_and(|first| first.name != clause.name) {
+                        flush(&mut program, &mut clauses);
+                    }
+                    clauses.push(clause);
+                }
+            }
+        }
+        flush(&mut program, &mut clauses);
+        Ok(program)
+    }
+
+    fn data(&mut self) -> Result<DataDef> {
+        self.expect(Tok::Data)?;
+        let (name, span) = self.ident()?;
+        self.expect(Tok::Eq)?;
+        let mut ctors = vec![];
+        loop {
+            let (ctor, ctor_span) = self.ident()?;
+            let (fields, _) = self.idents();
+            ctors.push(Ctor {
+                name: ctor,
+                fields,
+                span: Some(ctor_span),
+            });
+            if *self.peek() != Tok::Bar {
+                break;
+            }
+            self.next();
+        }
+        self.expect(Tok::Semi)?;
+        Ok(DataDef {
+            name,
+            ctors,
+            span: Some(span),
+        })
+    }
+
+    fn primitive(&mut self) -> Result<Def> {
+        self.expect(Tok::Hash)?;
+        let (name, name_span) = self.ident()?;
+        let (params, param_spans) = self.idents();
+        let mut ty = None;
+        if *self.peek() == Tok::Colon {
+            self.next();
+            ty = Some(self.ty()?);
+        }
+        self.expect(Tok::Semi)?;
+        Ok(Def {
+            name,
+            params,
+            body: None,
+            ty,
+            span: Some(DefSpan {
+                name: name_span,
+                params: param_spans,
+            }),
+            match_fail: false,
+        })
+    }
+
+    fn ty(&mut self) -> Result<Type> {
+        let arg = self.ty_atom()?;
+        if *self.peek() != Tok::Arrow {
+            return Ok(arg);
+        }
+        self.next();
+        Ok(Type::Fun(Box::new(arg), Box::new(self.ty()?)))
+    }
+
+    fn ty_atom(&mut self) -> Result<Type> {
+        match self.peek().clone() {
+            Tok::Ident(ident) => {
+                self.next();
+                if ident.starts_with(char::is_uppercase) {
+                    Ok(Type::Con(ident))
+                } else {
+                    Ok(Type::Var(ident))
+                }
+            }
+            Tok::LParen => {
+                self.next();
+                let ty = self.ty()?;
+                self.expect(Tok::RParen)?;
+                Ok(ty)
+            }
+            _ => Err(self.error("type")),
+        }
+    }
+
+    fn clause(&mut self) -> Result<Clause> {
+        let start = self.pos;
+        let (name, name_span) = self.ident()?;
+        let mut patterns = vec![];
+        let mut pattern_spans = vec![];
+        while matches!(self.peek(), Tok::Ident(_) | Tok::Int(_) | Tok::LParen) {
+            let pattern_start = self.pos;
+            patterns.push(self.pattern_atom()?);
+            pattern_spans.push(self.span_from(pattern_start));
+        }
+        let lhs_span = self.span_from(start);
+        self.expect(Tok::Eq)?;
+        let body = self.expr()?;
+        self.expect(Tok::Semi)?;
+        Ok(Clause {
+            name,
+            name_span,
+            patterns,
+            pattern_spans,
+            lhs_span,
+            body,
+        })
+    }
+
+    fn pattern_atom(&mut self) -> Result<Pattern> {
+        match self.peek().clone() {
+            Tok::Ident(ident) => {
+                self.next();
+                Ok(Pattern::Var(ident))
+            }
+            Tok::Int(int) => {
+                self.next();
+                Ok(Pattern::Lit(int))
+            }
+            Tok::LParen => {
+                self.next();
+                let pattern = self.pattern()?;
+                self.expect(Tok::RParen)?;
+                Ok(pattern)
+            }
+            _ => Err(self.error("pattern")),
+        }
+    }
+
+    fn pattern(&mut self) -> Result<Pattern> {
+        let Tok::Ident(ident) = self.peek().clone() else {
+            return self.pattern_atom();
+        };
+        self.next();
+        let mut args = vec![];
+        while matches!(self.peek(), Tok::Ident(_) | Tok::Int(_) | Tok::LParen) {
+            args.push(self.pattern_atom()?);
+        }
+        if args.is_empty() {
+            Ok(Pattern::Var(ident))
+        } else {
+            Ok(Pattern::Ctor(ident, args))
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        if self.at_block() {
+            return self.block();
+        }
+        let start = self.pos;
+        let mut expr = self.atom()?;
+        loop {
+            let (arg, last) = match self.peek() {
+                Tok::Ident(_) | Tok::Int(_) | Tok::LParen | Tok::Case => (self.atom()?, false),
+                _ if self.at_block() => (self.block()?, true),
+                _ => return Ok(expr),
+            };
+            let app = Expr::App(Box::new(expr), Box::new(arg));
+            expr = Expr::Spanned(self.span_from(start), Box::new(app));
+            if last {
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn at_block(&self) -> bool {
+        matches!(self.peek(), Tok::Lambda | Tok::Bar | Tok::Let | Tok::LetRec)
+    }
+
+    fn block(&mut self) -> Result<Expr> {
+        if matches!(self.peek(), Tok::Let | Tok::LetRec) {
+            self.let_expr()
+        } else {
+            self.lambda()
+        }
+    }
+
+    fn let_expr(&mut self) -> Result<Expr> {
+        let start = self.pos;
+        let recursive = self.next().tok == Tok::LetRec;
+        let mut binds = vec![];
+        loop {
+            let (name, _) = self.ident()?;
+            let value_start = self.pos;
+            let (params, _) = self.idents();
+            self.expect(Tok::Eq)?;
+            let mut value = self.expr()?;
+            if !params.is_empty() {
+                let lam = Expr::Lam(params, Box::new(value));
+                value = Expr::Spanned(self.span_from(value_start), Box::new(lam));
+            }
+            binds.push((name, value));
+            if *self.peek() == Tok::In {
+                self.next();
+                break;
+            }
+            self.expect(Tok::Semi)?;
+        }
+        let body = Box::new(self.expr()?);
+        let expr = if recursive {
+            Expr::LetRec(binds, body)
+        } else {
+            Expr::Let(binds, body)
+        };
+        Ok(Expr::Spanned(self.span_from(start), Box::new(expr)))
+    }
+
+    fn lambda(&mut self) -> Result<Expr> {
+        let start = self.pos;
+        let close = if self.next().tok == Tok::Lambda {
+            Tok::Dot
+        } else {
+            Tok::Bar
+        };
+        let (params, _) = self.idents();
+        if params.is_empty() {
+            return Err(self.error("lambda parameter"));
+        }
+        self.expect(close)?;
+        let body = self.expr()?;
+        let lam = Expr::Lam(params, Box::new(body));
+        Ok(Expr::Spanned(self.span_from(start), Box::new(lam)))
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        let span = self.span();
+        match self.peek().clone() {
+            Tok::Ident(ident) => {
+                self.next();
+                Ok(Expr::Spanned(span, Box::new(Expr::Id(ident))))
+            }
+            Tok::Int(int) => {
+                self.next();
+                Ok(Expr::Spanned(span, Box::new(Expr::Prim(int))))
+            }
+            Tok::LParen => {
+                self.next();
+                let expr = self.expr()?;
+                self.expect(Tok::RParen)?;
+                Ok(expr)
+            }
+            Tok::Case => self.case(),
+            _ => Err(self.error("expression")),
+        }
+    }
+
+    fn case(&mut self) -> Result<Expr> {
+        let start = self.pos;
+        self.expect(Tok::Case)?;
+        let scrutinee = self.expr()?;
+        self.expect(Tok::Of)?;
+        self.expect(Tok::LBrace)?;
+        let mut arms = vec![];
+        loop {
+            let arm_start = self.pos;
+            let (ctor, _) = self.ident()?;
+            let (fields, _) = self.idents();
+            self.expect(Tok::Arrow)?;
+            let body = self.expr()?;
+            arms.push(Arm {
+                ctor,
+                fields,
+                body,
+                span: Some(self.span_from(arm_start)),
+            });
+            match self.peek() {
+                Tok::Semi => {
+                    self.next();
+                    if *self.peek() == Tok::RBrace {
+                        self.next();
+                        break;
+                    }
+                }
+                Tok::RBrace => {
+                    self.next();
+                    break;
+                }
+                _ => return Err(self.error("`;` or `}`")),
+            }
+        }
+        let case = Expr::Case(Box::new(scrutinee), arms);
+        Ok(Expr::Spanned(self.span_from(start), Box::new(case)))
+    }
+}
+
+pub fn parse_program(src: &str) -> Result<Program> {
+    let mut parser = Parser::new(src)?;
+    parser.program()
+}
+
+// a file without a `module` header is the module `Main` exporting everything
+pub fn parse_module(src: &str) -> Result<Module> {
+    let mut parser = Parser::new(src)?;
+    parser.module()
+}
+
+pub fn parse_expr(src: &str) -> Result<Expr> {
+    let mut parser = Parser::new(src)?;
+    let expr = parser.expr()?;
+    parser.expect(Tok::Eof)?;
+    Ok(expr)
+}
+
+pub fn parse_type(src: &str) -> Result<Type> {
+    let mut parser = Parser::new(src)?;
+    let ty = parser.ty()?;
+    parser.expect(Tok::Eof)?;
+    Ok(ty)
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).map_err(|error| Error::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+pub fn parse_file(path: impl AsRef<Path>) -> Result<Program> {
+    parse_program(&read_file(path.as_ref())?)
+}
+
+pub fn parse_module_file(path: impl AsRef<Path>) -> Result<Module> {
+    parse_module(&read_file(path.as_ref())?)
+}
+
+impl FromStr for Program {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_program(s)
+    }
+}
+
+impl FromStr for Module {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_module(s)
+    }
+}
+
+impl FromStr for Type {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_type(s)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_expr(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[test]
+    fn parse_matches_macro() {
+        let src = "
+            #mul x y;
+            // comments are ignored
+            square x = mul x x;
+            square2 = (|x| mul x x);
+            square3 = (λ x. mul x x);
+            main = square (square2 3) 4;
+            rangesum = \\n. snd (n update (pair (|x| x) λx y. y));
+        ";
+        let expected = program![
+            #mul x y;
+            square x = mul x x;
+            square2 = (|x| mul x x);
+            square3 = (λ x. mul x x);
+            main = square (square2 3) 4;
+            rangesum = λ n. snd (n update (pair (|x| x) (λ x y. y)));
+        ];
+        let parsed = parse_program(src).unwrap();
+        assert_eq!(parsed.to_string(), expected.to_string());
+    }
+
+    #[test]
+    fn group_only_pattern_equations() {
+        let src = "f x = x; #g x; f y = y; h 0 = 1; h n = n;";
+        let parsed = parse_program(src).unwrap();
+        let names = parsed.defs.iter().map(|def| &*def.name).collect::<Vec<_>>();
+        assert_eq!(names, ["f", "g", "f"]);
+        assert_eq!(parsed.matches.len(), 1);
+        assert_eq!(parsed.matches[0].equations.len(), 2);
+    }
+
+    #[test]
+    fn parse_error_position() {
+        let src = "id x = x;\nbad = (id 1;";
+        let Err(Error::Syntax { span, .. }) = parse_program(src) else {
+            panic!("expected a syntax error");
+        };
+        assert_eq!(span, Span::new(src.len() - 1, src.len()));
+    }
+
+    #[test]
+    fn parse_negative_literal() {
+        let expr = parse_expr("SUB -1 (-2)").unwrap();
+        assert_eq!(expr.to_string(), "SUB -1 -2");
+    }
+
+    #[test]
+    fn parse_module_header() {
+        let src = "
+            module Main (main);
+            import Prelude as P (List, map) hiding (Nil);
+            import qualified Other;
+            main = P.map Other.f P.Nil;
+        ";
+        let module = parse_module(src).unwrap();
+        assert_eq!(
+            module.to_string(),
+            "module Main (main);\n\
+             import Prelude as P (List, map) hiding (Nil);\n\
+             import qualified Other;\n\
+             main = P.map Other.f P.Nil"
+        );
+    }
+}
@@ -2,24 +2,109 @@ use std::fmt::Display;
 
 pub type Ident = String;
 
-#[derive(Debug)]
+// byte range in the source text, `start` inclusive and `end` exclusive
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn to(self, other: Span) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Expr {
     Id(Ident),
     Prim(i64),
     App(Box<Self>, Box<Self>),
     Lam(Vec<Ident>, Box<Self>),
+    // sequential: each binding sees the ones before it
+    Let(Vec<(Ident, Expr)>, Box<Self>),
+    // mutually recursive: every binding sees the whole group
+    LetRec(Vec<(Ident, Expr)>, Box<Self>),
+    // applies the scrutinee to one lambda per constructor, see DataDef
+    Case(Box<Self>, Vec<Arm>),
+    // source location of the inner expression; transparent to every pass
+    Spanned(Span, Box<Self>),
+}
+
+// dropped with an explicit stack, since read-back results may be deeper than the Rust stack allows
+impl Drop for Expr {
+    fn drop(&mut self) {
+        // move the parts that have parts of their own out of `expr`, leaving it shallow to drop
+        fn take_parts(expr: &mut Expr, stack: &mut Vec<Expr>) {
+            let mut take = |e: &mut Expr| {
+                if !matches!(e, Expr::Id(_) | Expr::Prim(_)) {
+                    stack.push(e.take());
+                }
+            };
+            match expr {
+                Expr::Id(_) | Expr::Prim(_) => {}
+                Expr::App(e1, e2) => {
+                    take(e1);
+                    take(e2);
+                }
+                Expr::Lam(_, e) | Expr::Spanned(_, e) => take(e),
+                Expr::Let(binds, e) | Expr::LetRec(binds, e) => {
+                    binds.iter_mut().for_each(|(_, value)| take(value));
+                    take(e);
+                }
+                Expr::Case(e, arms) => {
+                    arms.iter_mut().for_each(|arm| take(&mut arm.body));
+                    take(e);
+                }
+            }
+        }
+        let mut stack = vec![];
+        take_parts(self, &mut stack);
+        while let Some(mut expr) = stack.pop() {
+            take_parts(&mut expr, &mut stack);
+        }
+    }
+}
+
+impl Expr {
+    // move `self` out, leaving a placeholder; `Expr` has a `Drop`, so passes that take an
+    // expression apart match on `&mut` and take the parts they keep
+    pub fn take(&mut self) -> Expr {
+        std::mem::replace(self, Expr::Prim(0))
+    }
+
+    pub fn unspanned(&self) -> &Self {
+        let mut expr = self;
+        while let Expr::Spanned(_, e) = expr {
+            expr = e;
+        }
+        expr
+    }
 }
 
 impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Expr::App(e1, e2) => {
-                if matches!(&**e1, Expr::Lam(_, _)) {
+                if matches!(
+                    e1.unspanned(),
+                    Expr::Lam(_, _) | Expr::Let(_, _) | Expr::LetRec(_, _)
+                ) {
                     write!(f, "({}) ", e1)?;
                 } else {
                     write!(f, "{} ", e1)?;
                 }
-                if matches!(&**e2, Expr::App(_, _) | Expr::Lam(_, _)) {
+                if matches!(
+                    e2.unspanned(),
+                    Expr::App(_, _) | Expr::Lam(_, _) | Expr::Let(_, _) | Expr::LetRec(_, _)
+                ) {
                     write!(f, "({})", e2)?;
                 } else {
                     write!(f, "{}", e2)?;
@@ -34,21 +119,112 @@ impl Display for Expr {
                 }
                 write!(f, ". {}", e)?;
             }
+            Expr::Let(binds, e) | Expr::LetRec(binds, e) => {
+                let keyword = if matches!(self, Expr::Let(_, _)) { "let" } else { "letrec" };
+                write!(f, "{} {} = {}", keyword, binds[0].0, binds[0].1)?;
+                for (ident, value) in &binds[1..] {
+                    write!(f, "; {} = {}", ident, value)?;
+                }
+                write!(f, " in {}", e)?;
+            }
+            Expr::Case(e, arms) => {
+                write!(f, "case {} of {{ {}", e, arms[0])?;
+                for arm in &arms[1..] {
+                    write!(f, "; {}", arm)?;
+                }
+                write!(f, " }}")?;
+            }
+            Expr::Spanned(_, e) => write!(f, "{}", e)?,
         }
         Ok(())
     }
 }
 
-#[derive(Debug)]
+// `ctor fields -> body`; the constructor `_` matches anything and binds no fields
+#[derive(Debug, Clone)]
+pub struct Arm {
+    pub ctor: Ident,
+    pub fields: Vec<Ident>,
+    pub body: Expr,
+    pub span: Option<Span>,
+}
+
+impl Display for Arm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.ctor)?;
+        for field in &self.fields {
+            write!(f, " {}", field)?;
+        }
+        write!(f, " -> {}", self.body)
+    }
+}
+
+// types as written in primop signatures and reported by the type checker
+// `Con` is an opaque base type such as `Int`; `Rec` is a recursive type `μt. ..` where `t` stands
+// for the whole type again
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Con(Ident),
+    Var(Ident),
+    Fun(Box<Type>, Box<Type>),
+    Rec(Ident, Box<Type>),
+}
+
+impl Type {
+    pub fn int() -> Self {
+        Type::Con("Int".to_string())
+    }
+
+    fn fmt_arg(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Fun(..) | Type::Rec(..) => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Con(name) | Type::Var(name) => write!(f, "{}", name),
+            Type::Fun(t1, t2) => {
+                t1.fmt_arg(f)?;
+                write!(f, " -> {}", t2)
+            }
+            Type::Rec(name, t) => write!(f, "μ{}. {}", name, t),
+        }
+    }
+}
+
+// source locations of a definition's name and parameters
+#[derive(Debug, Clone, Default)]
+pub struct DefSpan {
+    pub name: Span,
+    pub params: Vec<Span>,
+}
+
+// `body: None` is a primitive, which may carry a type signature
+// `match_fail` marks the primitive that match compilation generates for inputs that match no
+// equation; it has no implementation to attach and fails on every input (see match_compile)
+#[derive(Debug, Clone)]
 pub struct Def {
     pub name: Ident,
     pub params: Vec<Ident>,
     pub body: Option<Expr>,
+    pub ty: Option<Type>,
+    pub span: Option<DefSpan>,
+    pub match_fail: bool,
 }
 
 impl Display for Def {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let Def { name, params, body } = self;
+        let Def {
+            name,
+            params,
+            body,
+            ty,
+            ..
+        } = self;
         write!(f, "{}", name)?;
         for param in params {
             write!(f, " {}", param)?;
@@ -58,28 +234,254 @@ impl Display for Def {
         } else {
             write!(f, " = <builtin>")?;
         }
+        if let Some(ty) = ty {
+            write!(f, " : {}", ty)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ctor {
+    pub name: Ident,
+    pub fields: Vec<Ident>,
+    pub span: Option<Span>,
+}
+
+// `data List = SNil | SCons x xs` is Scott-encoded:
+// SNil $SNil $SCons = $SNil; SCons x xs $SNil $SCons = $SCons x xs
+#[derive(Debug, Clone)]
+pub struct DataDef {
+    pub name: Ident,
+    pub ctors: Vec<Ctor>,
+    pub span: Option<Span>,
+}
+
+impl Display for DataDef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "data {} =", self.name)?;
+        for (i, ctor) in self.ctors.iter().enumerate() {
+            let sep = if i == 0 { "" } else { " |" };
+            write!(f, "{} {}", sep, ctor.name)?;
+            for field in &ctor.fields {
+                write!(f, " {}", field)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// `_` as a variable pattern binds nothing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    Var(Ident),
+    Lit(i64),
+    Ctor(Ident, Vec<Pattern>),
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pattern::Var(ident) => write!(f, "{}", ident),
+            Pattern::Lit(i) => write!(f, "{}", i),
+            Pattern::Ctor(ctor, args) if args.is_empty() => write!(f, "{}", ctor),
+            Pattern::Ctor(ctor, args) => {
+                write!(f, "({}", ctor)?;
+                for arg in args {
+                    write!(f, " {}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Equation {
+    pub patterns: Vec<Pattern>,
+    pub body: Expr,
+    pub span: Option<Span>,
+}
+
+// a definition given by several equations, tried from top to bottom:
+// len SNil = 0; len (SCons _ xs) = ADD 1 (len xs)
+#[derive(Debug, Clone)]
+pub struct MatchDef {
+    pub name: Ident,
+    pub equations: Vec<Equation>,
+    pub span: Option<Span>,
+}
+
+impl Display for MatchDef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, equation) in self.equations.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", self.name)?;
+            for pattern in &equation.patterns {
+                write!(f, " {}", pattern)?;
+            }
+            write!(f, " = {}", equation.body)?;
+        }
         Ok(())
     }
 }
 
 #[derive(Debug)]
+pub enum Item {
+    Def(Def),
+    Data(DataDef),
+    Match(MatchDef),
+}
+
+impl From<Def> for Item {
+    fn from(def: Def) -> Self {
+        Item::Def(def)
+    }
+}
+
+impl From<DataDef> for Item {
+    fn from(data: DataDef) -> Self {
+        Item::Data(data)
+    }
+}
+
+impl From<MatchDef> for Item {
+    fn from(matches: MatchDef) -> Self {
+        Item::Match(matches)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct Program {
     pub defs: Vec<Def>,
+    pub datas: Vec<DataDef>,
+    pub matches: Vec<MatchDef>,
+}
+
+impl Program {
+    pub fn push(&mut self, item: impl Into<Item>) {
+        match item.into() {
+            Item::Def(def) => self.defs.push(def),
+            Item::Data(data) => self.datas.push(data),
+            Item::Match(matches) => self.matches.push(matches),
+        }
+    }
+
+    pub fn extend(&mut self, other: Program) {
+        self.defs.extend(other.defs);
+        self.datas.extend(other.datas);
+        self.matches.extend(other.matches);
+    }
 }
 
 impl Display for Program {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        if !self.defs.is_empty() {
-            write!(f, "{}", self.defs[0])?;
-            for def in &self.defs[1..] {
-                write!(f, "\n{}", def)?;
+        let datas = self.datas.iter().map(|data| data as &dyn Display);
+        let defs = self.defs.iter().map(|def| def as &dyn Display);
+        let matches = self.matches.iter().map(|matches| matches as &dyn Display);
+        for (i, item) in datas.chain(defs).chain(matches).enumerate() {
+            if i > 0 {
+                writeln!(f)?;
             }
+            write!(f, "{}", item)?;
         }
         Ok(())
     }
 }
 
+// `import M;` brings every export of `M` into scope both as `x` and `M.x`
+// `as Q` changes the qualifier, `qualified` drops the unqualified names,
+// and an import list or `hiding` list restricts which exports are brought in
 #[derive(Debug, Clone)]
+pub struct Import {
+    pub module: Ident,
+    pub alias: Option<Ident>,
+    pub qualified: bool,
+    pub names: Option<Vec<Ident>>,
+    pub hiding: Vec<Ident>,
+    pub span: Option<Span>,
+}
+
+impl Import {
+    pub fn new(module: impl Into<Ident>) -> Self {
+        Self {
+            module: module.into(),
+            alias: None,
+            qualified: false,
+            names: None,
+            hiding: vec![],
+            span: None,
+        }
+    }
+
+    pub fn qualifier(&self) -> &str {
+        self.alias.as_ref().unwrap_or(&self.module)
+    }
+}
+
+impl Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "import ")?;
+        if self.qualified {
+            write!(f, "qualified ")?;
+        }
+        write!(f, "{}", self.module)?;
+        if let Some(alias) = &self.alias {
+            write!(f, " as {}", alias)?;
+        }
+        if let Some(names) = &self.names {
+            write!(f, " ({})", names.join(", "))?;
+        }
+        if !self.hiding.is_empty() {
+            write!(f, " hiding ({})", self.hiding.join(", "))?;
+        }
+        write!(f, ";")
+    }
+}
+
+// a named program with an optional export list (`None` exports every top-level name)
+// exporting a data type exports all of its constructors
+#[derive(Debug, Clone)]
+pub struct Module {
+    pub name: Ident,
+    pub exports: Option<Vec<Ident>>,
+    // source locations of the export list entries, if known
+    pub export_spans: Vec<Span>,
+    pub imports: Vec<Import>,
+    pub program: Program,
+    pub span: Option<Span>,
+}
+
+impl Module {
+    pub fn new(name: impl Into<Ident>, program: Program) -> Self {
+        Self {
+            name: name.into(),
+            exports: None,
+            export_spans: vec![],
+            imports: vec![],
+            program,
+            span: None,
+        }
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "module {}", self.name)?;
+        if let Some(exports) = &self.exports {
+            write!(f, " ({})", exports.join(", "))?;
+        }
+        writeln!(f, ";")?;
+        for import in &self.imports {
+            writeln!(f, "{}", import)?;
+        }
+        write!(f, "{}", self.program)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AnonExpr {
     DefId(usize),
     ArgId(usize),
@@ -136,26 +538,37 @@ impl AnonExpr {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Name {
     Named(String),
     Unnamed(usize),
+    // a match-failure primitive (see Def::match_fail)
+    MatchFail(String),
 }
 
 impl Display for Name {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Name::Named(s) => write!(f, "{}", s),
+            Name::Named(s) | Name::MatchFail(s) => write!(f, "{}", s),
             Name::Unnamed(i) => write!(f, "?{}", i),
         }
     }
 }
 
-#[derive(Debug)]
+// `span` locates the definition's name in the source, for errors in later passes; it is not
+// compared, so that a program equals itself parsed back from its Display format
+#[derive(Debug, Eq)]
 pub struct AnonDef {
     pub name: Name,
     pub params: usize,
     pub body: Option<AnonExpr>,
+    pub span: Option<Span>,
+}
+
+impl PartialEq for AnonDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.params == other.params && self.body == other.body
+    }
 }
 
 impl AnonDef {
@@ -167,6 +580,8 @@ impl AnonDef {
         write!(f, " = ")?;
         if let Some(body) = &self.body {
             body.fmt(f, root, 0)?;
+        } else if let Name::MatchFail(_) = self.name {
+            write!(f, "<no match>")?;
         } else {
             write!(f, "<builtin>")?;
         }
@@ -174,7 +589,7 @@ impl AnonDef {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct AnonProgram {
     pub defs: Vec<AnonDef>,
 }
@@ -184,7 +599,7 @@ impl Display for AnonProgram {
         if !self.defs.is_empty() {
             self.defs[0].fmt(f, self)?;
             for def in &self.defs[1..] {
-                write!(f, "\n")?;
+                writeln!(f)?;
                 def.fmt(f, self)?;
             }
         }
@@ -200,12 +615,32 @@ pub enum ScExpr {
     App(Box<Self>, Box<Self>),
 }
 
+// dropped with an explicit stack, since read-back results may be deeper than the Rust stack allows
+impl Drop for ScExpr {
+    fn drop(&mut self) {
+        // move the applications out of `expr`, leaving it shallow to drop
+        fn take_apps(expr: &mut ScExpr, stack: &mut Vec<ScExpr>) {
+            if let ScExpr::App(e1, e2) = expr {
+                for e in [e1, e2] {
+                    if let ScExpr::App(_, _) = **e {
+                        stack.push(std::mem::replace(&mut **e, ScExpr::Prim(0)));
+                    }
+                }
+            }
+        }
+        let mut stack = vec![];
+        take_apps(self, &mut stack);
+        while let Some(mut expr) = stack.pop() {
+            take_apps(&mut expr, &mut stack);
+        }
+    }
+}
+
 impl ScExpr {
     fn fmt(
         &self,
         f: &mut std::fmt::Formatter<'_>,
         root: &ScProgram,
-        depth: usize,
     ) -> std::fmt::Result {
         match self {
             ScExpr::DefId(i) => {
@@ -219,14 +654,14 @@ impl ScExpr {
                 write!(f, "i{}", i)?;
             }
             ScExpr::App(e1, e2) => {
-                e1.fmt(f, root, depth)?;
+                e1.fmt(f, root)?;
                 write!(f, " ")?;
                 if matches!(&**e2, ScExpr::App(_, _)) {
                     write!(f, "(")?;
-                    e2.fmt(f, root, depth)?;
+                    e2.fmt(f, root)?;
                     write!(f, ")")?;
                 } else {
-                    e2.fmt(f, root, depth)?;
+                    e2.fmt(f, root)?;
                 }
             }
         }
@@ -234,11 +669,19 @@ impl ScExpr {
     }
 }
 
-#[derive(Debug)]
+// `span` as in AnonDef
+#[derive(Debug, Clone, Eq)]
 pub struct ScDef {
     pub name: Name,
     pub params: usize,
     pub body: Option<ScExpr>,
+    pub span: Option<Span>,
+}
+
+impl PartialEq for ScDef {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.params == other.params && self.body == other.body
+    }
 }
 
 impl ScDef {
@@ -249,7 +692,9 @@ impl ScDef {
         }
         write!(f, " = ")?;
         if let Some(body) = &self.body {
-            body.fmt(f, root, 0)?;
+            body.fmt(f, root)?;
+        } else if let Name::MatchFail(_) = self.name {
+            write!(f, "<no match>")?;
         } else {
             write!(f, "<builtin>")?;
         }
@@ -257,7 +702,7 @@ impl ScDef {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ScProgram {
     pub defs: Vec<ScDef>,
 }
@@ -267,7 +712,7 @@ impl Display for ScProgram {
         if !self.defs.is_empty() {
             self.defs[0].fmt(f, self)?;
             for def in &self.defs[1..] {
-                write!(f, "\n")?;
+                writeln!(f)?;
                 def.fmt(f, self)?;
             }
         }
@@ -275,7 +720,7 @@ impl Display for ScProgram {
     }
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Atom {
     Sc(usize),
     Prim(i64),
@@ -302,6 +747,26 @@ pub struct ScPrimProgram<'a> {
 
 #[macro_export]
 macro_rules! expr {
+    (let $($name: ident $($params: ident)* = $value: tt);+ in $($tail: tt)+) => {
+        Expr::Let(vec![$((stringify!($name).to_string(), expr!(@bind ($($params)*) $value))),+], Box::new(expr!($($tail)+)))
+    };
+    (letrec $($name: ident $($params: ident)* = $value: tt);+ in $($tail: tt)+) => {
+        Expr::LetRec(vec![$((stringify!($name).to_string(), expr!(@bind ($($params)*) $value))),+], Box::new(expr!($($tail)+)))
+    };
+    (case $scrut: tt of { $($ctor: tt $($fields: ident)* -> $body: tt);+ $(;)? }) => {
+        Expr::Case(Box::new(expr!($scrut)), vec![$(Arm {
+            ctor: stringify!($ctor).to_string(),
+            fields: vec![$(stringify!($fields).to_string()),*],
+            body: expr!($body),
+            span: None,
+        }),+])
+    };
+    (@bind () $value: tt) => {
+        expr!($value)
+    };
+    (@bind ($($params: ident)+) $value: tt) => {
+        Expr::Lam(vec![$(stringify!($params).to_string()),+], Box::new(expr!($value)))
+    };
     (λ $($params: ident)+ . $($tail: tt)+ ) => {
         Expr::Lam(vec![$(stringify!($params).to_string()),+], Box::new(expr!($($tail)+)))
     };
@@ -327,18 +792,45 @@ macro_rules! expr {
 
 #[macro_export]
 macro_rules! lambda {
+    (data $id: ident = $($ctor: ident $($fields: ident)*)|+) => {
+        DataDef {
+            name: stringify!($id).to_string(),
+            ctors: vec![$(Ctor {
+                name: stringify!($ctor).to_string(),
+                fields: vec![$(stringify!($fields).to_string()),*],
+                span: None,
+            }),+],
+            span: None,
+        }
+    };
     ($id: ident $($params: ident)* = $($expr: tt)+) => {
         Def {
             name: stringify!($id).to_string(),
             params: vec![$(stringify!($params).to_string()),*],
             body: Some(expr!($($expr)+)),
+            ty: None,
+            span: None,
+            match_fail: false,
+        }
+    };
+    (# $id: ident $($params: ident)* : $($ty: tt)+) => {
+        Def {
+            name: stringify!($id).to_string(),
+            params: vec![$(stringify!($params).to_string()),*],
+            body: None,
+            ty: Some(stringify!($($ty)+).parse().expect("malformed primop signature")),
+            span: None,
+            match_fail: false,
         }
     };
     (# $id: ident $($params: ident)*) => {
         Def {
             name: stringify!($id).to_string(),
             params: vec![$(stringify!($params).to_string()),*],
-            body: None
+            body: None,
+            ty: None,
+            span: None,
+            match_fail: false,
         }
     };
 }
@@ -351,9 +843,11 @@ macro_rules! program {
     (@ ($($h: tt)*) ($($e: expr,)*) $t: tt $($t2: tt)*) => {
         program!(@ ($($h)* $t) ($($e,)*) $($t2)*)
     };
-    (@ () ($($e: expr,)*)) => {
-        Program { defs: vec![$($e),*] }
-    };
+    (@ () ($($e: expr,)*)) => {{
+        let mut program = Program::default();
+        $(program.push($e);)*
+        program
+    }};
     ($($t: tt)*) => {
         program!(@ () () $($t)*)
     };
@@ -367,6 +861,7 @@ mod tests {
     fn macro_compiles() {
         let _ = program![
             #mul x y;
+            #add x y : Int -> (Int -> Int);
             square x = mul x x;
             square2 = (|x| mul x x);
             square3 = (λ x. mul x x);
@@ -386,5 +881,14 @@ mod tests {
             rangesum = λ n. snd (n update (pair (λ x. x) (λ x y. y)));
             main = (λ f x. f (f x)) rangesum;
         ];
+        let _ = program![
+            sum n = letrec go i acc = (EQ i n acc (go (ADD i 1) (ADD acc i))) in go 0 0;
+            twice f x = let y = (f x); z = (f y) in z;
+        ];
+        let _ = program![
+            data List = Nil | Cons x xs;
+            len xs = case xs of { Nil -> 0; Cons _ t -> (ADD 1 (len t)) };
+            isNil xs = case xs of { Nil -> True; _ -> False };
+        ];
     }
 }
@@ -1,5 +1,11 @@
 pub mod anonymize;
+pub mod data_lower;
+pub mod letrec_lift;
+pub mod lint;
 pub mod lambda_elim;
 pub mod lambda_lift;
+pub mod match_compile;
+pub mod module_resolve;
 pub mod sc_compress;
 pub mod sc_attach_prim;
+pub mod nbe;
@@ -3,120 +3,257 @@
 // supercombinator arguments are assigned sc indexes
 // lambda-local vars become de Bruijn indexes
 // lambdas become single-layered
+// let bindings become lambda applications: let x = a in b -> (λx. b) a
+// pattern-matching equations, data declarations and case expressions are lowered first
+// (see match_compile and data_lower)
+// letrec groups are lifted to top-level definitions first (see letrec_lift)
+// every undefined name and collision is reported, not just the first (see Error::Multiple)
 
-use crate::error::{Error, Result};
+use crate::diagnostic::suggest;
+use crate::error::{Error, Result, Warning};
 use crate::structures::*;
 use std::collections::HashMap;
 
-impl Expr {
-    fn into_anon_inner(
-        self,
-        name2id: &HashMap<String, AnonExpr>,
-        index: &mut Vec<String>,
-    ) -> Result<AnonExpr> {
-        let expr = self;
-        match expr {
+struct Anonymizer<'a> {
+    name2id: &'a HashMap<String, AnonExpr>,
+    // lambda-bound names, innermost last
+    index: Vec<String>,
+    def_name: &'a str,
+    span: Option<Span>,
+    errors: Vec<Error>,
+}
+
+impl Anonymizer<'_> {
+    fn undefined(&mut self, ident: String) -> AnonExpr {
+        let names = self.name2id.keys().chain(&self.index);
+        let suggestion = suggest(&ident, names.map(|name| name.as_str()));
+        self.errors.push(Error::UndefinedIdent {
+            def_name: self.def_name.to_string(),
+            undefined_name: ident,
+            span: self.span,
+            suggestion,
+        });
+        AnonExpr::Prim(0)
+    }
+
+    fn unlowered(&mut self, form: &'static str) -> AnonExpr {
+        self.errors.push(Error::UnloweredExpr {
+            def_name: self.def_name.to_string(),
+            form,
+            span: self.span,
+        });
+        AnonExpr::Prim(0)
+    }
+
+    fn anon(&mut self, mut expr: Expr) -> AnonExpr {
+        match &mut expr {
             Expr::Id(ident) => {
-                if let Some(pos) = index.iter().rev().position(|x| x == &ident) {
-                    return Ok(AnonExpr::DeBruijn(pos));
+                if let Some(pos) = self.index.iter().rev().position(|x| x == ident) {
+                    return AnonExpr::DeBruijn(pos);
+                }
+                match self.name2id.get(ident) {
+                    Some(e) => e.clone(),
+                    None => self.undefined(std::mem::take(ident)),
                 }
-                let Some(e) = name2id.get(&ident) else {
-                    return Err(Error::UndefinedIdent { def_name: String::default(), undefined_name: ident });
-                };
-                Ok(e.clone())
             }
-            Expr::Prim(int) => Ok(AnonExpr::Prim(int)),
+            Expr::Prim(int) => AnonExpr::Prim(*int),
             Expr::App(e1, e2) => {
-                let e1 = e1.into_anon_inner(name2id, index)?;
-                let e2 = e2.into_anon_inner(name2id, index)?;
-                Ok(AnonExpr::App(Box::new(e1), Box::new(e2)))
+                let e1 = self.anon(e1.take());
+                let e2 = self.anon(e2.take());
+                AnonExpr::App(Box::new(e1), Box::new(e2))
+            }
+            Expr::Spanned(span, e) => {
+                let outer = self.span.replace(*span);
+                let e = self.anon(e.take());
+                self.span = outer;
+                e
             }
             Expr::Lam(idents, e) => {
-                let prev_index_len = index.len();
+                let prev_index_len = self.index.len();
                 let idents_len = idents.len();
-                index.extend(idents);
-                let mut e = e.into_anon_inner(name2id, index)?;
-                index.truncate(prev_index_len);
+                self.index.append(idents);
+                let mut e = self.anon(e.take());
+                self.index.truncate(prev_index_len);
                 for _ in 0..idents_len {
                     e = AnonExpr::Lam(Box::new(e));
                 }
-                Ok(e)
+                e
+            }
+            Expr::Let(binds, body) => {
+                let prev_index_len = self.index.len();
+                let mut values = vec![];
+                for (ident, value) in std::mem::take(binds) {
+                    values.push(self.anon(value));
+                    self.index.push(ident);
+                }
+                let mut e = self.anon(body.take());
+                self.index.truncate(prev_index_len);
+                for value in values.into_iter().rev() {
+                    e = AnonExpr::App(Box::new(AnonExpr::Lam(Box::new(e))), Box::new(value));
+                }
+                e
             }
+            // only reached through Expr::into_anon and Def::into_anon, which have no data
+            // declarations or program to lower them into
+            Expr::Case(_, _) => self.unlowered("case"),
+            Expr::LetRec(_, _) => self.unlowered("letrec"),
         }
     }
+}
+
+impl Expr {
     pub fn into_anon(self, name2id: &HashMap<String, AnonExpr>) -> Result<AnonExpr> {
-        let expr = self;
-        expr.into_anon_inner(name2id, &mut vec![])
+        let mut anonymizer = Anonymizer {
+            name2id,
+            index: vec![],
+            def_name: "",
+            span: None,
+            errors: vec![],
+        };
+        let expr = anonymizer.anon(self);
+        Error::collect(anonymizer.errors)?;
+        Ok(expr)
     }
 }
 
 impl Def {
     pub fn into_anon(self, name2id: &mut HashMap<String, AnonExpr>) -> Result<AnonDef> {
         let def = self;
-        let Def { name, params, body } = def;
+        let Def { name, params, body, span, match_fail, .. } = def;
+        let name_span = span.as_ref().map(|span| span.name);
+        let param_spans = span.map(|span| span.params).unwrap_or_default();
         let Some(body) = body else {
             // Primitive
-            return Ok(AnonDef { name: Name::Named(name), params: params.len(), body: None });
+            let name = if match_fail { Name::MatchFail(name) } else { Name::Named(name) };
+            return Ok(AnonDef { name, params: params.len(), body: None, span: name_span });
         };
         let params_len = params.len();
+        let mut errors = vec![];
         let mut to_restore = vec![];
         let mut to_remove = vec![];
         for (id, param) in params.into_iter().enumerate() {
             let prev_expr = name2id.insert(param.clone(), AnonExpr::ArgId(id));
             if let Some(prev_expr) = prev_expr {
                 if let AnonExpr::ArgId(_) = prev_expr {
-                    return Err(Error::ScParamNameCollision {
-                        def_name: name,
+                    errors.push(Error::ScParamNameCollision {
+                        def_name: name.clone(),
                         param_name: param,
+                        span: param_spans.get(id).copied(),
                     });
+                    continue;
                 }
                 to_restore.push((param, prev_expr));
             } else {
                 to_remove.push(param);
             }
         }
-        let body = body.into_anon(name2id);
-        let body = match body {
-            Ok(body) => body,
-            Err(Error::UndefinedIdent { undefined_name, .. }) => {
-                return Err(Error::UndefinedIdent {
-                    def_name: name,
-                    undefined_name,
-                });
-            }
-            Err(error) => {
-                return Err(error);
-            }
+        let mut anonymizer = Anonymizer {
+            name2id,
+            index: vec![],
+            def_name: &name,
+            span: None,
+            errors,
         };
+        let body = anonymizer.anon(body);
+        let errors = anonymizer.errors;
         for k in to_remove {
             name2id.remove(&k);
         }
         for (k, v) in to_restore {
             name2id.insert(k, v);
         }
+        Error::collect(errors)?;
         Ok(AnonDef {
             name: Name::Named(name),
             params: params_len,
             body: Some(body),
+            span: name_span,
         })
     }
 }
 
 impl Program {
     pub fn into_anon(self) -> Result<AnonProgram> {
-        let program = self;
+        Ok(self.into_anon_with_warnings(&[])?.0)
+    }
+
+    // also reports pattern-matching warnings and lints (see lint);
+    // definitions unreachable from `entries` are reported only if `entries` is not empty
+    pub fn into_anon_with_warnings(self, entries: &[&str]) -> Result<(AnonProgram, Vec<Warning>)> {
+        let ctors = self.datas.iter().flat_map(|data| &data.ctors);
+        let ctors = ctors.map(|ctor| ctor.name.clone()).collect();
+        let (program, mut warnings) = self.compile_matches()?;
+        let program = program.lower_data()?.lift_letrec();
+        warnings.extend(program.lint(entries, &ctors));
+        let mut errors = vec![];
         let mut name2id = HashMap::new();
+        let def_span = |id: usize| program.defs[id].span.as_ref().map(|span| span.name);
         for (id, def) in program.defs.iter().enumerate() {
             let current_name = def.name.clone();
-            if name2id.contains_key(&current_name) {
-                return Err(Error::TopLevelNameCollision { name: current_name });
+            if let Some(AnonExpr::DefId(previous)) = name2id.get(&current_name) {
+                errors.push(Error::TopLevelNameCollision {
+                    name: current_name,
+                    span: def_span(id),
+                    previous: def_span(*previous),
+                });
+                continue;
             }
             name2id.insert(current_name, AnonExpr::DefId(id));
         }
         let mut anon_defs = vec![];
         for def in program.defs {
-            anon_defs.push(def.into_anon(&mut name2id)?);
+            match def.into_anon(&mut name2id) {
+                Ok(def) => anon_defs.push(def),
+                Err(error) => errors.push(error),
+            }
         }
-        Ok(AnonProgram { defs: anon_defs })
+        Error::collect(errors)?;
+        Ok((AnonProgram { defs: anon_defs }, warnings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::parser::parse_program;
+
+    #[test]
+    fn reports_every_undefined_name() {
+        let src = "
+            length xs = xs;
+            f x x = lenght x;
+            g = map helper;
+        ";
+        let Err(Error::Multiple(errors)) = parse_program(src).unwrap().into_anon() else {
+            panic!("expected several errors");
+        };
+        let suggestions: Vec<_> = errors
+            .iter()
+            .map(|error| match error {
+                Error::UndefinedIdent {
+                    undefined_name,
+                    suggestion,
+                    ..
+                } => format!("{} {:?}", undefined_name, suggestion),
+                error => error.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            suggestions,
+            [
+                "parameter `x` is bound more than once in definition `f`",
+                "lenght Some(\"length\")",
+                "map None",
+                "helper None",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unlowered_forms() {
+        let src = "f x = letrec go = x in go;";
+        let def = parse_program(src).unwrap().defs.remove(0);
+        let error = def.into_anon(&mut Default::default()).unwrap_err();
+        assert!(matches!(error, Error::UnloweredExpr { form: "letrec", .. }));
     }
 }
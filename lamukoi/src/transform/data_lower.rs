@@ -0,0 +1,354 @@
+// data lowering (runs at the start of Program::into_anon)
+// each constructor of `data T = C1 .. | .. | Cn ..` becomes a Scott-encoded definition:
+// Ck f1 .. fm $C1 .. $Cn = $Ck f1 .. fm
+// `case e of { C1 xs -> a; ..; Cn ys -> b }` becomes `e (λxs. a) .. (λys. b)`, with the arms
+// put into declaration order; a `_ -> d` arm is shared by all constructors without an arm:
+// case e of { C1 x -> a; _ -> d } -> let $default = d in e (λx. a) (λ_ _. $default)
+
+use crate::error::{Error, Result};
+use crate::structures::*;
+use std::collections::HashMap;
+
+const WILDCARD: &str = "_";
+const DEFAULT: &str = "$default";
+
+struct CtorInfo {
+    data: usize,
+    index: usize,
+    arity: usize,
+}
+
+struct Lowerer<'a> {
+    datas: &'a [DataDef],
+    ctors: &'a HashMap<&'a str, CtorInfo>,
+    def_name: &'a str,
+}
+
+impl DataDef {
+    fn ctor_defs(&self) -> Vec<Def> {
+        let conts = self
+            .ctors
+            .iter()
+            .map(|ctor| format!("${}", ctor.name))
+            .collect::<Vec<_>>();
+        let mut defs = vec![];
+        for (ctor, cont) in self.ctors.iter().zip(&conts) {
+            // declared field names are kept unless they cannot serve as parameter names
+            let fields = ctor
+                .fields
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    let unique = ctor.fields.iter().filter(|f| *f == field).count() == 1;
+                    if field != WILDCARD && unique {
+                        field.clone()
+                    } else {
+                        format!("${}", i)
+                    }
+                })
+                .collect::<Vec<_>>();
+            let mut body = Expr::Id(cont.clone());
+            for field in &fields {
+                body = Expr::App(Box::new(body), Box::new(Expr::Id(field.clone())));
+            }
+            let mut params = fields;
+            params.extend(conts.iter().cloned());
+            defs.push(Def {
+                name: ctor.name.clone(),
+                params,
+                body: Some(body),
+                ty: None,
+                span: ctor.span.map(|name| DefSpan {
+                    name,
+                    params: vec![],
+                }),
+                match_fail: false,
+            });
+        }
+        defs
+    }
+}
+
+impl Lowerer<'_> {
+    fn lower_binds(
+        &self,
+        binds: Vec<(Ident, Expr)>,
+        span: Option<Span>,
+    ) -> Result<Vec<(Ident, Expr)>> {
+        binds
+            .into_iter()
+            .map(|(ident, value)| Ok((ident, self.lower(value, span)?)))
+            .collect()
+    }
+
+    fn lower(&self, mut expr: Expr, span: Option<Span>) -> Result<Expr> {
+        let expr = match &mut expr {
+            Expr::Id(_) | Expr::Prim(_) => expr,
+            Expr::App(e1, e2) => {
+                let e1 = self.lower(e1.take(), span)?;
+                let e2 = self.lower(e2.take(), span)?;
+                Expr::App(Box::new(e1), Box::new(e2))
+            }
+            Expr::Lam(idents, e) => {
+                let e = self.lower(e.take(), span)?;
+                Expr::Lam(std::mem::take(idents), Box::new(e))
+            }
+            Expr::Let(binds, e) => {
+                let binds = self.lower_binds(std::mem::take(binds), span)?;
+                Expr::Let(binds, Box::new(self.lower(e.take(), span)?))
+            }
+            Expr::LetRec(binds, e) => {
+                let binds = self.lower_binds(std::mem::take(binds), span)?;
+                Expr::LetRec(binds, Box::new(self.lower(e.take(), span)?))
+            }
+            Expr::Case(e, arms) => self.lower_case(e.take(), std::mem::take(arms), span)?,
+            Expr::Spanned(span, e) => {
+                Expr::Spanned(*span, Box::new(self.lower(e.take(), Some(*span))?))
+            }
+        };
+        Ok(expr)
+    }
+
+    fn lower_case(&self, scrutinee: Expr, arms: Vec<Arm>, span: Option<Span>) -> Result<Expr> {
+        let def_name = self.def_name.to_string();
+        let scrutinee = self.lower(scrutinee, span)?;
+        let mut data = None;
+        for arm in &arms {
+            if arm.ctor == WILDCARD {
+                continue;
+            }
+            let Some(info) = self.ctors.get(&*arm.ctor) else {
+                return Err(Error::UnknownConstructor {
+                    def_name,
+                    ctor: arm.ctor.clone(),
+                    span: arm.span.or(span),
+                });
+            };
+            data = Some(info.data);
+            break;
+        }
+        let mut default = None;
+        let Some(data) = data else {
+            // only wildcard arms: the scrutinee is never inspected
+            let mut arms = arms.into_iter();
+            let arm = arms.next().expect("case has at least one arm");
+            if let Some(duplicate) = arms.next() {
+                return Err(Error::DuplicateCaseArm {
+                    def_name,
+                    ctor: duplicate.ctor,
+                    span: duplicate.span.or(span),
+                });
+            }
+            return self.lower(arm.body, arm.span.or(span));
+        };
+        let data_def = &self.datas[data];
+        let mut slots: Vec<Option<Expr>> = data_def.ctors.iter().map(|_| None).collect();
+        for Arm {
+            ctor,
+            fields,
+            body,
+            span: arm_span,
+        } in arms
+        {
+            let arm_span = arm_span.or(span);
+            if ctor == WILDCARD {
+                if default.is_some() {
+                    return Err(Error::DuplicateCaseArm {
+                        def_name,
+                        ctor,
+                        span: arm_span,
+                    });
+                }
+                default = Some(self.lower(body, arm_span)?);
+                continue;
+            }
+            let Some(info) = self.ctors.get(&*ctor) else {
+                return Err(Error::UnknownConstructor {
+                    def_name,
+                    ctor,
+                    span: arm_span,
+                });
+            };
+            if info.data != data {
+                return Err(Error::CaseArmMismatch {
+                    def_name,
+                    ctor,
+                    data_name: data_def.name.clone(),
+                    span: arm_span,
+                });
+            }
+            if info.arity != fields.len() {
+                return Err(Error::CaseArityMismatch {
+                    def_name,
+                    ctor,
+                    expected: info.arity,
+                    found: fields.len(),
+                    span: arm_span,
+                });
+            }
+            if slots[info.index].is_some() {
+                return Err(Error::DuplicateCaseArm {
+                    def_name,
+                    ctor,
+                    span: arm_span,
+                });
+            }
+            let body = self.lower(body, arm_span)?;
+            slots[info.index] = Some(if fields.is_empty() {
+                body
+            } else {
+                Expr::Lam(fields, Box::new(body))
+            });
+        }
+        let missing = data_def
+            .ctors
+            .iter()
+            .zip(&slots)
+            .filter(|(_, slot)| slot.is_none())
+            .map(|(ctor, _)| ctor.name.clone())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() && default.is_none() {
+            return Err(Error::MissingCaseArm {
+                def_name,
+                data_name: data_def.name.clone(),
+                ctors: missing,
+                span,
+            });
+        }
+        let mut expr = scrutinee;
+        for (ctor, slot) in data_def.ctors.iter().zip(slots) {
+            let branch = slot.unwrap_or_else(|| {
+                let body = Expr::Id(DEFAULT.to_string());
+                if ctor.fields.is_empty() {
+                    body
+                } else {
+                    let wildcards = vec![WILDCARD.to_string(); ctor.fields.len()];
+                    Expr::Lam(wildcards, Box::new(body))
+                }
+            });
+            expr = Expr::App(Box::new(expr), Box::new(branch));
+        }
+        if let (Some(default), false) = (default, missing.is_empty()) {
+            expr = Expr::Let(vec![(DEFAULT.to_string(), default)], Box::new(expr));
+        }
+        Ok(expr)
+    }
+}
+
+impl Program {
+    pub fn lower_data(self) -> Result<Program> {
+        let Program {
+            defs,
+            datas,
+            matches,
+        } = self;
+        let mut ctors = HashMap::new();
+        for (data, data_def) in datas.iter().enumerate() {
+            for (index, ctor) in data_def.ctors.iter().enumerate() {
+                let info = CtorInfo {
+                    data,
+                    index,
+                    arity: ctor.fields.len(),
+                };
+                ctors.insert(&*ctor.name, info);
+            }
+        }
+        let mut lowered = vec![];
+        for def in defs {
+            let Def {
+                name,
+                params,
+                body,
+                ty,
+                span,
+                match_fail,
+            } = def;
+            let body = match body {
+                Some(body) => {
+                    let lowerer = Lowerer {
+                        datas: &datas,
+                        ctors: &ctors,
+                        def_name: &name,
+                    };
+                    Some(lowerer.lower(body, None)?)
+                }
+                None => None,
+            };
+            lowered.push(Def {
+                name,
+                params,
+                body,
+                ty,
+                span,
+                match_fail,
+            });
+        }
+        for data_def in &datas {
+            lowered.extend(data_def.ctor_defs());
+        }
+        Ok(Program {
+            defs: lowered,
+            datas: vec![],
+            matches,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::parser::parse_program;
+
+    #[test]
+    fn constructors_and_case_are_scott_encoded() {
+        let src = "
+            data List = Nil | Cons x xs;
+            #ADD x y;
+            len l = case l of { Cons _ t -> ADD 1 (len t); Nil -> 0 };
+            isNil l = case l of { Nil -> 1; _ -> 0 };
+        ";
+        let program = parse_program(src).unwrap().lower_data().unwrap();
+        let expected = "\
+ADD x y = <builtin>
+len l = l 0 (λ_ t. ADD 1 (len t))
+isNil l = let $default = 0 in l 1 (λ_ _. $default)
+Nil $Nil $Cons = $Nil
+Cons x xs $Nil $Cons = $Cons x xs";
+        assert_eq!(program.to_string(), expected);
+    }
+
+    #[test]
+    fn case_errors() {
+        let data = "data List = Nil | Cons x xs;\n";
+        let lower = |body: &str| {
+            parse_program(&format!("{}f l = {};", data, body))
+                .unwrap()
+                .lower_data()
+                .unwrap_err()
+        };
+        assert!(matches!(
+            lower("case l of { Nil -> 0 }"),
+            Error::MissingCaseArm { ctors, .. } if ctors == ["Cons"]
+        ));
+        assert!(matches!(
+            lower("case l of { Nil -> 0; Cons x -> x }"),
+            Error::CaseArityMismatch {
+                expected: 2,
+                found: 1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            lower("case l of { Nil -> 0; Nil -> 1; _ -> 2 }"),
+            Error::DuplicateCaseArm { .. }
+        ));
+        assert!(matches!(
+            lower("case l of { _ -> 0; _ -> 1 }"),
+            Error::DuplicateCaseArm { ctor, .. } if ctor == "_"
+        ));
+        assert!(matches!(
+            lower("case l of { Nul -> 0; _ -> 1 }"),
+            Error::UnknownConstructor { .. }
+        ));
+    }
+}
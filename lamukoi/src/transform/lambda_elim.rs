@@ -30,6 +30,7 @@ impl AnonExpr {
             }
             AnonExpr::Lam(_) => Err(Error::UnexpectedLambda {
                 def_name: Name::Unnamed(0),
+                span: None,
             }),
         }
     }
@@ -37,22 +38,24 @@ impl AnonExpr {
 
 impl AnonDef {
     fn lambda_elim(self) -> Result<ScDef> {
-        let Self { name, params, body } = self;
+        let Self { name, params, body, span } = self;
         if let Some(body) = body {
             let res = body.lambda_elim(params);
             let Ok((new_body, new_params)) = res else {
-                return Err(Error::UnexpectedLambda { def_name: name });
+                return Err(Error::UnexpectedLambda { def_name: name, span });
             };
             Ok(ScDef {
                 name,
                 params: new_params,
                 body: Some(new_body),
+                span,
             })
         } else {
             Ok(ScDef {
                 name,
                 params,
                 body: None,
+                span,
             })
         }
     }
@@ -60,11 +63,15 @@ impl AnonDef {
 
 impl AnonProgram {
     pub fn lambda_elim(self) -> Result<ScProgram> {
-        let defs = self
-            .defs
-            .into_iter()
-            .map(|def| def.lambda_elim())
-            .collect::<Result<_>>()?;
+        let mut defs = vec![];
+        let mut errors = vec![];
+        for def in self.defs {
+            match def.lambda_elim() {
+                Ok(def) => defs.push(def),
+                Err(error) => errors.push(error),
+            }
+        }
+        Error::collect(errors)?;
         Ok(ScProgram { defs })
     }
 }
@@ -88,12 +88,19 @@ impl AnonExpr {
             AnonExpr::Lam(e) => {
                 let (e, mut defs) = e.lambda_lift(next_def_id);
                 let mut mfes = vec![];
-                let (e, _) = e.extract_mfe(&mut mfes);
+                let (mut e, state) = e.extract_mfe(&mut mfes);
+                if let VarState::Free = state {
+                    // the body never mentions the bound variable, so it is an MFE as a whole
+                    e.weaken();
+                    mfes.push(e.into_anon());
+                    e = MfeExpr::MfeId(0);
+                }
                 let cur_def_id = next_def_id + defs.len();
                 defs.push(AnonDef {
                     name: Name::Unnamed(cur_def_id),
                     params: mfes.len(),
                     body: Some(AnonExpr::Lam(Box::new(e.into_anon()))),
+                    span: None,
                 });
                 let mut new_e = AnonExpr::DefId(cur_def_id);
                 for mfe in mfes {
@@ -178,16 +185,21 @@ impl AnonDef {
     // return: transformed self, extracted defs
     // transform body (expr) and get extracted defs
     pub fn lambda_lift(self, next_def_id: usize) -> (AnonDef, Vec<AnonDef>) {
-        let Self { name, params, body } = self;
+        let Self { name, params, body, span } = self;
         let Some(body) = body else {
-            return (AnonDef { name, params, body: None }, vec![]);
+            return (AnonDef { name, params, body: None, span }, vec![]);
         };
-        let (body, defs) = body.lambda_lift(next_def_id);
+        let (body, mut defs) = body.lambda_lift(next_def_id);
+        // a lifted lambda is located at the definition it comes from
+        for def in &mut defs {
+            def.span = span;
+        }
         (
             AnonDef {
                 name,
                 params,
                 body: Some(body),
+                span,
             },
             defs,
         )
@@ -209,3 +221,16 @@ impl AnonProgram {
         AnonProgram { defs: transformed }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_program;
+
+    #[test]
+    fn lifts_lambdas_ignoring_their_variable() {
+        // `λy. x` mentions only the parent's argument, which must become the new def's argument
+        let src = "k x = λy. x;";
+        let program = parse_program(src).unwrap().into_anon().unwrap().lambda_lift();
+        assert_eq!(program.to_string(), "k x0 = ?1 x0\n?1 x0 = λv0. x0");
+    }
+}
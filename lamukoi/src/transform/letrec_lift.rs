@@ -0,0 +1,332 @@
+// letrec lifting (runs at the start of Program::into_anon)
+// each letrec group becomes a set of fresh top-level definitions named `def$name`
+// the group's free local variables (params, lambda and let binders) become their leading params,
+// and every occurrence of a bound name becomes an application of the fresh def to those variables
+// f x = letrec go n = g x (go n) in go 1
+// -> f x = f$go x 1; f$go x = λn. g x (f$go x n)
+// a binder that would capture one of those variables at an occurrence is renamed to `name$k`
+// f n = letrec go = g n (λn. go) in go
+// -> f n = f$go n; f$go n = g n (λn$1. f$go n)
+
+use crate::structures::*;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+struct Lifted {
+    name: Ident,
+    free: Vec<Ident>,
+}
+
+enum Bound {
+    // a local variable, under the name it has in the output
+    Local(Ident),
+    Lifted(Rc<Lifted>),
+}
+
+// innermost binding last
+type Scope = Vec<(Ident, Bound)>;
+
+fn lookup<'s>(scope: &'s Scope, ident: &str) -> Option<&'s Bound> {
+    scope
+        .iter()
+        .rev()
+        .find(|(name, _)| name == ident)
+        .map(|(_, bound)| bound)
+}
+
+impl Expr {
+    // syntactically free identifiers, in order of first occurrence
+    pub fn free_vars(&self, bound: &mut Vec<Ident>, out: &mut Vec<Ident>) {
+        match self {
+            Expr::Id(ident) => {
+                if !bound.contains(ident) && !out.contains(ident) {
+                    out.push(ident.clone());
+                }
+            }
+            Expr::Prim(_) => {}
+            Expr::App(e1, e2) => {
+                e1.free_vars(bound, out);
+                e2.free_vars(bound, out);
+            }
+            Expr::Lam(idents, e) => {
+                let prev_len = bound.len();
+                bound.extend(idents.iter().cloned());
+                e.free_vars(bound, out);
+                bound.truncate(prev_len);
+            }
+            Expr::Let(binds, e) => {
+                let prev_len = bound.len();
+                for (ident, value) in binds {
+                    value.free_vars(bound, out);
+                    bound.push(ident.clone());
+                }
+                e.free_vars(bound, out);
+                bound.truncate(prev_len);
+            }
+            Expr::LetRec(binds, e) => {
+                let prev_len = bound.len();
+                bound.extend(binds.iter().map(|(ident, _)| ident.clone()));
+                for (_, value) in binds {
+                    value.free_vars(bound, out);
+                }
+                e.free_vars(bound, out);
+                bound.truncate(prev_len);
+            }
+            Expr::Case(e, arms) => {
+                e.free_vars(bound, out);
+                for arm in arms {
+                    let prev_len = bound.len();
+                    bound.extend(arm.fields.iter().cloned());
+                    arm.body.free_vars(bound, out);
+                    bound.truncate(prev_len);
+                }
+            }
+            Expr::Spanned(_, e) => e.free_vars(bound, out),
+        }
+    }
+}
+
+struct Lifter<'a> {
+    def_name: &'a str,
+    taken: &'a mut HashSet<Ident>,
+    new_defs: Vec<Def>,
+    renamed: usize,
+}
+
+impl Lifter<'_> {
+    fn fresh_name(&mut self, ident: &str) -> Ident {
+        let mut name = format!("{}${}", self.def_name, ident);
+        let mut suffix = 1usize;
+        while self.taken.contains(&name) {
+            suffix += 1;
+            name = format!("{}${}${}", self.def_name, ident, suffix);
+        }
+        self.taken.insert(name.clone());
+        name
+    }
+
+    // bring a local variable into scope, renamed if it would capture a lifted group's variable
+    fn bind(&mut self, ident: Ident, scope: &mut Scope) -> Ident {
+        let captures = scope.iter().any(|(_, bound)| {
+            matches!(bound, Bound::Lifted(lifted) if lifted.free.contains(&ident))
+        });
+        let name = if captures {
+            self.renamed += 1;
+            format!("{}${}", ident, self.renamed)
+        } else {
+            ident.clone()
+        };
+        scope.push((ident, Bound::Local(name.clone())));
+        name
+    }
+
+    fn lift(&mut self, mut expr: Expr, scope: &mut Scope) -> Expr {
+        match &mut expr {
+            Expr::Id(ident) => match lookup(scope, ident) {
+                Some(Bound::Local(name)) => Expr::Id(name.clone()),
+                Some(Bound::Lifted(lifted)) => {
+                    let mut e = Expr::Id(lifted.name.clone());
+                    for var in &lifted.free {
+                        e = Expr::App(Box::new(e), Box::new(Expr::Id(var.clone())));
+                    }
+                    e
+                }
+                None => Expr::Id(std::mem::take(ident)),
+            },
+            Expr::Prim(int) => Expr::Prim(*int),
+            Expr::App(e1, e2) => {
+                let e1 = self.lift(e1.take(), scope);
+                let e2 = self.lift(e2.take(), scope);
+                Expr::App(Box::new(e1), Box::new(e2))
+            }
+            Expr::Lam(idents, e) => {
+                let prev_len = scope.len();
+                let idents = idents
+                    .drain(..)
+                    .map(|ident| self.bind(ident, scope))
+                    .collect();
+                let e = self.lift(e.take(), scope);
+                scope.truncate(prev_len);
+                Expr::Lam(idents, Box::new(e))
+            }
+            Expr::Let(binds, e) => {
+                let prev_len = scope.len();
+                let mut new_binds = vec![];
+                for (ident, value) in binds.drain(..) {
+                    let value = self.lift(value, scope);
+                    new_binds.push((self.bind(ident, scope), value));
+                }
+                let e = self.lift(e.take(), scope);
+                scope.truncate(prev_len);
+                Expr::Let(new_binds, Box::new(e))
+            }
+            Expr::LetRec(binds, e) => {
+                let mut group = binds.iter().map(|(ident, _)| ident.clone()).collect();
+                let mut candidates = vec![];
+                for (_, value) in binds.iter() {
+                    value.free_vars(&mut group, &mut candidates);
+                }
+                let mut free: Vec<Ident> = vec![];
+                for candidate in candidates {
+                    let vars = match lookup(scope, &candidate) {
+                        Some(Bound::Local(name)) => vec![name.clone()],
+                        Some(Bound::Lifted(lifted)) => lifted.free.clone(),
+                        None => vec![],
+                    };
+                    for var in vars {
+                        if !free.contains(&var) {
+                            free.push(var);
+                        }
+                    }
+                }
+                let mut group = vec![];
+                for (ident, _) in binds.iter() {
+                    let name = self.fresh_name(ident);
+                    group.push(Rc::new(Lifted {
+                        name,
+                        free: free.clone(),
+                    }));
+                }
+                let prev_len = scope.len();
+                for ((ident, _), lifted) in binds.iter().zip(&group) {
+                    scope.push((ident.clone(), Bound::Lifted(lifted.clone())));
+                }
+                for ((_, value), lifted) in binds.drain(..).zip(&group) {
+                    let body = self.lift(value, scope);
+                    self.new_defs.push(Def {
+                        name: lifted.name.clone(),
+                        params: lifted.free.clone(),
+                        body: Some(body),
+                        ty: None,
+                        span: None,
+                        match_fail: false,
+                    });
+                }
+                let e = self.lift(e.take(), scope);
+                scope.truncate(prev_len);
+                e
+            }
+            Expr::Case(e, arms) => {
+                let e = self.lift(e.take(), scope);
+                let mut new_arms = vec![];
+                for Arm {
+                    ctor,
+                    fields,
+                    body,
+                    span,
+                } in arms.drain(..)
+                {
+                    let prev_len = scope.len();
+                    let fields = fields
+                        .into_iter()
+                        .map(|field| self.bind(field, scope))
+                        .collect();
+                    let body = self.lift(body, scope);
+                    scope.truncate(prev_len);
+                    new_arms.push(Arm {
+                        ctor,
+                        fields,
+                        body,
+                        span,
+                    });
+                }
+                Expr::Case(Box::new(e), new_arms)
+            }
+            Expr::Spanned(span, e) => Expr::Spanned(*span, Box::new(self.lift(e.take(), scope))),
+        }
+    }
+}
+
+impl Program {
+    pub fn lift_letrec(self) -> Program {
+        let mut taken = self.defs.iter().map(|def| def.name.clone()).collect();
+        let mut defs = vec![];
+        let mut lifted = vec![];
+        for def in self.defs {
+            let Some(body) = def.body else {
+                defs.push(def);
+                continue;
+            };
+            let Def {
+                name, params, span, ..
+            } = def;
+            let mut lifter = Lifter {
+                def_name: &name,
+                taken: &mut taken,
+                new_defs: vec![],
+                renamed: 0,
+            };
+            let mut scope = params
+                .iter()
+                .map(|param| (param.clone(), Bound::Local(param.clone())))
+                .collect();
+            let body = lifter.lift(body, &mut scope);
+            lifted.extend(lifter.new_defs);
+            defs.push(Def {
+                name,
+                params,
+                body: Some(body),
+                ty: None,
+                span,
+                match_fail: false,
+            });
+        }
+        defs.extend(lifted);
+        Program {
+            defs,
+            datas: self.datas,
+            matches: self.matches,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_program;
+
+    #[test]
+    fn letrec_becomes_supercombinators() {
+        let src = "
+            #g x y;
+            f x = letrec go n = g x (go n) in go 1;
+            h x y = letrec even n = g y (odd n); odd n = letrec k = even x in k in even 2;
+        ";
+        let program = parse_program(src).unwrap().lift_letrec();
+        let expected = "\
+g x y = <builtin>
+f x = f$go x 1
+h x y = h$even y x 2
+f$go x = λn. g x (f$go x n)
+h$even y x = λn. g y (h$odd y x n)
+h$k y x = h$even y x x
+h$odd y x = λn. h$k y x";
+        assert_eq!(program.to_string(), expected);
+    }
+
+    #[test]
+    fn renames_binders_that_would_capture() {
+        let src = "
+            #g x y;
+            f n = letrec go = g n (λn. go) in go;
+            h x = letrec go = x in let x = 1 in case x of { _ -> go };
+        ";
+        let program = parse_program(src).unwrap().lift_letrec();
+        let expected = "\
+g x y = <builtin>
+f n = f$go n
+h x = let x$1 = 1 in case x$1 of { _ -> h$go x }
+f$go n = g n (λn$1. f$go n)
+h$go x = x";
+        assert_eq!(program.to_string(), expected);
+    }
+
+    #[test]
+    fn let_becomes_application() {
+        let src = "twice f x = let y = f x; z = f y in z;";
+        let program = parse_program(src).unwrap().into_anon().unwrap();
+        assert_eq!(
+            program.to_string(),
+            "twice x0 x1 = (λv0. (λv1. v1) (x0 v0)) (x0 x1)"
+        );
+    }
+}
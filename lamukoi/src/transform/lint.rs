@@ -0,0 +1,179 @@
+// lints over a lowered program (after data lowering and letrec lifting, see into_anon_with_warnings)
+// - definitions not reachable from any of the entry points
+// - parameters never mentioned in the body; names starting with `_` are exempt
+// - lambda and let binders that shadow a top-level definition
+// generated names (containing `$`) and library definitions (`M.name`) are not reported
+
+use crate::error::Warning;
+use crate::structures::*;
+use std::collections::{HashMap, HashSet};
+
+fn is_generated(name: &str) -> bool {
+    name.contains('$')
+}
+
+fn is_library(name: &str) -> bool {
+    name.contains('.')
+}
+
+struct Shadowing<'a> {
+    top_level: &'a HashSet<&'a str>,
+    def_name: &'a str,
+    span: Option<Span>,
+    warnings: &'a mut Vec<Warning>,
+}
+
+impl Shadowing<'_> {
+    fn binder(&mut self, name: &str) {
+        if self.top_level.contains(name) && !is_generated(name) {
+            self.warnings.push(Warning::ShadowsTopLevel {
+                def_name: self.def_name.to_string(),
+                name: name.to_string(),
+                span: self.span,
+            });
+        }
+    }
+
+    fn visit(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Id(_) | Expr::Prim(_) => {}
+            Expr::App(e1, e2) => {
+                self.visit(e1);
+                self.visit(e2);
+            }
+            Expr::Lam(idents, e) => {
+                idents.iter().for_each(|ident| self.binder(ident));
+                self.visit(e);
+            }
+            Expr::Let(binds, e) | Expr::LetRec(binds, e) => {
+                for (ident, value) in binds {
+                    self.binder(ident);
+                    self.visit(value);
+                }
+                self.visit(e);
+            }
+            Expr::Case(e, arms) => {
+                self.visit(e);
+                for arm in arms {
+                    arm.fields.iter().for_each(|field| self.binder(field));
+                    self.visit(&arm.body);
+                }
+            }
+            Expr::Spanned(span, e) => {
+                let outer = self.span.replace(*span);
+                self.visit(e);
+                self.span = outer;
+            }
+        }
+    }
+}
+
+impl Program {
+    // `ctors` are the constructor names of the program before data lowering, which are
+    // definitions by now but are not reported as unused
+    pub fn lint(&self, entries: &[&str], ctors: &HashSet<Ident>) -> Vec<Warning> {
+        let mut warnings = vec![];
+        let top_level: HashSet<&str> = self.defs.iter().map(|def| &*def.name).collect();
+        let mut free = HashMap::new();
+        for def in &self.defs {
+            let mut vars = vec![];
+            if let Some(body) = &def.body {
+                body.free_vars(&mut def.params.clone(), &mut vars);
+            }
+            free.insert(&*def.name, vars);
+        }
+
+        if !entries.is_empty() {
+            let mut reachable: HashSet<&str> = HashSet::new();
+            let mut stack = entries.to_vec();
+            while let Some(name) = stack.pop() {
+                if !reachable.insert(name) {
+                    continue;
+                }
+                let uses = free.get(name).into_iter().flatten();
+                stack.extend(
+                    uses.map(|name| &**name)
+                        .filter(|name| top_level.contains(name)),
+                );
+            }
+            for def in &self.defs {
+                let name = &*def.name;
+                if reachable.contains(name)
+                    || ctors.contains(name)
+                    || is_generated(name)
+                    || is_library(name)
+                {
+                    continue;
+                }
+                warnings.push(Warning::UnusedDef {
+                    def_name: def.name.clone(),
+                    span: def.span.as_ref().map(|span| span.name),
+                });
+            }
+        }
+
+        for def in &self.defs {
+            let Some(body) = &def.body else {
+                continue;
+            };
+            if is_generated(&def.name) || is_library(&def.name) || ctors.contains(&def.name) {
+                continue;
+            }
+            let mut used = vec![];
+            body.free_vars(&mut vec![], &mut used);
+            for (i, param) in def.params.iter().enumerate() {
+                if param.starts_with('_') || is_generated(param) || used.contains(param) {
+                    continue;
+                }
+                warnings.push(Warning::UnusedParam {
+                    def_name: def.name.clone(),
+                    param: param.clone(),
+                    span: def
+                        .span
+                        .as_ref()
+                        .and_then(|span| span.params.get(i).copied()),
+                });
+            }
+            let mut shadowing = Shadowing {
+                top_level: &top_level,
+                def_name: &def.name,
+                span: None,
+                warnings: &mut warnings,
+            };
+            shadowing.visit(body);
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Warning;
+    use crate::parser::parse_program;
+
+    #[test]
+    fn warns_on_unused_and_shadowing_names() {
+        let src = "
+            data Pair = Pair x y;
+            const x y = x;
+            skip _x y = y;
+            unused = 0;
+            main = let const = 1 in skip (Pair 1 2) (λunused. const);
+        ";
+        let (_, warnings) = parse_program(src)
+            .unwrap()
+            .into_anon_with_warnings(&["main"])
+            .unwrap();
+        let warnings: Vec<_> = warnings.iter().map(Warning::to_string).collect();
+        assert_eq!(
+            warnings,
+            [
+                "definition `const` is never used",
+                "definition `unused` is never used",
+                "unused parameter `y` in definition `const`",
+                "local variable `const` in definition `main` shadows a top-level definition",
+                "local variable `unused` in definition `main` shadows a top-level definition",
+            ]
+        );
+    }
+}
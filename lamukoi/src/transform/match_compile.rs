@@ -0,0 +1,671 @@
+// match compilation (runs at the start of Program::into_anon, before data lowering)
+// definitions given by equations with nested patterns become plain definitions whose bodies
+// are decision trees of `case` expressions over constructors and `EQ` tests over integers:
+// len SNil = 0; len (SCons _ xs) = ADD 1 (len xs)
+// -> len $p0 = case $p0 of { SNil -> 0; SCons $f0 $f1 -> ADD 1 (len $f1) }
+// integer patterns need a primop `EQ x y` that returns a boolean selector (λt f. t or λt f. f);
+// without one they are rejected with Error::LiteralPatternWithoutEq
+// an input that matches no equation reaches the primop `name$fail`, which is marked with
+// `Def::match_fail` and fails at runtime
+// a parameter of a plain definition that names a constructor is a pattern as well, and such a
+// definition forms one pattern-matching definition with the plain definitions of the same name
+// right next to it: not True = False; not False = True
+// exhaustiveness and redundancy are checked separately with the usefulness algorithm
+// (Maranget, "Warnings for pattern matching")
+
+use crate::error::{Error, Result, Warning};
+use crate::structures::*;
+use std::collections::HashMap;
+
+pub const LIT_EQ: &str = "EQ";
+const MATCH_FAIL_SUFFIX: &str = "$fail";
+const WILDCARD: &str = "_";
+
+fn wild() -> Pattern {
+    Pattern::Var(WILDCARD.to_string())
+}
+
+fn is_wild(pattern: &Pattern) -> bool {
+    matches!(pattern, Pattern::Var(_))
+}
+
+struct CtorInfo {
+    data: usize,
+    arity: usize,
+}
+
+struct Ctx<'a> {
+    datas: &'a [DataDef],
+    ctors: HashMap<&'a str, CtorInfo>,
+    // the program declares a primop `EQ x y` for literal patterns
+    lit_eq: bool,
+}
+
+// rows specialized to constructor `ctor`: its arguments replace the first column
+fn specialize_ctor(rows: &[Vec<Pattern>], ctor: &str, arity: usize) -> Vec<Vec<Pattern>> {
+    let mut specialized = vec![];
+    for row in rows {
+        let mut new_row = match &row[0] {
+            Pattern::Ctor(c, args) if c == ctor => args.clone(),
+            Pattern::Var(_) => vec![wild(); arity],
+            _ => continue,
+        };
+        new_row.extend(row[1..].iter().cloned());
+        specialized.push(new_row);
+    }
+    specialized
+}
+
+fn specialize_lit(rows: &[Vec<Pattern>], lit: i64) -> Vec<Vec<Pattern>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pattern::Var(_)) || row[0] == Pattern::Lit(lit))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn default_rows(rows: &[Vec<Pattern>]) -> Vec<Vec<Pattern>> {
+    rows.iter()
+        .filter(|row| is_wild(&row[0]))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+// the first variable bound more than once in `patterns`
+fn repeated_var(patterns: &[Pattern]) -> Option<&Ident> {
+    fn collect<'p>(pattern: &'p Pattern, vars: &mut Vec<&'p Ident>) -> Option<&'p Ident> {
+        match pattern {
+            Pattern::Var(var) if var == WILDCARD => None,
+            Pattern::Var(var) if vars.contains(&var) => Some(var),
+            Pattern::Var(var) => {
+                vars.push(var);
+                None
+            }
+            Pattern::Lit(_) => None,
+            Pattern::Ctor(_, args) => args.iter().find_map(|arg| collect(arg, vars)),
+        }
+    }
+    let mut vars = vec![];
+    patterns.iter().find_map(|pattern| collect(pattern, &mut vars))
+}
+
+fn rebuild_ctor(ctor: &str, arity: usize, mut witness: Vec<Pattern>) -> Vec<Pattern> {
+    let rest = witness.split_off(arity);
+    let mut rebuilt = vec![Pattern::Ctor(ctor.to_string(), witness)];
+    rebuilt.extend(rest);
+    rebuilt
+}
+
+impl Ctx<'_> {
+    fn resolve(&self, pattern: Pattern, def_name: &str, span: Option<Span>) -> Result<Pattern> {
+        let (ctor, args) = match pattern {
+            Pattern::Var(ident) if self.ctors.contains_key(&*ident) => (ident, vec![]),
+            Pattern::Var(_) | Pattern::Lit(_) => return Ok(pattern),
+            Pattern::Ctor(ctor, args) => (ctor, args),
+        };
+        let Some(info) = self.ctors.get(&*ctor) else {
+            return Err(Error::UnknownConstructor {
+                def_name: def_name.to_string(),
+                ctor,
+                span,
+            });
+        };
+        if info.arity != args.len() {
+            return Err(Error::CaseArityMismatch {
+                def_name: def_name.to_string(),
+                ctor,
+                expected: info.arity,
+                found: args.len(),
+                span,
+            });
+        }
+        let args = args
+            .into_iter()
+            .map(|arg| self.resolve(arg, def_name, span))
+            .collect::<Result<_>>()?;
+        Ok(Pattern::Ctor(ctor, args))
+    }
+
+    fn data_of(&self, ctor: &str) -> &DataDef {
+        &self.datas[self.ctors[ctor].data]
+    }
+
+    // a value vector matched by `v` but by none of `rows`, if there is one
+    fn useful(&self, rows: &[Vec<Pattern>], v: &[Pattern]) -> Option<Vec<Pattern>> {
+        let Some(head) = v.first() else {
+            return rows.is_empty().then(Vec::new);
+        };
+        match head {
+            Pattern::Ctor(ctor, args) => {
+                let mut v2 = args.clone();
+                v2.extend(v[1..].iter().cloned());
+                let witness = self.useful(&specialize_ctor(rows, ctor, args.len()), &v2)?;
+                Some(rebuild_ctor(ctor, args.len(), witness))
+            }
+            Pattern::Lit(lit) => {
+                let witness = self.useful(&specialize_lit(rows, *lit), &v[1..])?;
+                Some([vec![Pattern::Lit(*lit)], witness].concat())
+            }
+            Pattern::Var(_) => {
+                let mut heads = vec![];
+                let mut lits = vec![];
+                for row in rows {
+                    match &row[0] {
+                        Pattern::Ctor(ctor, _) if !heads.contains(&&**ctor) => heads.push(&**ctor),
+                        Pattern::Lit(lit) => lits.push(*lit),
+                        _ => {}
+                    }
+                }
+                if let Some(first) = heads.first() {
+                    let data = self.data_of(first);
+                    let missing = data.ctors.iter().find(|ctor| !heads.contains(&&*ctor.name));
+                    if missing.is_none() {
+                        for ctor in &data.ctors {
+                            let arity = ctor.fields.len();
+                            let mut v2 = vec![wild(); arity];
+                            v2.extend(v[1..].iter().cloned());
+                            let specialized = specialize_ctor(rows, &ctor.name, arity);
+                            if let Some(witness) = self.useful(&specialized, &v2) {
+                                return Some(rebuild_ctor(&ctor.name, arity, witness));
+                            }
+                        }
+                        return None;
+                    }
+                    let witness = self.useful(&default_rows(rows), &v[1..])?;
+                    let ctor = missing.unwrap();
+                    let head = Pattern::Ctor(ctor.name.clone(), vec![wild(); ctor.fields.len()]);
+                    return Some([vec![head], witness].concat());
+                }
+                let witness = self.useful(&default_rows(rows), &v[1..])?;
+                let head = match lits.iter().max() {
+                    Some(max) => Pattern::Lit(max.wrapping_add(1)),
+                    None => wild(),
+                };
+                Some([vec![head], witness].concat())
+            }
+        }
+    }
+}
+
+impl Expr {
+    // replace free occurrences of `from` with `to`; `to` must not be bound inside `self`
+    pub fn rename(mut self, from: &str, to: &str) -> Expr {
+        let shadows = |idents: &[Ident]| idents.iter().any(|ident| ident == from);
+        match &mut self {
+            Expr::Id(ident) if *ident == from => Expr::Id(to.to_string()),
+            Expr::Id(_) | Expr::Prim(_) => self,
+            Expr::App(e1, e2) => {
+                let e1 = e1.take().rename(from, to);
+                Expr::App(Box::new(e1), Box::new(e2.take().rename(from, to)))
+            }
+            Expr::Lam(idents, e) => {
+                let e = if shadows(idents) {
+                    e.take()
+                } else {
+                    e.take().rename(from, to)
+                };
+                Expr::Lam(std::mem::take(idents), Box::new(e))
+            }
+            Expr::Let(binds, e) => {
+                let mut shadowed = false;
+                let mut new_binds = vec![];
+                for (ident, value) in binds.drain(..) {
+                    let value = if shadowed {
+                        value
+                    } else {
+                        value.rename(from, to)
+                    };
+                    shadowed |= ident == from;
+                    new_binds.push((ident, value));
+                }
+                let e = if shadowed {
+                    e.take()
+                } else {
+                    e.take().rename(from, to)
+                };
+                Expr::Let(new_binds, Box::new(e))
+            }
+            Expr::LetRec(binds, e) => {
+                if binds.iter().any(|(ident, _)| ident == from) {
+                    return self;
+                }
+                let binds = binds
+                    .drain(..)
+                    .map(|(ident, value)| (ident, value.rename(from, to)))
+                    .collect();
+                Expr::LetRec(binds, Box::new(e.take().rename(from, to)))
+            }
+            Expr::Case(e, arms) => {
+                let arms = arms
+                    .drain(..)
+                    .map(|mut arm| {
+                        if !shadows(&arm.fields) {
+                            arm.body = arm.body.rename(from, to);
+                        }
+                        arm
+                    })
+                    .collect();
+                Expr::Case(Box::new(e.take().rename(from, to)), arms)
+            }
+            Expr::Spanned(span, e) => Expr::Spanned(*span, Box::new(e.take().rename(from, to))),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Row {
+    patterns: Vec<Pattern>,
+    equation: usize,
+    binds: Vec<(Ident, Ident)>,
+}
+
+struct Compiler<'a> {
+    ctx: &'a Ctx<'a>,
+    def_name: &'a str,
+    bodies: &'a [Expr],
+    span: Option<Span>,
+    next_field: usize,
+    failed: bool,
+}
+
+impl Compiler<'_> {
+    fn fresh_field(&mut self) -> Ident {
+        self.next_field += 1;
+        format!("$f{}", self.next_field - 1)
+    }
+
+    fn compile(&mut self, occs: &[Ident], rows: Vec<Row>) -> Result<Expr> {
+        let Some(first) = rows.first() else {
+            self.failed = true;
+            return Ok(Expr::Id(format!("{}{}", self.def_name, MATCH_FAIL_SUFFIX)));
+        };
+        let Some(col) = first.patterns.iter().position(|p| !is_wild(p)) else {
+            let mut body = self.bodies[first.equation].clone();
+            let vars = first
+                .patterns
+                .iter()
+                .zip(occs)
+                .filter_map(|(p, occ)| match p {
+                    Pattern::Var(var) => Some((var, occ)),
+                    _ => None,
+                });
+            for (var, occ) in first.binds.iter().map(|(v, o)| (v, o)).chain(vars) {
+                if var != WILDCARD {
+                    body = body.rename(var, occ);
+                }
+            }
+            return Ok(body);
+        };
+        let occ = occs[col].clone();
+        let has_ctor = rows
+            .iter()
+            .any(|row| matches!(row.patterns[col], Pattern::Ctor(..)));
+        let has_lit = rows
+            .iter()
+            .any(|row| matches!(row.patterns[col], Pattern::Lit(_)));
+        if has_ctor && has_lit {
+            return Err(Error::MixedPatterns {
+                def_name: self.def_name.to_string(),
+                span: self.span,
+            });
+        }
+        // rows that do not test this column, with the column removed
+        let default = |rows: &[Row]| {
+            let mut default = vec![];
+            for row in rows {
+                let Pattern::Var(var) = &row.patterns[col] else {
+                    continue;
+                };
+                let mut row = row.clone();
+                if var != WILDCARD {
+                    row.binds.push((var.clone(), occ.clone()));
+                }
+                row.patterns.remove(col);
+                default.push(row);
+            }
+            default
+        };
+        let mut rest_occs = occs.to_vec();
+        rest_occs.remove(col);
+        if has_lit {
+            if !self.ctx.lit_eq {
+                return Err(Error::LiteralPatternWithoutEq {
+                    def_name: self.def_name.to_string(),
+                    span: self.span,
+                });
+            }
+            let mut lits = vec![];
+            for row in &rows {
+                if let Pattern::Lit(lit) = row.patterns[col] {
+                    if !lits.contains(&lit) {
+                        lits.push(lit);
+                    }
+                }
+            }
+            let mut branches = vec![];
+            for &lit in &lits {
+                let mut specialized = vec![];
+                for row in &rows {
+                    let mut row = row.clone();
+                    match &row.patterns[col] {
+                        Pattern::Lit(l) if *l == lit => {}
+                        Pattern::Var(var) if var != WILDCARD => {
+                            row.binds.push((var.clone(), occ.clone()))
+                        }
+                        Pattern::Var(_) => {}
+                        _ => continue,
+                    }
+                    row.patterns.remove(col);
+                    specialized.push(row);
+                }
+                branches.push(self.compile(&rest_occs, specialized)?);
+            }
+            let mut expr = self.compile(&rest_occs, default(&rows))?;
+            for (lit, branch) in lits.into_iter().zip(branches).rev() {
+                let test = [Expr::Id(occ.clone()), Expr::Prim(lit), branch, expr];
+                expr = test
+                    .into_iter()
+                    .fold(Expr::Id(LIT_EQ.to_string()), |f, arg| {
+                        Expr::App(Box::new(f), Box::new(arg))
+                    });
+            }
+            return Ok(expr);
+        }
+        let mut heads: Vec<(Ident, usize)> = vec![];
+        for row in &rows {
+            if let Pattern::Ctor(ctor, args) = &row.patterns[col] {
+                if !heads.iter().any(|(c, _)| c == ctor) {
+                    heads.push((ctor.clone(), args.len()));
+                }
+            }
+        }
+        let mut arms = vec![];
+        for (ctor, arity) in &heads {
+            let fields = (0..*arity).map(|_| self.fresh_field()).collect::<Vec<_>>();
+            let mut new_occs = occs[..col].to_vec();
+            new_occs.extend(fields.iter().cloned());
+            new_occs.extend(occs[col + 1..].iter().cloned());
+            let mut specialized = vec![];
+            for row in &rows {
+                let mut row = row.clone();
+                let args = match &row.patterns[col] {
+                    Pattern::Ctor(c, args) if c == ctor => args.clone(),
+                    Pattern::Var(var) => {
+                        if var != WILDCARD {
+                            row.binds.push((var.clone(), occ.clone()));
+                        }
+                        vec![wild(); *arity]
+                    }
+                    _ => continue,
+                };
+                row.patterns.splice(col..col + 1, args);
+                specialized.push(row);
+            }
+            let body = self.compile(&new_occs, specialized)?;
+            arms.push(Arm {
+                ctor: ctor.clone(),
+                fields,
+                body,
+                span: None,
+            });
+        }
+        if heads.len() < self.ctx.data_of(&heads[0].0).ctors.len() {
+            let body = self.compile(&rest_occs, default(&rows))?;
+            arms.push(Arm {
+                ctor: WILDCARD.to_string(),
+                fields: vec![],
+                body,
+                span: None,
+            });
+        }
+        Ok(Expr::Case(Box::new(Expr::Id(occ)), arms))
+    }
+}
+
+impl MatchDef {
+    fn compile(self, ctx: &Ctx, warnings: &mut Vec<Warning>) -> Result<Vec<Def>> {
+        let MatchDef {
+            name,
+            equations,
+            span,
+        } = self;
+        let arity = equations[0].patterns.len();
+        let mut rows = vec![];
+        let mut bodies = vec![];
+        let mut spans = vec![];
+        for equation in equations {
+            let eq_span = equation.span.or(span);
+            if equation.patterns.len() != arity {
+                return Err(Error::EquationArityMismatch {
+                    def_name: name,
+                    expected: arity,
+                    found: equation.patterns.len(),
+                    span: eq_span,
+                });
+            }
+            let patterns = equation
+                .patterns
+                .into_iter()
+                .map(|p| ctx.resolve(p, &name, eq_span))
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(var) = repeated_var(&patterns) {
+                return Err(Error::NonLinearPattern {
+                    def_name: name,
+                    var: var.clone(),
+                    span: eq_span,
+                });
+            }
+            rows.push(patterns);
+            bodies.push(equation.body);
+            spans.push(eq_span);
+        }
+        for (i, row) in rows.iter().enumerate() {
+            if ctx.useful(&rows[..i], row).is_none() {
+                warnings.push(Warning::RedundantEquation {
+                    def_name: name.clone(),
+                    span: spans[i],
+                });
+            }
+        }
+        if let Some(witness) = ctx.useful(&rows, &vec![wild(); arity]) {
+            let mut missing = name.clone();
+            for pattern in witness {
+                missing = format!("{} {}", missing, pattern);
+            }
+            warnings.push(Warning::NonExhaustiveMatch {
+                def_name: name.clone(),
+                missing,
+                span,
+            });
+        }
+        let params = (0..arity).map(|i| format!("$p{}", i)).collect::<Vec<_>>();
+        let rows = rows
+            .into_iter()
+            .enumerate()
+            .map(|(equation, patterns)| Row {
+                patterns,
+                equation,
+                binds: vec![],
+            })
+            .collect();
+        let mut compiler = Compiler {
+            ctx,
+            def_name: &name,
+            bodies: &bodies,
+            span,
+            next_field: 0,
+            failed: false,
+        };
+        let body = compiler.compile(&params, rows)?;
+        let mut defs = vec![];
+        if compiler.failed {
+            defs.push(Def {
+                name: format!("{}{}", name, MATCH_FAIL_SUFFIX),
+                params: vec![],
+                body: None,
+                ty: None,
+                span: None,
+                match_fail: true,
+            });
+        }
+        defs.push(Def {
+            name,
+            params,
+            body: Some(body),
+            ty: None,
+            span: span.map(|name| DefSpan {
+                name,
+                params: vec![],
+            }),
+            match_fail: false,
+        });
+        Ok(defs)
+    }
+}
+
+impl Program {
+    pub fn compile_matches(self) -> Result<(Program, Vec<Warning>)> {
+        let Program {
+            defs,
+            datas,
+            mut matches,
+        } = self;
+        let mut ctors = HashMap::new();
+        for (data, data_def) in datas.iter().enumerate() {
+            for ctor in &data_def.ctors {
+                let arity = ctor.fields.len();
+                ctors.insert(&*ctor.name, CtorInfo { data, arity });
+            }
+        }
+        let lit_eq = defs
+            .iter()
+            .any(|def| def.name == LIT_EQ && def.body.is_none() && def.params.len() == 2);
+        let ctx = Ctx {
+            datas: &datas,
+            ctors,
+            lit_eq,
+        };
+        let mut plain_defs = vec![];
+        let mut defs = defs.into_iter().peekable();
+        while let Some(def) = defs.next() {
+            let mut run = vec![def];
+            while let Some(next) = defs.next_if(|next| {
+                next.name == run[0].name && next.body.is_some() && run[0].body.is_some()
+            }) {
+                run.push(next);
+            }
+            let has_ctor_param = |def: &Def| {
+                def.body.is_some() && def.params.iter().any(|p| ctx.ctors.contains_key(&**p))
+            };
+            if !run.iter().any(has_ctor_param) {
+                plain_defs.extend(run);
+                continue;
+            }
+            let name = run[0].name.clone();
+            let span = run[0].span.as_ref().map(|span| span.name);
+            let equations = run
+                .into_iter()
+                .map(|def| Equation {
+                    patterns: def.params.into_iter().map(Pattern::Var).collect(),
+                    body: def.body.unwrap(),
+                    span: def.span.map(|span| span.name),
+                })
+                .collect();
+            matches.push(MatchDef {
+                name,
+                equations,
+                span,
+            });
+        }
+        let mut warnings = vec![];
+        for matchdef in matches {
+            plain_defs.extend(matchdef.compile(&ctx, &mut warnings)?);
+        }
+        let program = Program {
+            defs: plain_defs,
+            datas,
+            matches: vec![],
+        };
+        Ok((program, warnings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{Error, Warning};
+    use crate::parser::parse_program;
+
+    #[test]
+    fn equations_become_decision_trees() {
+        let src = "
+            data List = Nil | Cons x xs;
+            #ADD x y;
+            #EQ x y;
+            len Nil = 0;
+            len (Cons _ xs) = ADD 1 (len xs);
+            fib 0 = 0;
+            fib 1 = 1;
+            fib n = ADD (fib (SUB n 1)) (fib (SUB n 2));
+            data Bool = True | False;
+            not True = False;
+            not False = True;
+        ";
+        let (program, warnings) = parse_program(src).unwrap().compile_matches().unwrap();
+        assert!(warnings.is_empty());
+        let expected = "\
+data List = Nil | Cons x xs
+data Bool = True | False
+ADD x y = <builtin>
+EQ x y = <builtin>
+len $p0 = case $p0 of { Nil -> 0; Cons $f0 $f1 -> ADD 1 (len $f1) }
+fib $p0 = EQ $p0 0 0 (EQ $p0 1 1 (ADD (fib (SUB $p0 1)) (fib (SUB $p0 2))))
+not $p0 = case $p0 of { True -> False; False -> True }";
+        assert_eq!(program.to_string(), expected);
+    }
+
+    #[test]
+    fn warns_on_missing_and_redundant_equations() {
+        let src = "
+            data List = Nil | Cons x xs;
+            data Bool = True | False;
+            zip Nil Nil = True;
+            zip (Cons _ xs) (Cons _ ys) = zip xs ys;
+            zip Nil (Cons True _) = False;
+            zip Nil (Cons True Nil) = False;
+            zip Nil (Cons _ (Cons _ _)) = False;
+        ";
+        let (program, warnings) = parse_program(src).unwrap().compile_matches().unwrap();
+        let [Warning::RedundantEquation { .. }, Warning::NonExhaustiveMatch { missing, .. }] =
+            &warnings[..]
+        else {
+            panic!("unexpected warnings {:?}", warnings);
+        };
+        assert_eq!(missing, "zip Nil (Cons False Nil)");
+        let fail = program.defs.iter().find(|def| def.name == "zip$fail").unwrap();
+        assert!(fail.match_fail);
+    }
+
+    #[test]
+    fn rejects_non_linear_patterns() {
+        let src = "
+            data List = Nil | Cons x xs;
+            same (Cons x (Cons x _)) = x;
+            same _ = Nil;
+        ";
+        let error = parse_program(src).unwrap().compile_matches().unwrap_err();
+        assert!(matches!(error, Error::NonLinearPattern { var, .. } if var == "x"));
+    }
+
+    #[test]
+    fn rejects_literal_patterns_without_eq() {
+        let fib = "fib 0 = 0; fib 1 = 1; fib n = fib (SUB n 1);";
+        for decls in ["#SUB x y;", "#SUB x y; EQ x y = x;", "#SUB x y; #EQ x;"] {
+            let src = format!("{} {}", decls, fib);
+            let error = parse_program(&src).unwrap().compile_matches().unwrap_err();
+            assert!(
+                matches!(error, Error::LiteralPatternWithoutEq { def_name, .. } if def_name == "fib")
+            );
+        }
+        let src = format!("#SUB x y; #EQ x y; {}", fib);
+        assert!(parse_program(&src).unwrap().compile_matches().is_ok());
+    }
+}
@@ -0,0 +1,529 @@
+// module resolution (runs before Program::into_anon)
+// links an entry module with the library modules it transitively imports into a single Program
+// top-level names of a library module `M` become `M.name`, while the entry module's names and
+// every primitive keep their plain names, so primops are attached and entries looked up as before
+// a reference is resolved against, in order: local binders, the module's own top-level names
+// (also reachable as `M.name`), then its imports (both `x` and `Q.x`, where two different
+// imported definitions under one name are ambiguous)
+// qualified names `Q.x` only see what the import with qualifier `Q` brings in, after its import
+// list and `hiding`: a `Q.x` that it leaves out, or whose `Q` is a linked module that this module
+// does not import under that name, is an error, so that a library's helpers stay private although
+// they are linked as `M.x`; other names that resolve to nothing are left for into_anon to report
+// module Main; import Prelude (map); f = map Prelude.id
+// -> f = Prelude.map Prelude.id
+
+use crate::error::*;
+use crate::structures::*;
+use std::collections::{HashMap, HashSet};
+
+pub const MAIN_MODULE: &str = "Main";
+
+fn qualify(module: &str, name: &str) -> Ident {
+    format!("{}.{}", module, name)
+}
+
+struct ModuleInfo<'m> {
+    module: &'m Module,
+    // plain top-level name -> linked name
+    own: HashMap<Ident, Ident>,
+    // data type name -> plain constructor names
+    types: HashMap<Ident, Vec<Ident>>,
+    exports: HashMap<Ident, Ident>,
+}
+
+impl<'m> ModuleInfo<'m> {
+    fn new(module: &'m Module, is_entry: bool) -> Result<Self> {
+        let program = &module.program;
+        let linked = |name: &str| {
+            if is_entry {
+                name.to_string()
+            } else {
+                qualify(&module.name, name)
+            }
+        };
+        let mut own = HashMap::new();
+        for def in &program.defs {
+            let name = if def.body.is_none() {
+                def.name.clone()
+            } else {
+                linked(&def.name)
+            };
+            own.insert(def.name.clone(), name);
+        }
+        for matches in &program.matches {
+            own.insert(matches.name.clone(), linked(&matches.name));
+        }
+        let mut types = HashMap::new();
+        for data in &program.datas {
+            for ctor in &data.ctors {
+                own.insert(ctor.name.clone(), linked(&ctor.name));
+            }
+            let ctors = data.ctors.iter().map(|ctor| ctor.name.clone()).collect();
+            types.insert(data.name.clone(), ctors);
+        }
+        let exports = match &module.exports {
+            None => own.clone(),
+            Some(names) => {
+                let mut exports = HashMap::new();
+                for (i, name) in names.iter().enumerate() {
+                    let ctors = types.get(name).map(Vec::as_slice).unwrap_or_default();
+                    for ctor in ctors {
+                        exports.insert(ctor.clone(), own[ctor].clone());
+                    }
+                    if let Some(linked) = own.get(name) {
+                        exports.insert(name.clone(), linked.clone());
+                    } else if !types.contains_key(name) {
+                        return Err(Error::UndefinedExport {
+                            module: module.name.clone(),
+                            name: name.clone(),
+                            span: module.export_spans.get(i).copied().or(module.span),
+                        });
+                    }
+                }
+                exports
+            }
+        };
+        Ok(Self {
+            module,
+            own,
+            types,
+            exports,
+        })
+    }
+
+    // expand an import or hiding list entry into exported names
+    fn exported(&self, name: &str, importer: &str, span: Option<Span>) -> Result<Vec<Ident>> {
+        let mut names = vec![];
+        if let Some(ctors) = self.types.get(name) {
+            names.extend(
+                ctors
+                    .iter()
+                    .filter(|ctor| self.exports.contains_key(*ctor))
+                    .cloned(),
+            );
+        }
+        if self.exports.contains_key(name) {
+            names.push(name.to_string());
+        }
+        if names.is_empty() {
+            return Err(Error::NotExported {
+                module: self.module.name.clone(),
+                importer: importer.to_string(),
+                name: name.to_string(),
+                span,
+            });
+        }
+        Ok(names)
+    }
+}
+
+struct Scope<'a> {
+    module: &'a str,
+    own: HashMap<Ident, Ident>,
+    imported: HashMap<Ident, Vec<Ident>>,
+    // import qualifier -> the modules it stands for and their exported names
+    qualifiers: HashMap<&'a str, Vec<(&'a str, HashSet<&'a str>)>>,
+    linked: HashSet<&'a str>,
+    ctors: &'a HashSet<Ident>,
+}
+
+impl Scope<'_> {
+    fn new<'a>(
+        info: &'a ModuleInfo,
+        infos: &'a HashMap<&str, ModuleInfo>,
+        ctors: &'a HashSet<Ident>,
+    ) -> Result<Scope<'a>> {
+        let module = info.module;
+        let mut own = HashMap::new();
+        for (name, linked) in &info.own {
+            own.insert(name.clone(), linked.clone());
+            own.insert(qualify(&module.name, name), linked.clone());
+        }
+        let exported = |source: &'a ModuleInfo| {
+            let names = source.exports.keys().map(|name| name.as_str()).collect();
+            (source.module.name.as_str(), names)
+        };
+        let mut qualifiers: HashMap<_, Vec<_>> = HashMap::new();
+        let mut imported: HashMap<Ident, Vec<Ident>> = HashMap::new();
+        for import in &module.imports {
+            let source = &infos[&*import.module];
+            let modules = qualifiers.entry(import.qualifier()).or_default();
+            modules.push(exported(source));
+            let mut names: Vec<Ident> = match &import.names {
+                Some(names) => {
+                    let mut listed = vec![];
+                    for name in names {
+                        listed.extend(source.exported(name, &module.name, import.span)?);
+                    }
+                    listed
+                }
+                None => source.exports.keys().cloned().collect(),
+            };
+            for name in &import.hiding {
+                let hidden = source.exported(name, &module.name, import.span)?;
+                names.retain(|name| !hidden.contains(name));
+            }
+            for name in names {
+                let linked = &source.exports[&name];
+                let mut keys = vec![qualify(import.qualifier(), &name)];
+                if !import.qualified {
+                    keys.push(name);
+                }
+                for key in keys {
+                    let candidates = imported.entry(key).or_default();
+                    if !candidates.contains(linked) {
+                        candidates.push(linked.clone());
+                    }
+                }
+            }
+        }
+        Ok(Scope {
+            module: &module.name,
+            own,
+            imported,
+            qualifiers,
+            linked: infos.keys().copied().collect(),
+            ctors,
+        })
+    }
+
+    fn unresolved(&self, name: &str, ctx: &Ctx) -> Result<Option<Ident>> {
+        let Some((qualifier, plain)) = name.split_once('.') else {
+            return Ok(None);
+        };
+        let not_imported = Error::NotImported {
+            importer: self.module.to_string(),
+            name: name.to_string(),
+            span: ctx.span,
+        };
+        let Some(modules) = self.qualifiers.get(qualifier) else {
+            if self.linked.contains(qualifier) {
+                return Err(not_imported);
+            }
+            return Ok(None);
+        };
+        // exported, but left out by the import list or `hiding`
+        if modules.iter().any(|(_, exports)| exports.contains(plain)) {
+            return Err(not_imported);
+        }
+        Err(Error::NotExported {
+            module: modules[0].0.to_string(),
+            importer: self.module.to_string(),
+            name: plain.to_string(),
+            span: ctx.span,
+        })
+    }
+
+    fn lookup(&self, name: &str, ctors_only: bool, ctx: &Ctx) -> Result<Option<Ident>> {
+        let allowed = |linked: &Ident| !ctors_only || self.ctors.contains(linked);
+        if let Some(linked) = self.own.get(name) {
+            return Ok(Some(linked.clone()).filter(allowed));
+        }
+        let Some(candidates) = self.imported.get(name) else {
+            return self.unresolved(name, ctx);
+        };
+        let candidates: Vec<Ident> = candidates.iter().filter(|c| allowed(c)).cloned().collect();
+        match candidates.len() {
+            0 => Ok(None),
+            1 => Ok(candidates.into_iter().next()),
+            _ => Err(Error::AmbiguousName {
+                def_name: ctx.def_name.to_string(),
+                name: name.to_string(),
+                candidates,
+                span: ctx.span,
+            }),
+        }
+    }
+}
+
+struct Ctx<'a> {
+    def_name: &'a str,
+    span: Option<Span>,
+}
+
+impl Scope<'_> {
+    fn resolve_pattern(
+        &self,
+        pattern: &mut Pattern,
+        bound: &mut Vec<Ident>,
+        ctx: &Ctx,
+    ) -> Result<()> {
+        match pattern {
+            Pattern::Var(ident) => {
+                if let Some(ctor) = self.lookup(ident, true, ctx)? {
+                    *ident = ctor;
+                } else {
+                    bound.push(ident.clone());
+                }
+            }
+            Pattern::Lit(_) => {}
+            Pattern::Ctor(ctor, args) => {
+                if let Some(linked) = self.lookup(ctor, true, ctx)? {
+                    *ctor = linked;
+                }
+                for arg in args {
+                    self.resolve_pattern(arg, bound, ctx)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_expr(&self, expr: &mut Expr, bound: &mut Vec<Ident>, ctx: &mut Ctx) -> Result<()> {
+        match expr {
+            Expr::Id(ident) => {
+                if !bound.contains(ident) {
+                    if let Some(linked) = self.lookup(ident, false, ctx)? {
+                        *ident = linked;
+                    }
+                }
+            }
+            Expr::Prim(_) => {}
+            Expr::App(e1, e2) => {
+                self.resolve_expr(e1, bound, ctx)?;
+                self.resolve_expr(e2, bound, ctx)?;
+            }
+            Expr::Lam(idents, e) => {
+                let prev_len = bound.len();
+                bound.extend(idents.iter().cloned());
+                self.resolve_expr(e, bound, ctx)?;
+                bound.truncate(prev_len);
+            }
+            Expr::Let(binds, e) => {
+                let prev_len = bound.len();
+                for (ident, value) in binds {
+                    self.resolve_expr(value, bound, ctx)?;
+                    bound.push(ident.clone());
+                }
+                self.resolve_expr(e, bound, ctx)?;
+                bound.truncate(prev_len);
+            }
+            Expr::LetRec(binds, e) => {
+                let prev_len = bound.len();
+                bound.extend(binds.iter().map(|(ident, _)| ident.clone()));
+                for (_, value) in binds {
+                    self.resolve_expr(value, bound, ctx)?;
+                }
+                self.resolve_expr(e, bound, ctx)?;
+                bound.truncate(prev_len);
+            }
+            Expr::Case(e, arms) => {
+                self.resolve_expr(e, bound, ctx)?;
+                for arm in arms {
+                    if let Some(linked) = self.lookup(&arm.ctor, true, ctx)? {
+                        arm.ctor = linked;
+                    }
+                    let prev_len = bound.len();
+                    bound.extend(arm.fields.iter().cloned());
+                    self.resolve_expr(&mut arm.body, bound, ctx)?;
+                    bound.truncate(prev_len);
+                }
+            }
+            Expr::Spanned(span, e) => {
+                let outer = ctx.span.replace(*span);
+                self.resolve_expr(e, bound, ctx)?;
+                ctx.span = outer;
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_program(&self, mut program: Program, qualifier: Option<&str>) -> Result<Program> {
+        for def in &mut program.defs {
+            let name = std::mem::take(&mut def.name);
+            let mut ctx = Ctx {
+                def_name: &name,
+                span: def.span.as_ref().map(|span| span.name),
+            };
+            if let Some(body) = &mut def.body {
+                // a parameter naming a constructor is a pattern, see match_compile
+                let mut bound = vec![];
+                for param in &mut def.params {
+                    if let Some(ctor) = self.lookup(param, true, &ctx)? {
+                        *param = ctor;
+                    } else {
+                        bound.push(param.clone());
+                    }
+                }
+                self.resolve_expr(body, &mut bound, &mut ctx)?;
+            }
+            def.name = self.own[&name].clone();
+        }
+        for matches in &mut program.matches {
+            for equation in &mut matches.equations {
+                let mut ctx = Ctx {
+                    def_name: &matches.name,
+                    span: equation.span,
+                };
+                let mut bound = vec![];
+                for pattern in &mut equation.patterns {
+                    self.resolve_pattern(pattern, &mut bound, &ctx)?;
+                }
+                self.resolve_expr(&mut equation.body, &mut bound, &mut ctx)?;
+            }
+            matches.name = self.own[&matches.name].clone();
+        }
+        for data in &mut program.datas {
+            if let Some(qualifier) = qualifier {
+                data.name = qualify(qualifier, &data.name);
+            }
+            for ctor in &mut data.ctors {
+                ctor.name = self.own[&ctor.name].clone();
+            }
+        }
+        Ok(program)
+    }
+}
+
+impl Module {
+    // link this module (the entry point) with the libraries it imports, directly or not
+    // libraries that are never imported are dropped
+    pub fn link(self, libraries: Vec<Module>) -> Result<Program> {
+        let mut available: HashMap<&str, &Module> = HashMap::new();
+        for module in std::iter::once(&self).chain(&libraries) {
+            if available.insert(&module.name, module).is_some() {
+                return Err(Error::DuplicateModule {
+                    name: module.name.clone(),
+                    span: module.span,
+                });
+            }
+        }
+        let mut order = vec![&self];
+        let mut infos = HashMap::new();
+        let mut i = 0;
+        while i < order.len() {
+            let module = order[i];
+            infos.insert(&*module.name, ModuleInfo::new(module, i == 0)?);
+            for import in &module.imports {
+                let Some(&imported) = available.get(&*import.module) else {
+                    return Err(Error::UnknownModule {
+                        importer: module.name.clone(),
+                        name: import.module.clone(),
+                        span: import.span,
+                    });
+                };
+                if !order.iter().any(|linked| linked.name == imported.name) {
+                    order.push(imported);
+                }
+            }
+            i += 1;
+        }
+        let mut ctors = HashSet::new();
+        for module in &order {
+            let own = &infos[&*module.name].own;
+            for data in &module.program.datas {
+                ctors.extend(data.ctors.iter().map(|ctor| own[&ctor.name].clone()));
+            }
+        }
+        let mut linked_programs = vec![];
+        for (i, module) in order.iter().enumerate() {
+            let scope = Scope::new(&infos[&*module.name], &infos, &ctors)?;
+            let qualifier = (i > 0).then_some(&*module.name);
+            linked_programs.push(scope.resolve_program(module.program.clone(), qualifier)?);
+        }
+        // the same primitive may be declared by several modules
+        let mut program = Program::default();
+        let mut primitives = HashSet::new();
+        for mut linked in linked_programs {
+            linked.defs.retain(|def| {
+                def.body.is_some() || primitives.insert((def.name.clone(), def.params.len()))
+            });
+            program.extend(linked);
+        }
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::tree_reducer::Node;
+    use crate::library::{compile_with_prelude, prelude_primops};
+    use crate::parser::parse_module;
+    use std::cell::Cell;
+
+    fn run(main: &str) -> i64 {
+        let sc = compile_with_prelude(main);
+        let table = sc.def_indexes();
+        let result = Cell::new(None);
+        let mut primops = prelude_primops(&table);
+        primops.insert(
+            "OUT",
+            Box::new(|a: &[i64]| {
+                result.set(Some(a[0]));
+                Some(Atom::Prim(a[0]))
+            }),
+        );
+        let mut node = Node::from_sc(table["main"]);
+        let mut sc = sc.attach_prim(&mut primops).unwrap();
+        sc.reduce_to_nf(&mut node).unwrap();
+        drop(sc);
+        result.get().unwrap()
+    }
+
+    #[test]
+    fn links_against_prelude() {
+        let main = "
+            import Prelude (List, range, map, sum);
+            import qualified Prelude as P;
+            #OUT x;
+            inc = P.ADD 1;
+            main = OUT (sum (map inc (range 1 10)));
+        ";
+        assert_eq!(run(main), 65);
+        let main = "
+            import Prelude hiding (length);
+            import qualified Prelude as P;
+            #OUT x;
+            length xs = 0;
+            count p xs = P.length (filter p xs);
+            main = OUT (ADD (length Nil) (count (LT 3) (range 1 10)));
+        ";
+        assert_eq!(run(main), 7);
+    }
+
+    #[test]
+    fn reports_resolution_errors() {
+        let a = parse_module("module A (x); x = 1; y = 2;").unwrap();
+        let b = parse_module("module B; x = 3;").unwrap();
+        let d = parse_module("module D; import B; z = x;").unwrap();
+        let link = |main: &str| {
+            let main = parse_module(main).unwrap();
+            main.link(vec![a.clone(), b.clone(), d.clone()])
+        };
+        let Err(Error::AmbiguousName { candidates, .. }) = link("import A; import B; f = x;")
+        else {
+            panic!("expected an ambiguity");
+        };
+        assert_eq!(candidates.len(), 2);
+        assert!(link("import A; import B; f = A.x; g = B.x; x = 4; h = x;").is_ok());
+        assert!(matches!(
+            link("import A (y);"),
+            Err(Error::NotExported { .. })
+        ));
+        assert!(matches!(
+            link("import C;"),
+            Err(Error::UnknownModule { .. })
+        ));
+        // a helper that `A` does not export stays private, even under its linked name `A.y`
+        assert!(matches!(
+            link("import A (x); f = A.y;"),
+            Err(Error::NotExported { name, .. }) if name == "y"
+        ));
+        // qualified names obey import lists and `hiding`, and need an import of their module
+        let not_imported = |main: &str| {
+            let error = link(main).unwrap_err();
+            matches!(error, Error::NotImported { name, .. } if name == "A.x" || name == "B.x")
+        };
+        assert!(not_imported("import A hiding (x); f = A.x;"));
+        assert!(not_imported("import A (); f = A.x;"));
+        assert!(not_imported("import qualified A as Q; f = A.x;"));
+        assert!(not_imported("import D; f = B.x;"));
+        assert!(link("import D; import qualified B; f = ADD z B.x; #ADD x y;").is_ok());
+        let src = "module A (x, z); x = 1;";
+        let Err(Error::UndefinedExport { span, .. }) = parse_module(src).unwrap().link(vec![]) else {
+            panic!("expected an undefined export");
+        };
+        assert_eq!(span, src.find('z').map(|start| Span::new(start, start + 1)));
+    }
+}
@@ -0,0 +1,429 @@
+// normalisation by evaluation
+// evaluates a term into values whose lambdas are closures, then reads the values back, going
+// under binders, so that the result is the full beta normal form in de Bruijn indices
+// arguments are evaluated lazily, so a discarded argument without a normal form is harmless;
+// a term without a normal form runs out of fuel (Error::NormalizeOutOfFuel)
+// definitions that are not (mutually) recursive are unfolded, so `f = λx. id x` becomes `λx. x`;
+// recursive ones and primops stay as they are, so that they cannot make normalisation diverge
+// eta: also reduce `λ. f v0` to `f` when `f` does not use `v0`
+
+use crate::error::{Error, Result};
+use crate::structures::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub struct Normalize {
+    pub eta: bool,
+    // definitions that are not recursive are unfolded
+    pub unfold: bool,
+    // beta reductions and unfoldings allowed per term
+    pub fuel: u64,
+}
+
+impl Default for Normalize {
+    fn default() -> Self {
+        Self {
+            eta: false,
+            unfold: true,
+            fuel: 1_000_000,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Value<'e> {
+    Lam(Env<'e>, &'e AnonExpr),
+    Neutral(Head, Vec<Thunk<'e>>),
+}
+
+#[derive(Clone, Copy)]
+enum Head {
+    Def(usize),
+    Prim(i64),
+    // a variable bound during readback, by its depth from the outside
+    Level(usize),
+}
+
+type Thunk<'e> = Rc<RefCell<Lazy<'e>>>;
+
+enum Lazy<'e> {
+    Delayed(&'e AnonExpr, Env<'e>),
+    Forced(Value<'e>),
+}
+
+// the values of the enclosing binders, the innermost first
+#[derive(Clone, Default)]
+struct Env<'e>(Option<Rc<(Thunk<'e>, Env<'e>)>>);
+
+impl<'e> Env<'e> {
+    fn push(&self, thunk: Thunk<'e>) -> Self {
+        Env(Some(Rc::new((thunk, self.clone()))))
+    }
+
+    fn get(&self, index: usize) -> Thunk<'e> {
+        let mut env = self;
+        for _ in 0..index {
+            env = &env.0.as_ref().expect("unbound de Bruijn index").1;
+        }
+        env.0.as_ref().expect("unbound de Bruijn index").0.clone()
+    }
+}
+
+// the closed term of each definition to unfold
+fn unfoldings(root: &AnonProgram, options: &Normalize) -> Vec<Option<AnonExpr>> {
+    let refs = root.defs.iter().map(|def| {
+        let mut refs = vec![];
+        if let Some(body) = &def.body {
+            body.def_refs(&mut refs);
+        }
+        refs
+    });
+    let refs = refs.collect::<Vec<_>>();
+    let recursive = |start: usize| {
+        let mut seen = vec![false; refs.len()];
+        let mut stack = refs[start].clone();
+        while let Some(i) = stack.pop() {
+            if i == start {
+                return true;
+            }
+            if !std::mem::replace(&mut seen[i], true) {
+                stack.extend(&refs[i]);
+            }
+        }
+        false
+    };
+    root.defs
+        .iter()
+        .enumerate()
+        .map(|(i, def)| {
+            if options.unfold && !recursive(i) {
+                def.closed_term()
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+struct Nbe<'e> {
+    unfoldings: &'e [Option<AnonExpr>],
+    options: &'e Normalize,
+    steps: u64,
+}
+
+impl<'e> Nbe<'e> {
+    fn step(&mut self) -> Result<()> {
+        if self.steps == self.options.fuel {
+            return Err(Error::NormalizeOutOfFuel {
+                def_name: None,
+                fuel: self.options.fuel,
+            });
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    // applications, unfoldings and beta reductions in tail position loop instead of recursing,
+    // so that only forcing an argument takes native stack
+    fn eval(&mut self, mut expr: &'e AnonExpr, env: &Env<'e>) -> Result<Value<'e>> {
+        let mut env = env.clone();
+        // the pending arguments, the next one last
+        let mut args: Vec<Thunk<'e>> = vec![];
+        loop {
+            let value = match expr {
+                AnonExpr::App(e1, e2) => {
+                    args.push(Rc::new(RefCell::new(Lazy::Delayed(e2, env.clone()))));
+                    expr = e1;
+                    continue;
+                }
+                AnonExpr::DefId(i) => match &self.unfoldings.get(*i) {
+                    Some(Some(term)) => {
+                        self.step()?;
+                        expr = term;
+                        env = Env::default();
+                        continue;
+                    }
+                    _ => Value::Neutral(Head::Def(*i), vec![]),
+                },
+                AnonExpr::Prim(i) => Value::Neutral(Head::Prim(*i), vec![]),
+                AnonExpr::DeBruijn(i) => self.force(&env.get(*i))?,
+                AnonExpr::ArgId(_) => panic!("parameters are bound as lambdas before evaluation"),
+                AnonExpr::Lam(body) => Value::Lam(env.clone(), body),
+            };
+            match value {
+                Value::Lam(lam_env, body) => {
+                    let Some(arg) = args.pop() else {
+                        return Ok(Value::Lam(lam_env, body));
+                    };
+                    self.step()?;
+                    expr = body;
+                    env = lam_env.push(arg);
+                }
+                Value::Neutral(head, mut head_args) => {
+                    head_args.extend(args.into_iter().rev());
+                    return Ok(Value::Neutral(head, head_args));
+                }
+            }
+        }
+    }
+
+    fn force(&mut self, thunk: &Thunk<'e>) -> Result<Value<'e>> {
+        let lazy = &mut *thunk.borrow_mut();
+        if let Lazy::Delayed(expr, env) = lazy {
+            let value = self.eval(expr, env)?;
+            *lazy = Lazy::Forced(value);
+        }
+        match lazy {
+            Lazy::Forced(value) => Ok(value.clone()),
+            Lazy::Delayed(..) => unreachable!(),
+        }
+    }
+
+    // `depth` binders enclose the value
+    fn read_back(&mut self, value: Value<'e>, depth: usize) -> Result<AnonExpr> {
+        match value {
+            Value::Lam(env, body) => {
+                let var = Value::Neutral(Head::Level(depth), vec![]);
+                let var = Rc::new(RefCell::new(Lazy::Forced(var)));
+                let body = self.eval(body, &env.push(var))?;
+                let body = self.read_back(body, depth + 1)?;
+                let eta = self.options.eta;
+                Ok(match body {
+                    AnonExpr::App(f, x) if eta && *x == AnonExpr::DeBruijn(0) && !f.uses(0) => {
+                        f.shift_down(0)
+                    }
+                    body => AnonExpr::Lam(Box::new(body)),
+                })
+            }
+            Value::Neutral(head, args) => {
+                let mut expr = match head {
+                    Head::Def(i) => AnonExpr::DefId(i),
+                    Head::Prim(i) => AnonExpr::Prim(i),
+                    Head::Level(level) => AnonExpr::DeBruijn(depth - 1 - level),
+                };
+                for arg in &args {
+                    let arg = self.force(arg)?;
+                    let arg = self.read_back(arg, depth)?;
+                    expr = AnonExpr::App(Box::new(expr), Box::new(arg));
+                }
+                Ok(expr)
+            }
+        }
+    }
+}
+
+impl AnonExpr {
+    // the normal form of a closed term, that is, one without `ArgId`s or unbound indices, whose
+    // `DefId`s refer to the definitions of `root`
+    pub fn normalize(&self, root: &AnonProgram, options: &Normalize) -> Result<AnonExpr> {
+        self.normalize_with(&unfoldings(root, options), options)
+    }
+
+    fn normalize_with(
+        &self,
+        unfoldings: &[Option<AnonExpr>],
+        options: &Normalize,
+    ) -> Result<AnonExpr> {
+        let mut nbe = Nbe {
+            unfoldings,
+            options,
+            steps: 0,
+        };
+        let value = nbe.eval(self, &Env::default())?;
+        nbe.read_back(value, 0)
+    }
+
+    // the definitions referred to, possibly repeated
+    fn def_refs(&self, out: &mut Vec<usize>) {
+        match self {
+            AnonExpr::DefId(i) => out.push(*i),
+            AnonExpr::App(e1, e2) => {
+                e1.def_refs(out);
+                e2.def_refs(out);
+            }
+            AnonExpr::Lam(e) => e.def_refs(out),
+            AnonExpr::DeBruijn(_) | AnonExpr::ArgId(_) | AnonExpr::Prim(_) => {}
+        }
+    }
+
+    // whether de Bruijn index `index`, counted from outside this term, occurs in it
+    fn uses(&self, index: usize) -> bool {
+        match self {
+            AnonExpr::DeBruijn(i) => *i == index,
+            AnonExpr::App(e1, e2) => e1.uses(index) || e2.uses(index),
+            AnonExpr::Lam(e) => e.uses(index + 1),
+            AnonExpr::DefId(_) | AnonExpr::ArgId(_) | AnonExpr::Prim(_) => false,
+        }
+    }
+
+    // remove the unused binder `cutoff`, renumbering the indices beyond it
+    fn shift_down(self, cutoff: usize) -> AnonExpr {
+        match self {
+            AnonExpr::DeBruijn(i) if i > cutoff => AnonExpr::DeBruijn(i - 1),
+            AnonExpr::App(e1, e2) => AnonExpr::App(
+                Box::new(e1.shift_down(cutoff)),
+                Box::new(e2.shift_down(cutoff)),
+            ),
+            AnonExpr::Lam(e) => AnonExpr::Lam(Box::new(e.shift_down(cutoff + 1))),
+            expr => expr,
+        }
+    }
+
+    // the parameters `ArgId`s become the indices of `params` lambdas around the term
+    fn bind_params(&self, params: usize, depth: usize) -> AnonExpr {
+        match self {
+            AnonExpr::ArgId(i) => AnonExpr::DeBruijn(depth + params - 1 - i),
+            AnonExpr::App(e1, e2) => AnonExpr::App(
+                Box::new(e1.bind_params(params, depth)),
+                Box::new(e2.bind_params(params, depth)),
+            ),
+            AnonExpr::Lam(e) => AnonExpr::Lam(Box::new(e.bind_params(params, depth + 1))),
+            expr => expr.clone(),
+        }
+    }
+
+    // equal up to the names the two programs give their definitions
+    fn eq_in(&self, root: &AnonProgram, other: &AnonExpr, other_root: &AnonProgram) -> bool {
+        match (self, other) {
+            (AnonExpr::DefId(i), AnonExpr::DefId(j)) => {
+                root.defs[*i].name == other_root.defs[*j].name
+            }
+            (AnonExpr::App(e1, e2), AnonExpr::App(f1, f2)) => {
+                e1.eq_in(root, f1, other_root) && e2.eq_in(root, f2, other_root)
+            }
+            (AnonExpr::Lam(e), AnonExpr::Lam(f)) => e.eq_in(root, f, other_root),
+            (e, f) => e == f,
+        }
+    }
+}
+
+impl AnonDef {
+    // the body with one lambda per parameter; `None` for primops
+    fn closed_term(&self) -> Option<AnonExpr> {
+        let mut term = self.body.as_ref()?.bind_params(self.params, 0);
+        for _ in 0..self.params {
+            term = AnonExpr::Lam(Box::new(term));
+        }
+        Some(term)
+    }
+
+    // the body as a closed term in normal form, with one lambda per parameter; `params` becomes
+    // 0 except for primops, which have no body
+    pub fn normalize(&self, root: &AnonProgram, options: &Normalize) -> Result<AnonDef> {
+        self.normalize_with(&unfoldings(root, options), options)
+    }
+
+    fn normalize_with(
+        &self,
+        unfoldings: &[Option<AnonExpr>],
+        options: &Normalize,
+    ) -> Result<AnonDef> {
+        let Some(term) = self.closed_term() else {
+            return Ok(AnonDef {
+                name: self.name.clone(),
+                params: self.params,
+                body: None,
+                span: self.span,
+            });
+        };
+        let body = term.normalize_with(unfoldings, options).map_err(|error| match error {
+            Error::NormalizeOutOfFuel { fuel, .. } => Error::NormalizeOutOfFuel {
+                def_name: Some(self.name.clone()),
+                fuel,
+            },
+            error => error,
+        })?;
+        Ok(AnonDef {
+            name: self.name.clone(),
+            params: 0,
+            body: Some(body),
+            span: self.span,
+        })
+    }
+}
+
+impl AnonProgram {
+    pub fn normalize(&self, options: &Normalize) -> Result<AnonProgram> {
+        let unfoldings = unfoldings(self, options);
+        let defs = self.defs.iter().map(|def| def.normalize_with(&unfoldings, options));
+        Ok(AnonProgram {
+            defs: defs.collect::<Result<_>>()?,
+        })
+    }
+
+    // whether both programs define the same names, with equal normal forms; definitions are
+    // matched by name, not by position
+    pub fn alpha_beta_eq(&self, other: &AnonProgram, options: &Normalize) -> Result<bool> {
+        let (this, other) = (self.normalize(options)?, other.normalize(options)?);
+        Ok(this.defs.len() == other.defs.len()
+            && this.defs.iter().all(|def| {
+                let Some(other_def) = other.defs.iter().find(|d| d.name == def.name) else {
+                    return false;
+                };
+                def.params == other_def.params
+                    && match (&def.body, &other_def.body) {
+                        (Some(e), Some(f)) => e.eq_in(&this, f, &other),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Normalize;
+    use crate::error::Error;
+    use crate::parser::parse_program;
+    use crate::structures::*;
+
+    fn anon(src: &str) -> AnonProgram {
+        parse_program(src).unwrap().into_anon().unwrap()
+    }
+
+    #[test]
+    fn normalizes_under_binders() {
+        let options = Normalize {
+            unfold: false,
+            ..Normalize::default()
+        };
+        let program = anon(
+            "
+            id x = x;
+            f = λx. (λy. y) x;
+            lazy = (λx. id) ((λx. x x) (λx. x x));
+            ",
+        )
+        .normalize(&options)
+        .unwrap();
+        let bodies: Vec<_> = program.defs.iter().map(|def| def.body.clone()).collect();
+        let var = |i| Box::new(AnonExpr::DeBruijn(i));
+        assert_eq!(bodies[1], Some(AnonExpr::Lam(var(0))));
+        assert_eq!(bodies[2], Some(AnonExpr::DefId(0)));
+
+        let left = anon("id x = x; k x y = x; g h = λx. h x;");
+        let right = anon("k = λa b. (λc. a) b; g h = h; id y = (λz. z) y;");
+        assert!(!left.alpha_beta_eq(&right, &options).unwrap());
+        let eta = Normalize { eta: true, ..options };
+        assert!(left.alpha_beta_eq(&right, &eta).unwrap());
+    }
+
+    #[test]
+    fn unfolds_definitions_that_are_not_recursive() {
+        let options = Normalize::default();
+        let program = anon("id x = x; f = λx. id x;");
+        assert!(program.alpha_beta_eq(&anon("id x = x; f = id;"), &options).unwrap());
+        assert!(program.alpha_beta_eq(&anon("id x = x; f = λx. x;"), &options).unwrap());
+        // recursive definitions stay folded, so normalising them terminates
+        let program = anon("#g x; loop x = g (loop x); h = loop 1;");
+        let expected = "g x0 = <builtin>\nloop = λv0. g (loop v0)\nh = loop i1";
+        assert_eq!(program.normalize(&options).unwrap().to_string(), expected);
+        let error = anon("omega = (λx. x x) (λx. x x);").normalize(&options).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::NormalizeOutOfFuel { def_name: Some(name), fuel: 1_000_000 }
+                if name == Name::Named("omega".to_string())
+        ));
+    }
+}
@@ -5,7 +5,7 @@ use crate::error::*;
 
 impl ScDef {
     fn attach_prim<'a>(self, primops: &mut HashMap<&'static str, Primop<'a>>) -> Result<ScPrimDef<'a>> {
-        let Self { name, params, body } = self;
+        let Self { name, params, body, span } = self;
         let body = if let Some(body) = body {
             ScBody::Body(body)
         } else {
@@ -14,11 +14,13 @@ impl ScDef {
                     if let Some(primop) = primops.remove(&**name) {
                         ScBody::Prim(primop)
                     } else {
-                        return Err(Error::UnknownPrimop { def_name: name.to_string() });
+                        return Err(Error::UnknownPrimop { def_name: name.to_string(), span });
                     }
                 }
+                // reached when no equation matches; always fails
+                Name::MatchFail(_) => ScBody::Prim(Box::new(|_: &[i64]| None)),
                 Name::Unnamed(id) => {
-                    return Err(Error::UnnamedPrimop { def_no: id });
+                    return Err(Error::UnnamedPrimop { def_no: id, span });
                 }
             }
         };
@@ -44,4 +46,16 @@ impl ScProgram {
         }
         hash
     }
-}
\ No newline at end of file
+}
+
+impl ScPrimProgram<'_> {
+    pub fn def_indexes(&self) -> HashMap<&str, usize> {
+        let mut hash = HashMap::new();
+        for (i, def) in self.defs.iter().enumerate() {
+            if let Name::Named(ref name) = def.name {
+                hash.insert(&**name, i);
+            }
+        }
+        hash
+    }
+}
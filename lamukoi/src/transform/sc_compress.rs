@@ -37,9 +37,9 @@ impl ScProgram {
                 }
             }
             let mut hash = HashMap::new();
-            for i in 0..len {
-                if let Some(body) = &defs[i].body {
-                    hash.entry((defs[i].params, body)).or_insert(vec![]).push(i);
+            for (i, def) in defs.iter().enumerate() {
+                if let Some(body) = &def.body {
+                    hash.entry((def.params, body)).or_insert(vec![]).push(i);
                 }
             }
             let mut keep = vec![true; len];
@@ -83,12 +83,10 @@ impl ScProgram {
                     next_defs.push(def);
                 }
             }
-            let mut next_unnamed_id = 0usize;
-            for def in &mut next_defs {
+            for (next_unnamed_id, def) in next_defs.iter_mut().enumerate() {
                 if let Name::Unnamed(i) = &mut def.name {
                     *i = next_unnamed_id;
                 }
-                next_unnamed_id += 1;
             }
             defs = next_defs;
             if len == defs.len() {
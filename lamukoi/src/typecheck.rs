@@ -0,0 +1,670 @@
+// Hindley-Milner type inference for Program (optional: nothing else in the pipeline needs it)
+// runs on the program after match compilation; data types stay structural: a constructor has the
+// type of its Scott encoding, with every continuation returning the same type,
+// Cons : a -> b -> r -> (a -> b -> r) -> r
+// and `case` requires the scrutinee to accept one continuation per constructor of the data type
+// top-level definitions are generalized per strongly connected component of the reference graph,
+// `let` and `letrec` bindings per binding group
+// with recursive types enabled, unification builds cyclic (equi-recursive) types where the occurs
+// check would fail, which is what any function consuming Scott-encoded recursive data needs:
+// length : (μa. Int -> (b -> a -> Int) -> Int) -> Int
+// primitives take the type in their signature (`#ADD x y : Int -> Int -> Int;`), except the
+// failure primitive generated for non-exhaustive matches, which has any type
+
+use crate::diagnostic::suggest;
+use crate::error::*;
+use crate::structures::*;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone)]
+pub struct TypeCheck {
+    pub recursive_types: bool,
+}
+
+impl Default for TypeCheck {
+    fn default() -> Self {
+        Self {
+            recursive_types: true,
+        }
+    }
+}
+
+// variables at this level are quantified
+const GENERIC: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
+enum Term {
+    Var(usize),
+    Con(Ident),
+    Fun(usize, usize),
+    Link(usize),
+}
+
+enum Clash {
+    Mismatch(usize, usize),
+    Infinite(usize, usize),
+}
+
+struct Infer<'a> {
+    terms: Vec<Term>,
+    level: usize,
+    options: &'a TypeCheck,
+    datas: &'a [DataDef],
+    // constructor name -> index of its data type
+    ctors: HashMap<Ident, usize>,
+    globals: HashMap<Ident, usize>,
+    // innermost last; `true` if the binding is polymorphic
+    locals: Vec<(Ident, usize, bool)>,
+    def_name: Ident,
+    span: Option<Span>,
+}
+
+impl Infer<'_> {
+    fn alloc(&mut self, term: Term) -> usize {
+        self.terms.push(term);
+        self.terms.len() - 1
+    }
+
+    fn fresh(&mut self) -> usize {
+        self.alloc(Term::Var(self.level))
+    }
+
+    fn fun(&mut self, t1: usize, t2: usize) -> usize {
+        self.alloc(Term::Fun(t1, t2))
+    }
+
+    fn arrows(&mut self, args: &[usize], mut result: usize) -> usize {
+        for &arg in args.iter().rev() {
+            result = self.fun(arg, result);
+        }
+        result
+    }
+
+    fn ctor_type(&mut self, data: &DataDef, k: usize) -> usize {
+        let result = self.alloc(Term::Var(GENERIC));
+        let mut args = vec![];
+        let mut conts = vec![];
+        for (j, ctor) in data.ctors.iter().enumerate() {
+            let fields: Vec<usize> = ctor
+                .fields
+                .iter()
+                .map(|_| self.alloc(Term::Var(GENERIC)))
+                .collect();
+            conts.push(self.arrows(&fields, result));
+            if j == k {
+                args = fields;
+            }
+        }
+        let t = self.arrows(&conts, result);
+        self.arrows(&args, t)
+    }
+
+    fn int(&mut self) -> usize {
+        self.alloc(Term::Con("Int".to_string()))
+    }
+
+    fn find(&mut self, mut t: usize) -> usize {
+        let mut path = vec![];
+        while let Term::Link(next) = self.terms[t] {
+            path.push(t);
+            t = next;
+        }
+        for node in path {
+            self.terms[node] = Term::Link(t);
+        }
+        t
+    }
+
+    // every node reachable from `t`, each once
+    fn reachable(&mut self, t: usize) -> Vec<usize> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![t];
+        let mut out = vec![];
+        while let Some(t) = stack.pop() {
+            let t = self.find(t);
+            if !seen.insert(t) {
+                continue;
+            }
+            out.push(t);
+            if let Term::Fun(t1, t2) = self.terms[t] {
+                stack.push(t2);
+                stack.push(t1);
+            }
+        }
+        out
+    }
+
+    fn bind(&mut self, var: usize, t: usize) -> std::result::Result<(), Clash> {
+        let Term::Var(level) = self.terms[var] else {
+            unreachable!()
+        };
+        for node in self.reachable(t) {
+            if node == var && !self.options.recursive_types {
+                return Err(Clash::Infinite(var, t));
+            }
+            if let Term::Var(other) = &mut self.terms[node] {
+                *other = (*other).min(level);
+            }
+        }
+        self.terms[var] = Term::Link(t);
+        Ok(())
+    }
+
+    fn unify(&mut self, t1: usize, t2: usize) -> std::result::Result<(), Clash> {
+        let mut stack = vec![(t1, t2)];
+        while let Some((t1, t2)) = stack.pop() {
+            let (t1, t2) = (self.find(t1), self.find(t2));
+            if t1 == t2 {
+                continue;
+            }
+            match (self.terms[t1].clone(), self.terms[t2].clone()) {
+                (Term::Var(_), _) => self.bind(t1, t2)?,
+                (_, Term::Var(_)) => self.bind(t2, t1)?,
+                (Term::Con(c1), Term::Con(c2)) if c1 == c2 => self.terms[t1] = Term::Link(t2),
+                (Term::Fun(a1, r1), Term::Fun(a2, r2)) => {
+                    // linking first makes cyclic types meet again at `t1 == t2` above
+                    self.terms[t1] = Term::Link(t2);
+                    stack.push((r1, r2));
+                    stack.push((a1, a2));
+                }
+                _ => return Err(Clash::Mismatch(t1, t2)),
+            }
+        }
+        Ok(())
+    }
+
+    fn generalize(&mut self, t: usize) {
+        for node in self.reachable(t) {
+            if let Term::Var(level) = &mut self.terms[node] {
+                if *level > self.level {
+                    *level = GENERIC;
+                }
+            }
+        }
+    }
+
+    fn instantiate(&mut self, t: usize) -> usize {
+        let mut copies = HashMap::new();
+        self.copy(t, &mut copies)
+    }
+
+    fn copy(&mut self, t: usize, copies: &mut HashMap<usize, usize>) -> usize {
+        let t = self.find(t);
+        if let Some(&copy) = copies.get(&t) {
+            return copy;
+        }
+        match self.terms[t] {
+            Term::Var(GENERIC) => {
+                let var = self.fresh();
+                copies.insert(t, var);
+                var
+            }
+            Term::Fun(t1, t2) => {
+                let copy = self.alloc(Term::Link(t));
+                copies.insert(t, copy);
+                let t1 = self.copy(t1, copies);
+                let t2 = self.copy(t2, copies);
+                self.terms[copy] = Term::Fun(t1, t2);
+                copy
+            }
+            _ => t,
+        }
+    }
+
+    fn unify_here(&mut self, t1: usize, t2: usize) -> Result<()> {
+        self.unify(t1, t2).map_err(|clash| {
+            let mut names = Names::default();
+            match clash {
+                Clash::Mismatch(t1, t2) => Error::TypeMismatch {
+                    def_name: self.def_name.clone(),
+                    left: self.export(t1, &mut names),
+                    right: self.export(t2, &mut names),
+                    span: self.span,
+                },
+                Clash::Infinite(var, t) => Error::InfiniteType {
+                    def_name: self.def_name.clone(),
+                    var: self.export(var, &mut names),
+                    ty: self.export(t, &mut names),
+                    span: self.span,
+                },
+            }
+        })
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Result<usize> {
+        match expr {
+            Expr::Id(ident) => {
+                let local = self.locals.iter().rev().find(|(name, ..)| name == ident);
+                if let Some(&(_, t, poly)) = local {
+                    return Ok(if poly { self.instantiate(t) } else { t });
+                }
+                match self.globals.get(ident) {
+                    Some(&t) => Ok(self.instantiate(t)),
+                    None => {
+                        let locals = self.locals.iter().map(|(name, ..)| name.as_str());
+                        let names = self.globals.keys().map(|name| name.as_str());
+                        Err(Error::UndefinedIdent {
+                            def_name: self.def_name.clone(),
+                            undefined_name: ident.clone(),
+                            span: self.span,
+                            suggestion: suggest(ident, names.chain(locals)),
+                        })
+                    }
+                }
+            }
+            Expr::Prim(_) => Ok(self.int()),
+            Expr::App(e1, e2) => {
+                let t1 = self.infer(e1)?;
+                let t2 = self.infer(e2)?;
+                let result = self.fresh();
+                let expected = self.fun(t2, result);
+                self.unify_here(t1, expected)?;
+                Ok(result)
+            }
+            Expr::Lam(params, body) => {
+                let prev_len = self.locals.len();
+                let vars: Vec<usize> = params.iter().map(|_| self.fresh()).collect();
+                for (param, &var) in params.iter().zip(&vars) {
+                    self.locals.push((param.clone(), var, false));
+                }
+                let t = self.infer(body)?;
+                self.locals.truncate(prev_len);
+                Ok(self.arrows(&vars, t))
+            }
+            Expr::Let(binds, body) => {
+                let prev_len = self.locals.len();
+                for (name, value) in binds {
+                    self.level += 1;
+                    let t = self.infer(value)?;
+                    self.level -= 1;
+                    self.generalize(t);
+                    self.locals.push((name.clone(), t, true));
+                }
+                let t = self.infer(body)?;
+                self.locals.truncate(prev_len);
+                Ok(t)
+            }
+            Expr::LetRec(binds, body) => {
+                let prev_len = self.locals.len();
+                self.level += 1;
+                for (name, _) in binds {
+                    let var = self.fresh();
+                    self.locals.push((name.clone(), var, false));
+                }
+                for (i, (_, value)) in binds.iter().enumerate() {
+                    let t = self.infer(value)?;
+                    self.unify_here(self.locals[prev_len + i].1, t)?;
+                }
+                self.level -= 1;
+                for i in prev_len..self.locals.len() {
+                    self.generalize(self.locals[i].1);
+                    self.locals[i].2 = true;
+                }
+                let t = self.infer(body)?;
+                self.locals.truncate(prev_len);
+                Ok(t)
+            }
+            Expr::Case(scrutinee, arms) => {
+                let scrutinee = self.infer(scrutinee)?;
+                let Some(&data) = arms.iter().find_map(|arm| self.ctors.get(&arm.ctor)) else {
+                    // a lone `_` arm
+                    return self.infer(&arms[0].body);
+                };
+                let result = self.fresh();
+                let mut default = None;
+                let mut conts = vec![];
+                for ctor in &self.datas[data].ctors {
+                    let Some(arm) = arms.iter().find(|arm| arm.ctor == ctor.name) else {
+                        let body = match default {
+                            Some(body) => body,
+                            None => {
+                                let arm = arms.iter().find(|arm| arm.ctor == "_").unwrap();
+                                *default.insert(self.infer(&arm.body)?)
+                            }
+                        };
+                        self.unify_here(result, body)?;
+                        let fields: Vec<usize> = ctor.fields.iter().map(|_| self.fresh()).collect();
+                        conts.push(self.arrows(&fields, result));
+                        continue;
+                    };
+                    let prev_len = self.locals.len();
+                    let fields: Vec<usize> = arm.fields.iter().map(|_| self.fresh()).collect();
+                    for (field, &var) in arm.fields.iter().zip(&fields) {
+                        self.locals.push((field.clone(), var, false));
+                    }
+                    let body = self.infer(&arm.body)?;
+                    self.locals.truncate(prev_len);
+                    self.unify_here(result, body)?;
+                    conts.push(self.arrows(&fields, result));
+                }
+                let expected = self.arrows(&conts, result);
+                self.unify_here(scrutinee, expected)?;
+                Ok(result)
+            }
+            Expr::Spanned(span, e) => {
+                let outer = self.span.replace(*span);
+                let t = self.infer(e)?;
+                self.span = outer;
+                Ok(t)
+            }
+        }
+    }
+
+    fn import(&mut self, ty: &Type, vars: &mut HashMap<Ident, usize>) -> usize {
+        match ty {
+            Type::Con(name) => self.alloc(Term::Con(name.clone())),
+            Type::Var(name) => match vars.get(name) {
+                Some(&var) => var,
+                None => {
+                    let var = self.alloc(Term::Var(GENERIC));
+                    vars.insert(name.clone(), var);
+                    var
+                }
+            },
+            Type::Fun(t1, t2) => {
+                let t1 = self.import(t1, vars);
+                let t2 = self.import(t2, vars);
+                self.fun(t1, t2)
+            }
+            Type::Rec(name, t) => {
+                let var = self.alloc(Term::Var(GENERIC));
+                let outer = vars.insert(name.clone(), var);
+                let t = self.import(t, vars);
+                match outer {
+                    Some(outer) => vars.insert(name.clone(), outer),
+                    None => vars.remove(name),
+                };
+                self.terms[var] = Term::Link(t);
+                t
+            }
+        }
+    }
+
+    // a node met again on the current path becomes the variable of a `μ`
+    fn export(&mut self, t: usize, names: &mut Names) -> Type {
+        let t = self.find(t);
+        match self.terms[t].clone() {
+            Term::Var(_) => Type::Var(names.var(t)),
+            Term::Con(name) => Type::Con(name),
+            Term::Fun(t1, t2) => {
+                if names.path.contains(&t) {
+                    return Type::Var(names.rec(t));
+                }
+                names.path.push(t);
+                let t1 = self.export(t1, names);
+                let t2 = self.export(t2, names);
+                names.path.pop();
+                let ty = Type::Fun(Box::new(t1), Box::new(t2));
+                match names.recs.remove(&t) {
+                    Some(name) => Type::Rec(name, Box::new(ty)),
+                    None => ty,
+                }
+            }
+            Term::Link(_) => unreachable!(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Names {
+    vars: HashMap<usize, Ident>,
+    recs: HashMap<usize, Ident>,
+    path: Vec<usize>,
+    count: usize,
+}
+
+impl Names {
+    fn next(&mut self) -> Ident {
+        let letter = (b'a' + (self.count % 26) as u8) as char;
+        let name = match self.count / 26 {
+            0 => letter.to_string(),
+            n => format!("{}{}", letter, n),
+        };
+        self.count += 1;
+        name
+    }
+
+    fn var(&mut self, t: usize) -> Ident {
+        if let Some(name) = self.vars.get(&t) {
+            return name.clone();
+        }
+        let name = self.next();
+        self.vars.insert(t, name.clone());
+        name
+    }
+
+    fn rec(&mut self, t: usize) -> Ident {
+        if let Some(name) = self.recs.get(&t) {
+            return name.clone();
+        }
+        let name = self.next();
+        self.recs.insert(t, name.clone());
+        name
+    }
+}
+
+// strongly connected components of the definitions with bodies, dependencies first (Tarjan)
+fn components(defs: &[&Def]) -> Vec<Vec<usize>> {
+    let index: HashMap<&str, usize> = defs
+        .iter()
+        .enumerate()
+        .map(|(i, def)| (&*def.name, i))
+        .collect();
+    let edges: Vec<Vec<usize>> = defs
+        .iter()
+        .map(|def| {
+            let mut free = vec![];
+            let mut bound = def.params.clone();
+            def.body.as_ref().unwrap().free_vars(&mut bound, &mut free);
+            free.iter()
+                .filter_map(|name| index.get(&**name).copied())
+                .collect()
+        })
+        .collect();
+    struct Tarjan<'e> {
+        edges: &'e [Vec<usize>],
+        order: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        next: usize,
+        out: Vec<Vec<usize>>,
+    }
+    impl Tarjan<'_> {
+        fn visit(&mut self, v: usize) {
+            self.order[v] = Some(self.next);
+            self.low[v] = self.next;
+            self.next += 1;
+            self.stack.push(v);
+            self.on_stack[v] = true;
+            for &w in &self.edges[v] {
+                match self.order[w] {
+                    None => {
+                        self.visit(w);
+                        self.low[v] = self.low[v].min(self.low[w]);
+                    }
+                    Some(order) if self.on_stack[w] => self.low[v] = self.low[v].min(order),
+                    _ => {}
+                }
+            }
+            if Some(self.low[v]) == self.order[v] {
+                let mut component = vec![];
+                loop {
+                    let w = self.stack.pop().unwrap();
+                    self.on_stack[w] = false;
+                    component.push(w);
+                    if w == v {
+                        break;
+                    }
+                }
+                component.sort();
+                self.out.push(component);
+            }
+        }
+    }
+    let mut tarjan = Tarjan {
+        edges: &edges,
+        order: vec![None; defs.len()],
+        low: vec![0; defs.len()],
+        stack: vec![],
+        on_stack: vec![false; defs.len()],
+        next: 0,
+        out: vec![],
+    };
+    for v in 0..defs.len() {
+        if tarjan.order[v].is_none() {
+            tarjan.visit(v);
+        }
+    }
+    tarjan.out
+}
+
+impl Program {
+    // the type of every top-level definition after lowering, constructors included, in order
+    pub fn infer_types(&self, options: &TypeCheck) -> Result<Vec<(Ident, Type)>> {
+        let (program, _) = self.clone().compile_matches()?;
+        // reports unknown constructors and malformed case expressions
+        program.clone().lower_data()?;
+        let mut ctors = HashMap::new();
+        for (i, data) in program.datas.iter().enumerate() {
+            for ctor in &data.ctors {
+                ctors.insert(ctor.name.clone(), i);
+            }
+        }
+        let mut infer = Infer {
+            terms: vec![],
+            level: 0,
+            options,
+            datas: &program.datas,
+            ctors,
+            globals: HashMap::new(),
+            locals: vec![],
+            def_name: String::new(),
+            span: None,
+        };
+        let mut defs = vec![];
+        for def in &program.defs {
+            if def.body.is_some() {
+                defs.push(def);
+                continue;
+            }
+            let t = match &def.ty {
+                Some(ty) => infer.import(ty, &mut HashMap::new()),
+                None if def.match_fail => infer.alloc(Term::Var(GENERIC)),
+                None => {
+                    return Err(Error::MissingPrimopType {
+                        def_name: def.name.clone(),
+                        span: def.span.as_ref().map(|span| span.name),
+                    })
+                }
+            };
+            infer.globals.insert(def.name.clone(), t);
+        }
+        let mut ctor_names = vec![];
+        for data in &program.datas {
+            for (k, ctor) in data.ctors.iter().enumerate() {
+                let t = infer.ctor_type(data, k);
+                infer.globals.insert(ctor.name.clone(), t);
+                ctor_names.push(ctor.name.clone());
+            }
+        }
+        for component in components(&defs) {
+            infer.level = 1;
+            for &i in &component {
+                let var = infer.fresh();
+                infer.globals.insert(defs[i].name.clone(), var);
+            }
+            for &i in &component {
+                let def = defs[i];
+                infer.def_name = def.name.clone();
+                infer.span = def.span.as_ref().map(|span| span.name);
+                let body = def.body.clone().unwrap();
+                let lam = if def.params.is_empty() {
+                    body
+                } else {
+                    Expr::Lam(def.params.clone(), Box::new(body))
+                };
+                let t = infer.infer(&lam)?;
+                infer.unify_here(infer.globals[&def.name], t)?;
+            }
+            infer.level = 0;
+            for &i in &component {
+                infer.generalize(infer.globals[&defs[i].name]);
+            }
+        }
+        let names = program.defs.iter().map(|def| def.name.clone());
+        Ok(names
+            .chain(ctor_names)
+            .map(|name| {
+                let t = infer.globals[&name];
+                (name, infer.export(t, &mut Names::default()))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::prelude;
+
+    fn types(src: &str, options: &TypeCheck) -> Result<HashMap<Ident, String>> {
+        let program: Program = src.parse().unwrap();
+        let types = program.infer_types(options)?;
+        Ok(types
+            .into_iter()
+            .map(|(name, ty)| (name, ty.to_string()))
+            .collect())
+    }
+
+    #[test]
+    fn infers_polymorphic_and_recursive_types() {
+        let src = "
+            #ADD x y : Int -> Int -> Int;
+            data List = Nil | Cons x xs;
+            compose f g x = f (g x);
+            twice = let id = λx. x in id id (id 3);
+            length Nil = 0;
+            length (Cons _ xs) = ADD 1 (length xs);
+            ones = Cons 1 ones;
+        ";
+        let inferred = types(src, &TypeCheck::default()).unwrap();
+        assert_eq!(inferred["compose"], "(a -> b) -> (c -> a) -> c -> b");
+        assert_eq!(inferred["twice"], "Int");
+        assert_eq!(inferred["Cons"], "a -> b -> c -> (a -> b -> c) -> c");
+        assert_eq!(
+            inferred["length"],
+            "(μb. Int -> (a -> b -> Int) -> Int) -> Int"
+        );
+        assert_eq!(inferred["ones"], "μb. a -> (Int -> b -> a) -> a");
+        let options = TypeCheck {
+            recursive_types: false,
+        };
+        assert!(matches!(
+            types(src, &options),
+            Err(Error::InfiniteType { .. })
+        ));
+        let program = prelude().link(vec![]).unwrap();
+        assert!(program.infer_types(&TypeCheck::default()).is_ok());
+    }
+
+    #[test]
+    fn reports_conflicting_types() {
+        let src = "
+            #ADD x y : Int -> Int -> Int;
+            ok = ADD 1 2;
+            bad = ADD 1 (λx. x);
+        ";
+        let Err(error) = types(src, &TypeCheck::default()) else {
+            panic!("expected a type error");
+        };
+        assert_eq!(
+            error.to_string(),
+            "type mismatch in definition `bad`: `Int` conflicts with `a -> a`"
+        );
+        let Err(error) = types("#ADD x y; f = ADD 1 2;", &TypeCheck::default()) else {
+            panic!("expected a missing signature");
+        };
+        assert!(matches!(error, Error::MissingPrimopType { .. }));
+    }
+}